@@ -0,0 +1,359 @@
+//! Minimal reader and writer for POSIX ustar archives, with GNU long name
+//! support. Only regular files, directories, and symlinks are handled.
+
+use std::fs;
+use std::io::{self, Error, ErrorKind, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use walkdir::WalkDir;
+
+#[cfg(unix)]
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+const BLOCK_SIZE: usize = 512;
+const NAME_LEN: usize = 100;
+const LONG_LINK_NAME: &str = "././@LongLink";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink(PathBuf),
+    Other(u8),
+}
+
+#[derive(Debug)]
+pub struct TarEntry {
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    pub data: Vec<u8>,
+}
+
+/// Turn an absolute path into the relative name stored in an archive,
+/// dropping roots, prefixes, and `.`/`..` components.
+pub fn archive_name(path: &Path) -> PathBuf {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part),
+            _ => None,
+        })
+        .collect()
+}
+
+pub struct TarWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> TarWriter<W> {
+    pub fn new(inner: W) -> TarWriter<W> {
+        TarWriter { inner }
+    }
+
+    /// Append `source`, recursing into directories, with its
+    /// contents stored under `name`.
+    pub fn append_path(&mut self, source: &Path, name: &Path) -> Result<(), Error> {
+        for entry in WalkDir::new(source).sort_by(|a, b| a.cmp(b)) {
+            let entry = entry?;
+            let orphan = entry.path().strip_prefix(source).map_err(|_| {
+                Error::other("Parent directory isn't a prefix of child directories?")
+            })?;
+            let entry_name = if orphan.as_os_str().is_empty() {
+                name.to_path_buf()
+            } else {
+                name.join(orphan)
+            };
+            self.append_entry(entry.path(), &entry_name)?;
+        }
+        Ok(())
+    }
+
+    fn append_entry(&mut self, source: &Path, name: &Path) -> Result<(), Error> {
+        let metadata = fs::symlink_metadata(source)?;
+        let file_type = metadata.file_type();
+        let mut name = name.to_string_lossy().replace('\\', "/");
+
+        let (typeflag, size, linkname) = if file_type.is_dir() {
+            name.push('/');
+            (b'5', 0, String::new())
+        } else if file_type.is_symlink() {
+            let link = fs::read_link(source)?;
+            (b'2', 0, link.to_string_lossy().into_owned())
+        } else if file_type.is_file() {
+            (b'0', metadata.len(), String::new())
+        } else {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Cannot archive special file {}", source.display()),
+            ));
+        };
+
+        #[cfg(unix)]
+        let (mode, uid, gid) = (
+            metadata.permissions().mode() & 0o7777,
+            metadata.uid() as u64,
+            metadata.gid() as u64,
+        );
+        #[cfg(not(unix))]
+        let (mode, uid, gid) = (if file_type.is_dir() { 0o755 } else { 0o644 }, 0, 0);
+
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+
+        if name.len() > NAME_LEN {
+            self.write_long_name(b'L', &name)?;
+        }
+        if linkname.len() > NAME_LEN {
+            self.write_long_name(b'K', &linkname)?;
+        }
+        let header = Header {
+            name: &name,
+            mode,
+            uid,
+            gid,
+            size,
+            mtime,
+            typeflag,
+            linkname: &linkname,
+        };
+        self.inner.write_all(&header.encode())?;
+
+        if typeflag == b'0' {
+            let copied = io::copy(&mut fs::File::open(source)?, &mut self.inner)?;
+            if copied != size {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    format!("{} changed size while archiving", source.display()),
+                ));
+            }
+            self.pad(size)?;
+        }
+        Ok(())
+    }
+
+    /// GNU extension: store a name too long for the header as the
+    /// contents of a preceding pseudo-entry
+    fn write_long_name(&mut self, typeflag: u8, name: &str) -> Result<(), Error> {
+        let size = name.len() as u64 + 1;
+        let header = Header {
+            name: LONG_LINK_NAME,
+            mode: 0o644,
+            uid: 0,
+            gid: 0,
+            size,
+            mtime: 0,
+            typeflag,
+            linkname: "",
+        };
+        self.inner.write_all(&header.encode())?;
+        self.inner.write_all(name.as_bytes())?;
+        self.inner.write_all(&[0])?;
+        self.pad(size)
+    }
+
+    fn pad(&mut self, size: u64) -> Result<(), Error> {
+        let remainder = (size % BLOCK_SIZE as u64) as usize;
+        if remainder != 0 {
+            self.inner.write_all(&[0; BLOCK_SIZE][remainder..])?;
+        }
+        Ok(())
+    }
+
+    /// Write the end-of-archive marker and return the inner writer
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.inner.write_all(&[0; 2 * BLOCK_SIZE])?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+struct Header<'a> {
+    name: &'a str,
+    mode: u32,
+    uid: u64,
+    gid: u64,
+    size: u64,
+    mtime: u64,
+    typeflag: u8,
+    linkname: &'a str,
+}
+
+impl Header<'_> {
+    fn encode(&self) -> [u8; BLOCK_SIZE] {
+        let mut block = [0; BLOCK_SIZE];
+        write_str(&mut block[0..100], self.name);
+        write_octal(&mut block[100..108], self.mode as u64);
+        write_octal(&mut block[108..116], self.uid);
+        write_octal(&mut block[116..124], self.gid);
+        write_octal(&mut block[124..136], self.size);
+        write_octal(&mut block[136..148], self.mtime);
+        block[156] = self.typeflag;
+        write_str(&mut block[157..257], self.linkname);
+        block[257..263].copy_from_slice(b"ustar\0");
+        block[263..265].copy_from_slice(b"00");
+
+        // The checksum is computed with its own field set to spaces
+        block[148..156].fill(b' ');
+        let checksum: u64 = block.iter().map(|&b| b as u64).sum();
+        let checksum = format!("{:06o}\0 ", checksum);
+        block[148..156].copy_from_slice(checksum.as_bytes());
+        block
+    }
+}
+
+fn write_str(field: &mut [u8], value: &str) {
+    let bytes = value.as_bytes();
+    let len = bytes.len().min(field.len());
+    field[..len].copy_from_slice(&bytes[..len]);
+}
+
+/// Write a NUL-terminated octal number, falling back to the GNU
+/// base-256 encoding for values that don't fit
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    let octal = format!("{:0width$o}", value, width = digits);
+    if octal.len() <= digits {
+        field[..digits].copy_from_slice(octal.as_bytes());
+        field[digits] = 0;
+    } else {
+        field.fill(0);
+        let bytes = value.to_be_bytes();
+        let start = field.len() - bytes.len();
+        field[start..].copy_from_slice(&bytes);
+        field[0] |= 0x80;
+    }
+}
+
+fn read_str(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+fn read_number(field: &[u8]) -> Result<u64, Error> {
+    let invalid = || Error::new(ErrorKind::InvalidData, "Invalid number in tar header");
+    if field[0] & 0x80 != 0 {
+        // Base-256, where a set sign bit would make the number negative
+        if field[0] & 0x40 != 0 {
+            return Err(invalid());
+        }
+        let mut value: u64 = (field[0] & 0x3f) as u64;
+        for &b in &field[1..] {
+            if value > u64::MAX >> 8 {
+                return Err(invalid());
+            }
+            value = (value << 8) | b as u64;
+        }
+        return Ok(value);
+    }
+    let text = read_str(field);
+    let text = text.trim_matches(|c: char| c == ' ' || c == '\0');
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, 8).map_err(|_| invalid())
+}
+
+/// Read `size` bytes of member data, plus the padding up to the next
+/// block. Only the data is kept, and only with `keep`, so a member can't
+/// make us allocate more than the archive actually holds.
+fn read_data(reader: &mut impl Read, size: u64, keep: bool) -> Result<Vec<u8>, Error> {
+    let truncated = || {
+        Error::new(
+            ErrorKind::InvalidData,
+            "Invalid tar entry: its size runs past the end of the archive",
+        )
+    };
+    let padding = (BLOCK_SIZE as u64 - size % BLOCK_SIZE as u64) % BLOCK_SIZE as u64;
+    let mut data = Vec::new();
+    let read = if keep {
+        reader.take(size).read_to_end(&mut data)? as u64
+    } else {
+        io::copy(&mut reader.take(size), &mut io::sink())?
+    };
+    if read != size || io::copy(&mut reader.take(padding), &mut io::sink())? != padding {
+        return Err(truncated());
+    }
+    Ok(data)
+}
+
+/// Read every entry of an archive into memory
+pub fn read_entries(reader: impl Read) -> Result<Vec<TarEntry>, Error> {
+    read_archive(reader, true)
+}
+
+/// Read the path and kind of every entry of an archive, skipping over
+/// the contents of files
+pub fn read_names(reader: impl Read) -> Result<Vec<TarEntry>, Error> {
+    read_archive(reader, false)
+}
+
+/// GNU long names and link targets can't be longer than this
+const LONG_NAME_MAX: u64 = 1 << 16;
+
+fn read_archive(mut reader: impl Read, keep_data: bool) -> Result<Vec<TarEntry>, Error> {
+    let mut entries = Vec::new();
+    let mut long_name: Option<String> = None;
+    let mut long_link: Option<String> = None;
+    let mut block = [0; BLOCK_SIZE];
+    loop {
+        match reader.read_exact(&mut block) {
+            Ok(()) => {}
+            // Tolerate archives missing their end-of-archive marker
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        if block.iter().all(|&b| b == 0) {
+            break;
+        }
+
+        let size = read_number(&block[124..136])?;
+        let typeflag = block[156];
+        let is_long_name = typeflag == b'L' || typeflag == b'K';
+        if is_long_name && size > LONG_NAME_MAX {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Invalid tar entry: its long name is too long",
+            ));
+        }
+        let data = read_data(&mut reader, size, keep_data || is_long_name)?;
+
+        match typeflag {
+            b'L' => {
+                long_name = Some(read_str(&data));
+                continue;
+            }
+            b'K' => {
+                long_link = Some(read_str(&data));
+                continue;
+            }
+            _ => {}
+        }
+
+        let name = long_name.take().unwrap_or_else(|| {
+            let name = read_str(&block[0..100]);
+            let prefix = read_str(&block[345..500]);
+            if &block[257..262] == b"ustar" && !prefix.is_empty() {
+                format!("{}/{}", prefix, name)
+            } else {
+                name
+            }
+        });
+        let link = long_link
+            .take()
+            .unwrap_or_else(|| read_str(&block[157..257]));
+        let kind = match typeflag {
+            b'0' | 0 => EntryKind::File,
+            b'5' => EntryKind::Directory,
+            b'2' => EntryKind::Symlink(PathBuf::from(link)),
+            other => EntryKind::Other(other),
+        };
+        entries.push(TarEntry {
+            path: PathBuf::from(name.trim_end_matches('/')),
+            kind,
+            data,
+        });
+    }
+    Ok(entries)
+}
@@ -1,9 +1,12 @@
 use anstyle::{AnsiColor, Color::Ansi, Style};
 use clap::builder::styling::Styles;
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 
 use std::io::{Error, ErrorKind};
 use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::util;
 
 const CMD_STYLE: Style = Style::new()
     .bold()
@@ -30,11 +33,12 @@ fn help_template(template: &str) -> String {
             "\
 rip: a safe and ergonomic alternative to rm
 
-{header}Usage{rheader}: {rip_s}rip{rrip_s} [{place}OPTIONS{rplace}] [{place}FILES{rplace}]...
+{header}Usage{rheader}: {rip_s}rip{rrip_s} [{place}OPTIONS{rplace}] [--] [{place}FILES{rplace}]...
        {rip_s}rip{rrip_s} [{place}SUBCOMMAND{rplace}]
 
 {header}Arguments{rheader}:
     [{place}FILES{rplace}]...  Files or directories to remove
+                (use -- before names starting with a dash)
 
 {header}Options{rheader}:
 {OPTIONS_PLACEHOLDER}
@@ -47,7 +51,7 @@ rip: a safe and ergonomic alternative to rm
             "\
 Generate the shell completions file
 
-{header}Usage{rheader}: {rip_s}rip completions{rrip_s} <{place}SHELL{rplace}>
+{header}Usage{rheader}: {rip_s}rip completions{rrip_s} [{place}OPTIONS{rplace}] <{place}SHELL{rplace}>
 
 {header}Arguments{rheader}:
     <{place}SHELL{rplace}>  The shell to generate completions for (bash, elvish, fish, powershell, zsh, nushell)
@@ -80,13 +84,27 @@ Print the graveyard path
     help_template = help_template("rip"),
 )]
 pub struct Args {
-    /// Files and directories to remove
+    /// Files and directories to remove.
+    /// Everything after `--` is treated as a
+    /// target, even if it starts with a dash
     pub targets: Vec<PathBuf>,
 
     /// Directory where deleted files rest
     #[arg(long)]
     pub graveyard: Option<PathBuf>,
 
+    /// Keep a separate graveyard for each
+    /// host, in a subdirectory named after
+    /// the hostname
+    #[arg(long)]
+    pub graveyard_per_host: bool,
+
+    /// With --graveyard-per-host, include
+    /// the graves of every host in seance,
+    /// unbury, and find
+    #[arg(long)]
+    pub all_hosts: bool,
+
     /// Permanently deletes the graveyard
     #[arg(short, long)]
     pub decompose: bool,
@@ -99,18 +117,519 @@ pub struct Args {
     /// Restore the specified
     /// files or the last file
     /// if none are specified
-    #[arg(short, long, num_args = 0)]
+    #[arg(short, long, num_args = 0.., value_name = "GRAVE")]
     pub unbury: Option<Vec<PathBuf>>,
 
+    /// With --unbury, choose the graves to
+    /// restore from seance by editing a list
+    /// of them in $EDITOR
+    #[arg(long)]
+    pub edit: bool,
+
+    /// With --unbury, restore the grave with
+    /// this id, as shown by seance
+    #[arg(long, value_name = "ID")]
+    pub id: Vec<String>,
+
+    /// With --unbury, treat each path as an
+    /// original directory and restore every
+    /// grave that was buried from under it
+    #[arg(long)]
+    pub recursive: bool,
+
+    /// With --unbury, restore every grave
+    /// matching a bare file name instead of
+    /// refusing when there are several
+    #[arg(long)]
+    pub all_matches: bool,
+
+    /// Also bury the files listed as
+    /// members of this tar archive,
+    /// relative to the current directory
+    #[arg(long, value_name = "ARCHIVE")]
+    pub from_archive: Option<PathBuf>,
+
+    /// Unbury into this tar archive
+    /// instead of the original locations
+    #[arg(long, value_name = "ARCHIVE")]
+    pub archive: Option<PathBuf>,
+
+    /// When unburying onto an existing
+    /// file, move it aside to a .bak
+    /// backup instead of renaming the
+    /// restored file
+    #[arg(long)]
+    pub backup: bool,
+
+    /// When unburying onto an existing
+    /// file, drop the grave if the file has
+    /// the same contents, or else replace it
+    #[arg(long, alias = "replace-existing")]
+    pub replace_if_different: bool,
+
+    /// Restore the graves into DIR instead
+    /// of their original locations
+    #[arg(long, value_name = "DIR")]
+    pub to: Option<PathBuf>,
+
+    /// With --to, keep the original path
+    /// below PREFIX when restoring
+    #[arg(long, value_name = "PREFIX")]
+    pub strip_prefix: Option<PathBuf>,
+
     /// Print some info about TARGET before
     /// burying
     #[arg(short, long)]
     pub inspect: bool,
 
+    /// With --inspect, preview the last
+    /// lines of files instead of the first
+    #[arg(long)]
+    pub inspect_tail: bool,
+
+    /// Print extra notes about what is
+    /// being buried, and the progress of
+    /// decompose
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// Accept the default answer of prompts
+    /// that can't lose data
+    #[arg(short, long)]
+    pub yes: bool,
+
+    /// Answer yes to every prompt, including
+    /// those that permanently delete files
+    #[arg(long)]
+    pub force: bool,
+
+    /// Bury each target in a .graveyard
+    /// directory beside it; seance and
+    /// unbury use the nearest .graveyard
+    /// above the current directory
+    #[arg(long)]
+    pub local: bool,
+
+    /// Permanently delete empty files and
+    /// directories instead of burying them
+    #[arg(long)]
+    pub empty_permanently: bool,
+
+    /// Bury under a subdirectory of the
+    /// graveyard named after today's date
+    /// (YYYY-MM-DD)
+    #[arg(long)]
+    pub date_partition: bool,
+
+    /// Bury directly under the graveyard as
+    /// the target's file name instead of
+    /// mirroring its full path; the record
+    /// keeps the original location
+    #[arg(long)]
+    pub flat: bool,
+
+    /// Where graves go in the graveyard;
+    /// --flat and --date-partition are
+    /// shorthands for two of these
+    /// [default: mirror]
+    #[arg(long, value_enum, value_name = "LAYOUT")]
+    pub grave_layout: Option<GraveLayout>,
+
+    /// Record the target as having been at
+    /// PATH, so unbury restores it there
+    /// (with a single target only)
+    #[arg(long, value_name = "PATH")]
+    pub name: Option<PathBuf>,
+
+    /// Use a named room of the graveyard
+    /// for burying, seance, and decompose
+    #[arg(long)]
+    pub room: Option<String>,
+
+    /// Bury files whose name matches the
+    /// glob PATTERN in ROOM, unless --room
+    /// is given; the first match wins
+    /// [env: RIP_ROUTES, separated by ;]
+    #[arg(long, value_name = "PATTERN=ROOM")]
+    pub route: Vec<String>,
+
+    /// Refuse to bury PATH, anything inside
+    /// it, or any directory containing it
+    /// [env: RIP_PROTECT, separated by :]
+    #[arg(long, value_name = "PATH")]
+    pub protect: Vec<PathBuf>,
+
+    /// Limit copies into and out of the
+    /// graveyard to this many bytes per second
+    #[arg(
+        long,
+        value_name = "BYTES_PER_SEC",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub throttle: Option<u64>,
+
+    /// Write the number of files buried
+    /// or unburied to this file
+    #[arg(long, value_name = "PATH")]
+    pub count_file: Option<PathBuf>,
+
+    /// After burying, warn if the graveyard's
+    /// filesystem is at least PERCENT full
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        num_args = 0..=1,
+        default_missing_value = "90",
+        value_parser = clap::value_parser!(u8).range(0..=100)
+    )]
+    pub warn_disk_usage: Option<u8>,
+
+    /// After burying or unburying, write a
+    /// JSON summary of the run to PATH, or
+    /// to stdout if no PATH is given
+    #[arg(long, value_name = "PATH")]
+    pub summary_json: Option<Option<PathBuf>>,
+
+    /// Don't carry POSIX ACLs over to graves
+    /// or restored files (Linux only)
+    #[arg(long)]
+    pub no_inherit_acl: bool,
+
+    /// Lower the I/O priority of rip
+    /// (Linux only)
+    #[arg(long)]
+    pub ionice: bool,
+
+    /// Ask for confirmation before burying
+    /// a directory with more than COUNT
+    /// entries
+    #[arg(long, value_name = "COUNT")]
+    pub confirm_large_dir: Option<u64>,
+
+    /// What to do when a target is the
+    /// same file (e.g. a hardlink) as one
+    /// already in the graveyard
+    #[arg(long, value_enum, default_value_t)]
+    pub duplicates: DuplicatePolicy,
+
+    /// Bury a copy of each file, then cut
+    /// the original down to its last SIZE
+    /// bytes (e.g. 512K, 10M)
+    #[arg(long, value_name = "SIZE", value_parser = util::parse_size)]
+    pub truncate_after: Option<u64>,
+
+    /// Make graves readable only by their
+    /// owner (files 0600, directories 0700),
+    /// restoring the original mode on unbury
+    #[arg(long)]
+    pub private_graveyard: bool,
+
+    /// What to do when a grave already
+    /// exists at the target's place in the
+    /// graveyard
+    #[arg(long, value_enum, default_value_t)]
+    pub grave_collision: GraveCollision,
+
+    /// How to name a grave that would
+    /// collide with an existing one
+    #[arg(long, value_enum, default_value_t)]
+    pub collision_suffix: CollisionSuffix,
+
+    /// Permanently delete the graves created
+    /// by this invocation once --expire is
+    /// run after DURATION (e.g. 30d, 12h)
+    #[arg(long, value_name = "DURATION", value_parser = util::parse_duration)]
+    pub expire_after: Option<Duration>,
+
+    /// Keep burying the files in DIR as they
+    /// appear, until interrupted
+    #[arg(long, value_name = "DIR")]
+    pub watch: Option<PathBuf>,
+
+    /// With --watch, only bury files whose
+    /// name matches this glob
+    #[arg(long = "match", value_name = "GLOB")]
+    pub watch_match: Option<String>,
+
+    /// With --watch, only bury files last
+    /// modified at least DURATION ago
+    #[arg(long, value_name = "DURATION", value_parser = util::parse_duration)]
+    pub older_than: Option<Duration>,
+
+    /// Permanently delete every grave whose
+    /// expiry has passed
+    #[arg(long)]
+    pub expire: bool,
+
+    /// Move the files of a FreeDesktop trash
+    /// (e.g. from trash-cli) into the
+    /// graveyard, keeping their original
+    /// paths and deletion times [default:
+    /// $XDG_DATA_HOME/Trash]
+    #[arg(long, value_name = "DIR")]
+    pub import_trash: Option<Option<PathBuf>>,
+
+    /// Replace identical grave files with
+    /// hardlinks to a single copy
+    #[arg(long, visible_alias = "dedup-graveyard")]
+    pub dedup: bool,
+
+    /// Bury the targets as pending, to be
+    /// finalized with --commit or restored
+    /// with --rollback
+    #[arg(long)]
+    pub stage: bool,
+
+    /// Once the record holds more than this
+    /// many entries, move those whose graves
+    /// are gone to <record>.old [default: 10000]
+    #[arg(long, value_name = "LINES")]
+    pub record_max_lines: Option<usize>,
+
+    /// Include entries rotated out of the
+    /// record during seance
+    #[arg(long)]
+    pub all_history: bool,
+
+    /// Create the graveyard and an empty
+    /// record, print its path, and exit
+    #[arg(long)]
+    pub graveyard_init: bool,
+
+    /// Remove the record entries of these
+    /// original or grave paths without
+    /// restoring them
+    #[arg(long, value_name = "PATH", num_args = 1.., alias = "clear-record")]
+    pub forget: Option<Vec<PathBuf>>,
+
+    /// With --forget, also permanently
+    /// delete the graves
+    #[arg(long)]
+    pub purge: bool,
+
+    /// Finalize all pending graves
+    #[arg(long)]
+    pub commit: bool,
+
+    /// Restore all pending graves
+    #[arg(long)]
+    pub rollback: bool,
+
+    /// List the graves buried within the
+    /// grace period
+    #[arg(long)]
+    pub recent: bool,
+
+    /// Restore every grave buried within
+    /// the grace period
+    #[arg(long)]
+    pub undo_recent: bool,
+
+    /// How long a bury counts as recent for
+    /// --recent and --undo-recent (e.g. 5m,
+    /// 1h) [default: 5m]
+    #[arg(long, value_name = "DURATION", value_parser = util::parse_duration)]
+    pub grace: Option<Duration>,
+
+    /// Attach a note to the graves
+    /// created by this invocation
+    #[arg(long)]
+    pub note: Option<String>,
+
+    /// Show extra details about each
+    /// grave during seance
+    #[arg(short, long)]
+    pub long: bool,
+
+    /// Show both the original path and the
+    /// grave location during seance
+    #[arg(long)]
+    pub show_dest: bool,
+
+    /// Show seance and tree output through
+    /// $RIP_PAGER or $PAGER (default: less)
+    /// when stdout is a terminal
+    #[arg(long)]
+    pub pager: bool,
+
+    /// Show original paths during seance
+    /// relative to DIR, or to the current
+    /// directory if no DIR is given; with
+    /// --unbury, resolve relative paths
+    /// against DIR
+    #[arg(
+        long,
+        value_name = "DIR",
+        num_args = 0..=1,
+        default_missing_value = ".",
+        visible_alias = "relative"
+    )]
+    pub relative_to: Option<PathBuf>,
+
+    /// Name of the record file inside the
+    /// graveyard [env: RIP_RECORD]
+    /// [default: .record]
+    #[arg(long, value_name = "NAME")]
+    pub record_name: Option<String>,
+
+    /// List graves whose original path
+    /// contains PATTERN
+    #[arg(long, value_name = "PATTERN")]
+    pub find: Option<String>,
+
+    /// Treat the --find pattern as a
+    /// regular expression
+    #[arg(long)]
+    pub regex: bool,
+
+    /// Make --find search graves deleted
+    /// from anywhere, not just under the
+    /// current directory
+    #[arg(long)]
+    pub all: bool,
+
+    /// Order seance output
+    #[arg(long, value_enum)]
+    pub sort: Option<SortOrder>,
+
+    /// Print at most N graves during seance
+    #[arg(long, value_name = "N")]
+    pub limit: Option<usize>,
+
+    /// Only show graves buried at least
+    /// DURATION ago (e.g. 7d) during seance;
+    /// graves without a readable deletion
+    /// time are always shown
+    #[arg(long, value_name = "DURATION", value_parser = util::parse_duration)]
+    pub min_age: Option<Duration>,
+
+    /// Print the graveyard (or the --room)
+    /// as a tree annotated with sizes
+    #[arg(long)]
+    pub tree: bool,
+
+    /// Show at most DEPTH levels of the
+    /// graveyard with --tree
+    #[arg(long, value_name = "DEPTH")]
+    pub inspect_depth: Option<usize>,
+
+    /// Exit with CODE instead of 0 when
+    /// seance finds no graves
+    #[arg(long, value_name = "CODE")]
+    pub exit_code_on_empty: Option<u8>,
+
+    /// Move the entire graveyard to a new
+    /// location, updating the record
+    #[arg(long, value_name = "NEWPATH")]
+    pub relocate: Option<PathBuf>,
+
+    /// Move every grave of another graveyard
+    /// into this one, keeping their record
+    /// entries
+    #[arg(long, value_name = "OTHER")]
+    pub merge_graveyard: Option<PathBuf>,
+
+    /// Move the targets into DIR instead of
+    /// the graveyard, renaming on conflicts
+    #[arg(long, value_name = "DIR")]
+    pub move_to: Option<PathBuf>,
+
+    /// Write the targets to stdout as a tar
+    /// archive, then remove them, without
+    /// keeping anything in the graveyard
+    #[arg(long)]
+    pub to_stdout: bool,
+
+    /// Check the graveyard and record
+    /// for problems
+    #[arg(long)]
+    pub doctor: bool,
+
+    /// Count the valid, invalid, and dead
+    /// entries of the record, failing if
+    /// any are invalid
+    #[arg(long, alias = "touch-record")]
+    pub check_record: bool,
+
+    /// Report graves that others can read
+    /// or modify, and symlinks leading out
+    /// of the graveyard
+    #[arg(long)]
+    pub audit: bool,
+
+    /// How to print errors
+    #[arg(long, value_enum, default_value_t)]
+    pub error_format: ErrorFormat,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// Plain messages
+    #[default]
+    Human,
+    /// A JSON object on stderr
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Print a warning and bury it anyway
+    #[default]
+    Warn,
+    /// Leave it in place
+    Skip,
+    /// Bury it without checking
+    Allow,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GraveCollision {
+    /// Bury it next to the old grave under a new name
+    #[default]
+    Rename,
+    /// Permanently delete the old grave first
+    Overwrite,
+    /// Leave the target in place
+    Skip,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CollisionSuffix {
+    /// A counter, like `foo~1`
+    #[default]
+    Numeric,
+    /// The time of burial, like `foo~20240102T030405`
+    Timestamp,
+    /// A random UUID, like `foo~1b4e28ba-2fa1-4d2e-8c3f-6f0e5a9e2c71`
+    Uuid,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GraveLayout {
+    /// Mirror the full original path
+    #[default]
+    Mirror,
+    /// The file name, directly under the graveyard
+    Flat,
+    /// The full original path, under today's date
+    Date,
+    /// The file name, under a hash of its directory, keeping deep paths short
+    Hash,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Oldest deletions first
+    Oldest,
+    /// Most recent deletions first
+    Newest,
+    /// By original path
+    Path,
+}
+
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Generate shell completions file
@@ -119,6 +638,12 @@ pub enum Commands {
         /// The shell to generate completions for
         #[arg(value_name = "SHELL")]
         shell: String,
+
+        /// Write the completions to PATH
+        /// instead of stdout, creating its
+        /// parent directories
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
     },
 
     /// Print the graveyard path
@@ -132,6 +657,7 @@ pub enum Commands {
 }
 
 struct IsDefault {
+    targets: bool,
     graveyard: bool,
     decompose: bool,
     seance: bool,
@@ -144,6 +670,7 @@ impl IsDefault {
     fn new(cli: &Args) -> IsDefault {
         let defaults = Args::default();
         IsDefault {
+            targets: cli.targets == defaults.targets,
             graveyard: cli.graveyard == defaults.graveyard,
             decompose: cli.decompose == defaults.decompose,
             seance: cli.seance == defaults.seance,
@@ -158,9 +685,11 @@ impl IsDefault {
 pub fn validate_args(cli: &Args) -> Result<(), Error> {
     let defaults = IsDefault::new(cli);
 
-    // [completions] can only be used by itself
+    // [completions] can only be used by itself. Everything else may be
+    // pointed at a specific --graveyard.
     if !defaults.completions
-        && !(defaults.graveyard
+        && !(defaults.targets
+            && defaults.graveyard
             && defaults.decompose
             && defaults.seance
             && defaults.unbury
@@ -171,6 +700,173 @@ pub fn validate_args(cli: &Args) -> Result<(), Error> {
             "--completions can only be used by itself",
         ));
     }
+    if [cli.flat, cli.date_partition, cli.grave_layout.is_some()]
+        .iter()
+        .filter(|given| **given)
+        .count()
+        > 1
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Only one of --grave-layout, --flat, and --date-partition can be used",
+        ));
+    }
+    if cli.local && !defaults.graveyard {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--local and --graveyard cannot be used together",
+        ));
+    }
+    if cli.to_stdout && cli.targets.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--to-stdout needs at least one target",
+        ));
+    }
+    if cli.archive.is_some() && defaults.unbury {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--archive can only be used with --unbury",
+        ));
+    }
+    if cli.edit && defaults.unbury {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--edit can only be used with --unbury",
+        ));
+    }
+    if !cli.id.is_empty() && defaults.unbury {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--id can only be used with --unbury",
+        ));
+    }
+    if cli.recursive && defaults.unbury {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--recursive can only be used with --unbury",
+        ));
+    }
+    if cli.all_matches && defaults.unbury {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--all-matches can only be used with --unbury",
+        ));
+    }
+    if cli.to.is_some() && defaults.unbury {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--to can only be used with --unbury",
+        ));
+    }
+    if cli.strip_prefix.is_some() && cli.to.is_none() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--strip-prefix can only be used with --to",
+        ));
+    }
+    if (cli.regex || cli.all) && cli.find.is_none() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--regex and --all can only be used with --find",
+        ));
+    }
+    if cli.all_history && defaults.seance {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--all-history can only be used with --seance",
+        ));
+    }
+    if cli.relative_to.is_some() && defaults.seance && defaults.unbury {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--relative-to can only be used with --seance or --unbury",
+        ));
+    }
+    if cli.min_age.is_some() && defaults.seance {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--min-age can only be used with --seance",
+        ));
+    }
+    if cli.exit_code_on_empty.is_some() && defaults.seance {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--exit-code-on-empty can only be used with --seance",
+        ));
+    }
+    if cli.all_hosts && !cli.graveyard_per_host {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--all-hosts can only be used with --graveyard-per-host",
+        ));
+    }
+    if (cli.watch_match.is_some() || cli.older_than.is_some()) && cli.watch.is_none() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--match and --older-than can only be used with --watch",
+        ));
+    }
+    if cli.name.is_some() && cli.targets.len() != 1 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--name can only be used with exactly one target",
+        ));
+    }
+    if cli.replace_if_different && cli.unbury.is_none() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--replace-if-different can only be used with --unbury",
+        ));
+    }
+    if cli.replace_if_different && cli.backup {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--replace-if-different and --backup cannot be used together",
+        ));
+    }
+    if cli.inspect_tail && !cli.inspect {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--inspect-tail can only be used with --inspect",
+        ));
+    }
+    if cli.inspect_depth.is_some() && !cli.tree {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--inspect-depth can only be used with --tree",
+        ));
+    }
+    if cli.purge && cli.forget.is_none() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--purge can only be used with --forget",
+        ));
+    }
+    if cli.commit && cli.rollback {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--commit and --rollback cannot be used together",
+        ));
+    }
+    if cli.recent && cli.undo_recent {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--recent and --undo-recent cannot be used together",
+        ));
+    }
+    if cli.grace.is_some() && !(cli.recent || cli.undo_recent) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--grace can only be used with --recent or --undo-recent",
+        ));
+    }
+    if cli.stage && (cli.commit || cli.rollback) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--stage cannot be used with --commit or --rollback",
+        ));
+    }
     if !defaults.decompose && !(defaults.seance && defaults.unbury && defaults.inspect) {
         return Err(Error::new(
             ErrorKind::InvalidInput,
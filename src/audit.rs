@@ -0,0 +1,117 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{Error, Write};
+use std::path::{Component, Path, PathBuf};
+
+use walkdir::WalkDir;
+
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+
+/// Print every grave that others could read or modify, and every symlink
+/// that leads out of the graveyard. Returns an error if anything was found.
+///
+/// Only paths that others can reach count: behind a graveyard root closed
+/// to group and others (as rip creates it, 0700) no mode is an issue. The
+/// record and the directories rip creates to mirror original paths are
+/// not graves, so only a world-writable one of those is reported.
+pub fn run_audit(
+    graveyard: &Path,
+    record_name: &str,
+    stream: &mut impl Write,
+) -> Result<(), Error> {
+    if !graveyard.exists() {
+        writeln!(stream, "No graveyard at {}", graveyard.display())?;
+        return Ok(());
+    }
+    // Reading the record this way doesn't create it
+    let record = fs::read_to_string(graveyard.join(record_name)).unwrap_or_default();
+    let mirror_dirs: HashSet<&Path> = record
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split('\t').nth(2))
+        .flat_map(|dest| Path::new(dest).ancestors().skip(1))
+        .filter(|dir| dir.starts_with(graveyard))
+        .collect();
+    let mut findings = 0;
+    for entry in WalkDir::new(graveyard).sort_by(|a, b| a.cmp(b)) {
+        let entry = entry?;
+        let path = entry.path();
+        let metadata = entry.metadata()?;
+        let is_record =
+            entry.depth() == 1 && entry.file_name().to_string_lossy().starts_with(record_name);
+        if is_record {
+            continue;
+        }
+
+        if metadata.is_symlink() {
+            let target = fs::read_link(path)?;
+            let resolved = lexical_normalize(&path.parent().unwrap_or(graveyard).join(&target));
+            if !resolved.starts_with(graveyard) {
+                writeln!(
+                    stream,
+                    "{}: symlink points outside the graveyard ({})",
+                    path.display(),
+                    target.display()
+                )?;
+                findings += 1;
+            }
+            // The permissions of a symlink itself mean nothing
+            continue;
+        }
+
+        #[cfg(unix)]
+        if reachable_by_others(graveyard, path) {
+            let mode = metadata.mode() & 0o7777;
+            let is_mirror_dir = entry.depth() == 0 || mirror_dirs.contains(path);
+            if mode & 0o002 != 0 {
+                writeln!(
+                    stream,
+                    "{}: world-writable (mode {:o})",
+                    path.display(),
+                    mode
+                )?;
+                findings += 1;
+            } else if mode & 0o077 != 0 && !is_mirror_dir {
+                writeln!(
+                    stream,
+                    "{}: accessible by group or others (mode {:o})",
+                    path.display(),
+                    mode
+                )?;
+                findings += 1;
+            }
+        }
+    }
+
+    if findings > 0 {
+        return Err(Error::other(format!("rip audit found {} issues", findings)));
+    }
+    writeln!(stream, "No issues found in {}", graveyard.display())?;
+    Ok(())
+}
+
+/// Whether group or others can get to `path`, through every directory
+/// from the graveyard root down to it
+#[cfg(unix)]
+fn reachable_by_others(graveyard: &Path, path: &Path) -> bool {
+    path.ancestors()
+        .skip(1)
+        .take_while(|dir| dir.starts_with(graveyard))
+        .all(|dir| fs::metadata(dir).is_ok_and(|m| m.mode() & 0o011 != 0))
+}
+
+/// Resolve `.` and `..` in a path without touching the filesystem
+fn lexical_normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
@@ -1,7 +1,9 @@
 use clap::CommandFactory;
 use clap_complete::{generate, Shell};
 use clap_complete_nushell::Nushell;
+use std::fs;
 use std::io::{Error, ErrorKind, Result, Write};
+use std::path::Path;
 use std::str::FromStr;
 
 use crate::args;
@@ -25,3 +27,27 @@ pub fn generate_shell_completions(shell_s: &str, buf: &mut dyn Write) -> Result<
     }
     Ok(())
 }
+
+/// Generate completions into `output` if given, creating its parent
+/// directories, and into `buf` otherwise
+pub fn write_shell_completions(
+    shell_s: &str,
+    output: Option<&Path>,
+    buf: &mut dyn Write,
+) -> Result<()> {
+    let Some(output) = output else {
+        return generate_shell_completions(shell_s, buf);
+    };
+    let mut contents = Vec::new();
+    generate_shell_completions(shell_s, &mut contents)?;
+    let parent = output.parent().filter(|p| !p.as_os_str().is_empty());
+    parent
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(output, contents))
+        .map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!("Failed to write completions to {}: {}", output.display(), e),
+            )
+        })
+}
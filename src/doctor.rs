@@ -0,0 +1,175 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Write};
+use std::path::Path;
+
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+
+use crate::record::RecordItem;
+use crate::util;
+
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+    Info,
+}
+
+impl Status {
+    fn label(&self) -> &'static str {
+        match self {
+            Status::Ok => "OK  ",
+            Status::Warn => "WARN",
+            Status::Fail => "FAIL",
+            Status::Info => "INFO",
+        }
+    }
+}
+
+/// Summary of the lines in a record
+#[derive(Debug, Default)]
+pub struct RecordHealth {
+    pub valid: usize,
+    pub malformed: usize,
+    pub missing: usize,
+}
+
+/// Parse every line of the record at `path` without modifying it
+pub fn check_record(path: &Path) -> Result<RecordHealth, Error> {
+    let contents = fs::read_to_string(path)?;
+    let mut health = RecordHealth::default();
+    for line in contents.lines().skip(1).filter(|l| !l.is_empty()) {
+        match RecordItem::parse(line) {
+            Some(item) if chrono::DateTime::parse_from_rfc3339(&item.time).is_ok() => {
+                health.valid += 1;
+                if !util::symlink_exists(&item.dest) {
+                    health.missing += 1;
+                }
+            }
+            _ => health.malformed += 1,
+        }
+    }
+    Ok(health)
+}
+
+/// Print a checklist describing the state of the graveyard.
+/// Returns an error if anything is broken.
+pub fn run_doctor(
+    graveyard: &Path,
+    record_name: &str,
+    stream: &mut impl Write,
+) -> Result<(), Error> {
+    let mut broken = false;
+    let mut report = |status: Status, message: String| -> Result<(), Error> {
+        broken |= matches!(status, Status::Fail);
+        writeln!(stream, "{}  {}", status.label(), message)
+    };
+
+    report(Status::Info, format!("graveyard: {}", graveyard.display()))?;
+    if !graveyard.exists() {
+        report(
+            Status::Warn,
+            "graveyard does not exist yet; it will be created on the first bury".to_string(),
+        )?;
+    } else if !graveyard.is_dir() {
+        report(Status::Fail, "graveyard is not a directory".to_string())?;
+    } else {
+        match util::check_writable(graveyard) {
+            Ok(()) => report(Status::Ok, "graveyard is writable".to_string())?,
+            Err(e) => report(Status::Fail, e.to_string())?,
+        }
+
+        #[cfg(unix)]
+        {
+            let mode = fs::metadata(graveyard)?.permissions().mode() & 0o777;
+            if mode == 0o700 {
+                report(Status::Ok, "graveyard has mode 0700".to_string())?;
+            } else {
+                report(
+                    Status::Warn,
+                    format!("graveyard has mode {:o}, expected 700", mode),
+                )?;
+            }
+        }
+
+        let record = graveyard.join(record_name);
+        if record.exists() {
+            let health = check_record(&record)?;
+            if health.malformed == 0 {
+                report(
+                    Status::Ok,
+                    format!("record parses cleanly ({} entries)", health.valid),
+                )?;
+            } else {
+                report(
+                    Status::Fail,
+                    format!("record has {} malformed lines", health.malformed),
+                )?;
+            }
+            if health.missing == 0 {
+                report(Status::Ok, "every grave in the record exists".to_string())?;
+            } else {
+                report(
+                    Status::Warn,
+                    format!("{} graves in the record are missing", health.missing),
+                )?;
+            }
+        } else {
+            report(Status::Ok, "no record yet".to_string())?;
+        }
+
+        if let Some(size) = util::grave_size(graveyard) {
+            report(
+                Status::Info,
+                format!("total size: {}", util::humanize_bytes(size)),
+            )?;
+        }
+
+        #[cfg(target_os = "linux")]
+        report(
+            Status::Info,
+            match util::xattrs_supported(graveyard) {
+                Ok(true) => "extended attributes: supported".to_string(),
+                Ok(false) => "extended attributes: unsupported".to_string(),
+                Err(e) => format!("extended attributes: couldn't check ({})", e),
+            },
+        )?;
+    }
+
+    report(
+        Status::Info,
+        "reflink copies: not used, files are renamed or copied".to_string(),
+    )?;
+
+    if broken {
+        return Err(Error::other("rip doctor found problems"));
+    }
+    Ok(())
+}
+
+/// Print how many entries of the record are valid, malformed, and dead
+/// (their grave is gone), without modifying anything. Returns an error
+/// if any are malformed.
+pub fn run_check_record(record: &Path, stream: &mut impl Write) -> Result<(), Error> {
+    if !record.is_file() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("No record at {}", record.display()),
+        ));
+    }
+    let health = check_record(record)?;
+    writeln!(stream, "valid: {}", health.valid)?;
+    writeln!(stream, "invalid: {}", health.malformed)?;
+    writeln!(stream, "dead: {}", health.missing)?;
+    if health.malformed > 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{} has {} invalid lines",
+                record.display(),
+                health.malformed
+            ),
+        ));
+    }
+    Ok(())
+}
@@ -1,228 +1,2353 @@
 use clap::CommandFactory;
-use fs_extra::dir::get_size;
+use std::collections::{HashMap, HashSet};
 use std::fs::Metadata;
-use std::io::{BufRead, BufReader, Error, ErrorKind, Write};
-use std::path::{Path, PathBuf};
+use std::io::{BufRead, BufReader, Error, ErrorKind, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 use std::{env, fs};
 use walkdir::WalkDir;
 
 // Platform-specific imports
 #[cfg(unix)]
-use std::os::unix::fs::{symlink, FileTypeExt, PermissionsExt};
+use std::os::unix::fs::{symlink, FileTypeExt, MetadataExt, PermissionsExt};
 
 #[cfg(target_os = "windows")]
 use std::os::windows::fs::symlink_file as symlink;
 
+pub mod archive;
 pub mod args;
+pub mod audit;
 pub mod completions;
+pub mod doctor;
 pub mod record;
 pub mod util;
 
-use args::Args;
-use record::{Record, RecordItem};
+use args::{Args, CollisionSuffix, DuplicatePolicy, GraveCollision, GraveLayout, SortOrder};
+use record::{Extras, Record, RecordItem};
 
 const LINES_TO_INSPECT: usize = 6;
 const FILES_TO_INSPECT: usize = 6;
 pub const BIG_FILE_THRESHOLD: u64 = 500000000; // 500 MB
+const COPY_CHUNK_SIZE: usize = 1 << 16;
+const DECOMPOSE_PROGRESS_INTERVAL: usize = 1000;
+const RECORD_MAX_LINES: usize = 10000;
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+const DEFAULT_GRACE: Duration = Duration::from_secs(5 * 60);
+/// Name of the graveyard kept beside the targets with `--local`
+pub const LOCAL_GRAVEYARD: &str = ".graveyard";
+
+/// What became of a target handed to `move_target` or `copy_file`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveOutcome {
+    /// The target now lives at the destination
+    Moved,
+    /// The user chose to permanently delete the target instead of copying
+    /// it. `copy_file` leaves the removal to its caller.
+    Deleted,
+    /// The user cancelled, so the target was left where it was
+    Kept,
+}
+
+/// Settings that affect how files are copied in and out of the graveyard
+#[derive(Debug, Default, Clone)]
+pub struct CopyOptions {
+    /// Maximum number of bytes copied per second
+    pub throttle: Option<u64>,
+    /// Which prompts are answered without asking
+    pub prompts: util::PromptPolicy,
+    /// Leave POSIX ACLs behind instead of carrying them over
+    pub drop_acl: bool,
+}
+
+impl From<&Args> for CopyOptions {
+    fn from(cli: &Args) -> CopyOptions {
+        CopyOptions {
+            throttle: cli.throttle,
+            prompts: util::PromptPolicy {
+                yes: cli.yes,
+                force: cli.force,
+            },
+            drop_acl: cli.no_inherit_acl,
+        }
+    }
+}
 
 pub fn run(cli: Args, mode: impl util::TestingMode, stream: &mut impl Write) -> Result<(), Error> {
+    let start = Instant::now();
     args::validate_args(&cli)?;
-    let graveyard: &PathBuf = &get_graveyard(cli.graveyard);
+    // With --local, a bury starts with the graveyard beside the first
+    // target, so no other .graveyard gets created
+    let graveyard: &PathBuf = &match (cli.local, cli.targets.first()) {
+        (true, Some(target)) => resolve_through_parent(&env::current_dir()?, target)
+            .parent()
+            .map_or_else(
+                || PathBuf::from(LOCAL_GRAVEYARD),
+                |dir| dir.join(LOCAL_GRAVEYARD),
+            ),
+        (true, None) => local_graveyard(&env::current_dir()?),
+        (false, _) => resolve_graveyard(&cli),
+    };
+    let record_name = &get_record_name(cli.record_name.clone())?;
+    let opts = &CopyOptions::from(&cli);
+
+    if cli.ionice {
+        util::set_idle_io_priority()?;
+    }
+
+    if cli.doctor {
+        // Diagnose before anything gets created
+        return doctor::run_doctor(graveyard, record_name, stream);
+    }
+    if cli.check_record {
+        return doctor::run_check_record(&graveyard.join(record_name), stream);
+    }
+    if graveyard.exists() && !graveyard.is_dir() {
+        return Err(util::path_error(
+            ErrorKind::NotADirectory,
+            graveyard,
+            format!(
+                "Graveyard path is a file: {}; choose another location with --graveyard",
+                graveyard.display()
+            ),
+        ));
+    }
+    if cli.audit {
+        return audit::run_audit(graveyard, record_name, stream);
+    }
+    if let Some(dir) = &cli.move_to {
+        return move_into(&cli.targets, dir, cli.collision_suffix, opts, &mode, stream);
+    }
+    if cli.to_stdout {
+        return bury_to_stream(&cli.targets, &mode, stream);
+    }
+    if let Some(new_graveyard) = &cli.relocate {
+        return relocate_graveyard(graveyard, new_graveyard, record_name, opts, &mode, stream);
+    }
+
+    create_graveyard(graveyard)?;
+    // Listing graves works from a read-only or shared graveyard too
+    if !reads_only(&cli) {
+        util::check_writable(graveyard)?;
+    }
+
+    // Note whether anything was buried here before the record is created
+    let record_existed = graveyard.join(record_name).is_file();
+    // Stores the deleted files
+    let record = Record::new(graveyard, record_name);
+    if !reads_only(&cli) {
+        record.create()?;
+    }
+    let cwd = &env::current_dir()?;
+
+    if cli.graveyard_init {
+        writeln!(stream, "{}", graveyard.display())?;
+        return Ok(());
+    }
+
+    // If the user wishes to restore everything
+    if cli.decompose {
+        if cli.room.is_some() {
+            decompose_room(&grave_root(graveyard, &cli)?, &record, &cli, &mode, stream)?;
+        } else if opts.prompts.ask(
+            "Really unlink the entire graveyard?",
+            false,
+            true,
+            &mode,
+            stream,
+        )? {
+            let freed = remove_tree(graveyard, cli.verbose, stream)?;
+            writeln!(
+                stream,
+                "Freed {} from {}",
+                util::humanize_bytes(freed),
+                graveyard.display()
+            )?;
+        }
+    } else if let Some(mut graves_to_exhume) = cli.unbury.clone() {
+        // Vector to hold the grave path of items we want to unbury.
+        // This will be used to determine which items to remove from the
+        // record following the unbury.
+        // Initialize it with the targets passed to -r
+
+        // If -s is also passed, push all files found by seance onto
+        // the graves_to_exhume.
+        let records = read_records(graveyard, record_name, &cli);
+        let base = match &cli.relative_to {
+            Some(dir) => dunce::canonicalize(cwd.join(dir))?,
+            None => cwd.clone(),
+        };
+        // Paths given apart from the flag, as in `rip GRAVE -u`, name
+        // graves too
+        graves_to_exhume.extend(cli.targets.iter().cloned());
+        graves_to_exhume = resolve_grave_refs(&graves_to_exhume, &records, &base, &cli, stream)?;
+        for id in &cli.id {
+            let grave = records
+                .iter()
+                .find_map(|(_, record)| record.find_id(id).ok().flatten())
+                .ok_or_else(|| {
+                    Error::new(ErrorKind::NotFound, format!("No grave with id {}", id))
+                })?;
+            graves_to_exhume.push(grave.dest);
+        }
+        let edited = if cli.edit {
+            edit_graves(&seance_graves(&records, &cli, cwd)?, &mode, stream)?
+        } else {
+            None
+        };
+        match &edited {
+            Some(kept) => graves_to_exhume.extend(kept.iter().cloned()),
+            // Graves may also lie in the other graveyards searched
+            None if cli.seance && (record_existed || records.len() > 1) => {
+                for grave in seance_graves(&records, &cli, cwd)? {
+                    graves_to_exhume.push(grave.dest);
+                }
+            }
+            None if cli.seance => {
+                writeln!(
+                    stream,
+                    "No graveyard record found at {}",
+                    graveyard.join(record_name).display()
+                )?;
+            }
+            None => {}
+        }
+
+        // Otherwise, add the last deleted file
+        if graves_to_exhume.is_empty() && edited.is_none() {
+            if let Ok(s) = record.get_last_bury() {
+                graves_to_exhume.push(s);
+            }
+        }
+
+        // Each grave is exhumed through the record of the graveyard it
+        // lies in
+        let mut exhumed: Vec<(&Record, Vec<PathBuf>)> = Vec::new();
+        for (_, record) in &records {
+            let graves: Vec<PathBuf> = record
+                .lines_of_graves(&graves_to_exhume)
+                .map(|line| RecordItem::new(&line).dest)
+                .collect();
+            if !graves.is_empty() {
+                exhumed.push((record, graves));
+            }
+        }
+        if let Some(archive_path) = &cli.archive {
+            let entries: Vec<RecordItem> = exhumed
+                .iter()
+                .flat_map(|(record, graves)| record.lines_of_graves(graves))
+                .map(|line| RecordItem::new(&line))
+                .collect();
+            archive_graves(archive_path, &entries, stream)?;
+        } else {
+            let mut summary = RunSummary::default();
+            for (record, graves) in &mut exhumed {
+                let sizes: HashMap<PathBuf, u64> = match cli.summary_json {
+                    Some(_) => graves
+                        .iter()
+                        .map(|grave| (grave.clone(), util::grave_size(grave).unwrap_or(0)))
+                        .collect(),
+                    None => HashMap::new(),
+                };
+                *graves = unbury_graves(record, graves, &cli, &mode, stream)?;
+                for grave in graves.iter() {
+                    summary.unburied += 1;
+                    summary.bytes += sizes.get(grave).copied().unwrap_or(0);
+                    summary.targets.push((grave.clone(), "unburied"));
+                }
+            }
+            write_count_file(&cli, summary.unburied)?;
+            write_summary_json(&cli, &summary, start.elapsed(), stream)?;
+        }
+        for (record, graves) in &exhumed {
+            record.log_exhumed_graves(graves)?;
+        }
+    } else if let Some(other) = &cli.merge_graveyard {
+        merge_graveyard(graveyard, other, &record, record_name, &cli, &mode, stream)?;
+    } else if let Some(dir) = &cli.watch {
+        writeln!(stream, "Watching {}", dir.display())?;
+        loop {
+            watch_once(dir, graveyard, &record, cwd, &cli, &mode, stream)?;
+            // Tests check a single pass
+            if mode.is_test() {
+                break;
+            }
+            std::thread::sleep(WATCH_INTERVAL);
+        }
+    } else if let Some(paths) = &cli.forget {
+        forget_graves(&record, paths, cli.purge, cwd, stream)?;
+    } else if cli.expire {
+        expire_graves(&record, stream)?;
+    } else if let Some(trash) = &cli.import_trash {
+        let trash = match trash {
+            Some(dir) => dir.clone(),
+            None => default_trash()?,
+        };
+        import_trash(&trash, graveyard, &record, &cli, &mode, stream)?;
+    } else if cli.dedup {
+        dedup_graves(&record, cli.verbose, stream)?;
+    } else if cli.commit {
+        let count = record.commit_pending()?;
+        writeln!(stream, "Committed {} pending graves", count)?;
+    } else if cli.rollback {
+        let pending = record.pending_graves()?;
+        let exhumed = unbury_graves(&record, &pending, &cli, &mode, stream)?;
+        record.log_exhumed_graves(&exhumed)?;
+    } else if cli.recent || cli.undo_recent {
+        let grace =
+            chrono::Duration::from_std(cli.grace.unwrap_or(DEFAULT_GRACE)).map_err(Error::other)?;
+        let graves = record.graves_since(chrono::Local::now().fixed_offset() - grace)?;
+        if cli.undo_recent {
+            let graves: Vec<PathBuf> = graves.into_iter().map(|grave| grave.dest).collect();
+            let exhumed = unbury_graves(&record, &graves, &cli, &mode, stream)?;
+            record.log_exhumed_graves(&exhumed)?;
+        } else {
+            print_seance(&graves, &cli, None, stream)?;
+        }
+    } else if let Some(pattern) = &cli.find {
+        find_graves(
+            &read_records(graveyard, record_name, &cli),
+            pattern,
+            &cli,
+            cwd,
+            stream,
+        )?;
+    } else if cli.tree {
+        let root = grave_root(graveyard, &cli)?;
+        let mut output = Vec::new();
+        print_tree(
+            &root,
+            &graveyard.join(record_name),
+            cli.inspect_depth,
+            &mut output,
+        )?;
+        page(&output, &cli, &mode, stream)?;
+    } else if cli.seance {
+        let mut records = read_records(graveyard, record_name, &cli);
+        if cli.all_history {
+            let history_name = format!("{}{}", record_name, record::HISTORY_SUFFIX);
+            let histories: Vec<(PathBuf, Record)> = records
+                .iter()
+                .filter(|(graveyard, _)| graveyard.join(&history_name).is_file())
+                .map(|(graveyard, _)| (graveyard.clone(), Record::new(graveyard, &history_name)))
+                .collect();
+            records.extend(histories);
+        }
+        let mut graves = seance_graves(&records, &cli, cwd)?;
+        if let Some(min_age) = cli.min_age {
+            let now = chrono::Local::now().fixed_offset();
+            graves.retain(
+                |grave| match chrono::DateTime::parse_from_rfc3339(&grave.time) {
+                    Ok(time) => (now - time).to_std().is_ok_and(|age| age >= min_age),
+                    Err(_) => true,
+                },
+            );
+        }
+        if let (true, Some(code)) = (graves.is_empty(), cli.exit_code_on_empty) {
+            return Err(Error::other(util::EmptySeance { code }));
+        }
+        let deletion_time =
+            |grave: &RecordItem| chrono::DateTime::parse_from_rfc3339(&grave.time).ok();
+        match cli.sort {
+            Some(SortOrder::Oldest) => graves.sort_by_key(deletion_time),
+            Some(SortOrder::Newest) => {
+                graves.sort_by_key(|grave| std::cmp::Reverse(deletion_time(grave)))
+            }
+            Some(SortOrder::Path) => graves.sort_by(|a, b| a.orig.cmp(&b.orig)),
+            None => {}
+        }
+        let hidden = cli
+            .limit
+            .map_or(0, |limit| graves.len().saturating_sub(limit));
+        graves.truncate(graves.len() - hidden);
+        let base = match &cli.relative_to {
+            Some(dir) => Some(dunce::canonicalize(cwd.join(dir))?),
+            None => None,
+        };
+        let mut output = Vec::new();
+        print_seance(&graves, &cli, base.as_deref(), &mut output)?;
+        if hidden > 0 {
+            writeln!(output, "... and {} more", hidden)?;
+        }
+        page(&output, &cli, &mode, stream)?;
+    } else if cli.targets.is_empty() && cli.from_archive.is_none() {
+        Args::command().print_help()?;
+    } else {
+        let mut targets = cli.targets.clone();
+        if let Some(archive_path) = &cli.from_archive {
+            targets.extend(archive_targets(archive_path)?);
+        }
+        let mut summary = RunSummary::default();
+        let _interrupt = util::InterruptGuard::new();
+        for target in &targets {
+            if util::interrupted() {
+                return Err(Error::new(
+                    ErrorKind::Interrupted,
+                    "Interrupted; the remaining targets were left in place",
+                ));
+            }
+            // Measured up front, as the target is gone afterwards
+            let size = match cli.summary_json {
+                Some(_) => util::grave_size(target).unwrap_or(0),
+                None => 0,
+            };
+            // With --local, each target goes to the graveyard in its own
+            // directory
+            let local = match resolve_through_parent(cwd, target).parent() {
+                Some(dir) if cli.local => {
+                    let local = dir.join(LOCAL_GRAVEYARD);
+                    create_graveyard(&local)?;
+                    util::check_writable(&local)?;
+                    let record = Record::new(&local, record_name);
+                    Some((local, record))
+                }
+                _ => None,
+            };
+            let (graveyard, record) = match &local {
+                Some((local, record)) => (local, record),
+                None => (graveyard, &record),
+            };
+            if bury_target(target, graveyard, record, cwd, &cli, &mode, stream)? {
+                summary.buried += 1;
+                summary.bytes += size;
+                summary.targets.push((target.clone(), "buried"));
+            } else {
+                summary.targets.push((target.clone(), "skipped"));
+            }
+        }
+        write_count_file(&cli, summary.buried)?;
+        warn_disk_usage(graveyard, &cli, stream)?;
+        write_summary_json(&cli, &summary, start.elapsed(), stream)?;
+        record.rotate(cli.record_max_lines.unwrap_or(RECORD_MAX_LINES))?;
+    }
+
+    Ok(())
+}
+
+/// Restore each of the given graves to its original location, returning
+/// the graves that are gone from the graveyard afterwards
+fn unbury_graves(
+    record: &Record,
+    graves: &[PathBuf],
+    cli: &Args,
+    mode: &impl util::TestingMode,
+    stream: &mut impl Write,
+) -> Result<Vec<PathBuf>, Error> {
+    let opts = &CopyOptions::from(cli);
+    let mut exhumed = Vec::new();
+    // Work out every destination up front, so a bad --strip-prefix
+    // fails before anything is moved
+    let entries = record
+        .lines_of_graves(graves)
+        .map(|line| {
+            let entry = RecordItem::new(&line);
+            let restore = restore_path(&entry.orig, cli)?;
+            Ok((entry, restore))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    // Go through the graveyard and exhume all the graves
+    for (entry, restore) in entries {
+        // A file shouldn't land next to (or in) a directory that was
+        // recreated at its path, nor the other way around, unless the
+        // existing one is backed up first
+        if let (false, Ok(existing), Ok(grave)) = (
+            cli.backup,
+            fs::symlink_metadata(&restore),
+            fs::symlink_metadata(&entry.dest),
+        ) {
+            if existing.is_dir() != grave.is_dir() {
+                let kind = |m: &Metadata| if m.is_dir() { "directory" } else { "file" };
+                writeln!(
+                    stream,
+                    "Warning: skipping {}: it is a {}, but {} is now a {}",
+                    entry.dest.display(),
+                    kind(&grave),
+                    restore.display(),
+                    kind(&existing)
+                )?;
+                continue;
+            }
+        }
+        let orig: PathBuf = match util::symlink_exists(&restore) {
+            true if cli.backup => {
+                let backup = util::backup_path(&restore);
+                fs::rename(&restore, &backup).map_err(|e| {
+                    Error::new(
+                        e.kind(),
+                        format!(
+                            "Unbury failed: couldn't back up {} to {}",
+                            restore.display(),
+                            backup.display()
+                        ),
+                    )
+                })?;
+                writeln!(
+                    stream,
+                    "Backed up {} to {}",
+                    restore.display(),
+                    backup.display()
+                )?;
+                restore
+            }
+            true if cli.replace_if_different
+                && fs::symlink_metadata(&restore).is_ok_and(|m| m.is_file())
+                && fs::symlink_metadata(&entry.dest).is_ok_and(|m| m.is_file()) =>
+            {
+                if util::same_contents(&entry.dest, &restore)? {
+                    // Nothing to restore, so the grave just goes away
+                    fs::remove_file(&entry.dest)?;
+                    writeln!(
+                        stream,
+                        "{} is unchanged; removed {}",
+                        restore.display(),
+                        entry.dest.display()
+                    )?;
+                    exhumed.push(entry.dest);
+                    continue;
+                }
+                fs::remove_file(&restore)?;
+                writeln!(stream, "Replacing {}", restore.display())?;
+                restore
+            }
+            true => util::rename_grave(&restore, cli.collision_suffix),
+            false => restore,
+        };
+        // A device node left as a marker file can be recreated now, given
+        // the privilege that was missing at bury time
+        if let Some(device) = entry.device() {
+            if fs::symlink_metadata(&entry.dest).is_ok_and(|m| m.is_file()) {
+                match device.create(&orig) {
+                    Ok(()) => {
+                        fs::remove_file(&entry.dest)?;
+                        writeln!(
+                            stream,
+                            "Recreated device node {} from {}",
+                            orig.display(),
+                            entry.dest.display()
+                        )?;
+                        exhumed.push(entry.dest);
+                        continue;
+                    }
+                    Err(e) => writeln!(
+                        stream,
+                        "Warning: couldn't recreate device node {} ({}); restoring the marker file",
+                        orig.display(),
+                        e
+                    )?,
+                }
+            }
+        }
+        #[cfg(unix)]
+        break_hardlinks(&entry.dest)?;
+        let outcome = move_target(&entry.dest, &orig, opts, mode, stream).map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!(
+                    "Unbury failed: couldn't copy files from {} to {}",
+                    entry.dest.display(),
+                    orig.display()
+                ),
+            )
+        })?;
+        match outcome {
+            MoveOutcome::Kept => {
+                writeln!(stream, "Skipping {}", entry.dest.display())?;
+                continue;
+            }
+            MoveOutcome::Deleted => {
+                exhumed.push(entry.dest);
+                continue;
+            }
+            MoveOutcome::Moved => exhumed.push(entry.dest.clone()),
+        }
+        #[cfg(unix)]
+        if let Some(mode) = entry.mode() {
+            fs::set_permissions(&orig, fs::Permissions::from_mode(mode))?;
+        }
+        // Copying gives the file a new birth time, so put the old one back
+        // where the platform allows it
+        if let Some(crtime) = entry.crtime() {
+            util::set_crtime(&orig, crtime).ok();
+        }
+        writeln!(
+            stream,
+            "Returned {} to {}",
+            entry.dest.display(),
+            orig.display()
+        )?;
+    }
+    Ok(exhumed)
+}
+
+/// Give each file in the grave that has other hardlinks, as left by
+/// `--dedup`, a copy of its own, so editing it once restored can't change
+/// the graves still buried
+#[cfg(unix)]
+fn break_hardlinks(grave: &Path) -> Result<(), Error> {
+    for entry in WalkDir::new(grave).into_iter().filter_map(|e| e.ok()) {
+        let metadata = fs::symlink_metadata(entry.path())?;
+        if !metadata.is_file() || metadata.nlink() < 2 {
+            continue;
+        }
+        // Copy beside the file, then swap it in, so the grave is never
+        // missing
+        let copy = PathBuf::from(format!("{}.rip-unlink", entry.path().display()));
+        fs::copy(entry.path(), &copy)?;
+        util::set_mtime(&copy, metadata.modified()?)?;
+        fs::rename(&copy, entry.path())?;
+    }
+    Ok(())
+}
+
+/// Where a grave is restored to: its original path, or with `--to`, that
+/// path moved under the new root. `--strip-prefix` keeps the part of the
+/// original path below the prefix; otherwise only the file name is kept.
+fn restore_path(orig: &Path, cli: &Args) -> Result<PathBuf, Error> {
+    let Some(to) = &cli.to else {
+        return Ok(orig.to_path_buf());
+    };
+    let relative = match &cli.strip_prefix {
+        Some(prefix) => orig.strip_prefix(prefix).map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Unbury failed: {} is not under --strip-prefix {}",
+                    orig.display(),
+                    prefix.display()
+                ),
+            )
+        })?,
+        None => Path::new(orig.file_name().unwrap_or_default()),
+    };
+    Ok(to.join(relative))
+}
+
+/// Let the user pick which graves to restore by editing a list of them in
+/// `$EDITOR`, keeping the lines to restore. Returns `None` when no editor
+/// can be used, so unbury carries on as if `--edit` wasn't given.
+fn edit_graves(
+    candidates: &[RecordItem],
+    mode: &impl util::TestingMode,
+    stream: &mut impl Write,
+) -> Result<Option<Vec<PathBuf>>, Error> {
+    let Some(editor) = env::var_os("EDITOR").filter(|editor| !editor.is_empty()) else {
+        writeln!(stream, "$EDITOR is not set; ignoring --edit")?;
+        return Ok(None);
+    };
+    if !mode.is_test() && !std::io::stdin().is_terminal() {
+        writeln!(stream, "Not running in a terminal; ignoring --edit")?;
+        return Ok(None);
+    }
+
+    let (path, mut file) = util::create_private_temp("rip-unbury", ".txt")?;
+    let mut contents = String::from(
+        "# Delete or comment out the graves to leave buried,\n\
+         # then save and close the editor.\n",
+    );
+    for grave in candidates {
+        contents.push_str(&format!("{}\t{}\n", grave.id(), grave.orig.display()));
+    }
+    file.write_all(contents.as_bytes())?;
+    drop(file);
+    let editor = editor.to_string_lossy().into_owned();
+    let mut words = editor.split_whitespace();
+    let status = std::process::Command::new(words.next().unwrap_or_default())
+        .args(words)
+        .arg(&path)
+        .status();
+    let edited = fs::read_to_string(&path);
+    fs::remove_file(&path).ok();
+    let status = status?;
+    if !status.success() {
+        return Err(Error::other(format!("{} exited with {}", editor, status)));
+    }
+
+    let edited = edited?;
+    let kept: Vec<&str> = edited
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_whitespace().next())
+        .collect();
+    Ok(Some(
+        candidates
+            .iter()
+            .filter(|grave| kept.contains(&grave.id().as_str()))
+            .map(|grave| grave.dest.clone())
+            .collect(),
+    ))
+}
+
+/// Permanently delete every grave past its expiry and forget it
+fn expire_graves(record: &Record, stream: &mut impl Write) -> Result<(), Error> {
+    let graves = record.expired_graves(chrono::Local::now().fixed_offset())?;
+    let mut freed = 0;
+    for grave in &graves {
+        freed += util::grave_size(grave).unwrap_or(0);
+        match fs::symlink_metadata(grave) {
+            Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(grave)?,
+            Ok(_) => fs::remove_file(grave)?,
+            // Already gone, so only the record needs updating
+            Err(_) => {}
+        }
+        writeln!(stream, "Expired {}", grave.display())?;
+    }
+    record.rewrite_lines(|line| match RecordItem::parse(line) {
+        Some(item) if graves.contains(&item.dest) => None,
+        _ => Some(line.to_string()),
+    })?;
+    writeln!(stream, "Freed {}", util::humanize_bytes(freed))?;
+    Ok(())
+}
+
+/// The home trash of the FreeDesktop spec: `$XDG_DATA_HOME/Trash`, or
+/// `~/.local/share/Trash`
+fn default_trash() -> Result<PathBuf, Error> {
+    match (env::var_os("XDG_DATA_HOME"), env::var_os("HOME")) {
+        (Some(data), _) if !data.is_empty() => Ok(PathBuf::from(data).join("Trash")),
+        (_, Some(home)) => Ok(PathBuf::from(home).join(".local/share/Trash")),
+        _ => Err(Error::new(
+            ErrorKind::NotFound,
+            "Couldn't find the trash; pass its directory to --import-trash",
+        )),
+    }
+}
+
+/// Move every file of a FreeDesktop trash into the graveyard as if it had
+/// been buried from its original path, at its deletion time. Its
+/// `.trashinfo` is removed once the grave is recorded.
+fn import_trash(
+    trash: &Path,
+    graveyard: &Path,
+    record: &Record,
+    cli: &Args,
+    mode: &impl util::TestingMode,
+    stream: &mut impl Write,
+) -> Result<(), Error> {
+    let info_dir = trash.join("info");
+    let mut infos: Vec<PathBuf> = fs::read_dir(&info_dir)
+        .map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!("Failed to read trash {}: {}", info_dir.display(), e),
+            )
+        })?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "trashinfo"))
+        .collect();
+    infos.sort();
+
+    let opts = &CopyOptions::from(cli);
+    let mut imported = 0;
+    for info in infos {
+        let Some((orig, date)) = fs::read_to_string(&info)
+            .ok()
+            .and_then(|contents| util::parse_trashinfo(&contents))
+        else {
+            writeln!(stream, "Skipping {}: not a trash info file", info.display())?;
+            continue;
+        };
+        let file = trash
+            .join("files")
+            .join(info.file_stem().unwrap_or_default());
+        let Ok(metadata) = fs::symlink_metadata(&file) else {
+            writeln!(stream, "Skipping {}: no trashed file", info.display())?;
+            continue;
+        };
+        // Relative paths are relative to the directory holding the trash
+        let orig = match trash.parent() {
+            Some(top) if orig.is_relative() => top.join(orig),
+            _ => orig,
+        };
+        let time = date
+            .and_then(|date| chrono::NaiveDateTime::parse_from_str(&date, "%Y-%m-%dT%H:%M:%S").ok())
+            .and_then(|naive| naive.and_local_timezone(chrono::Local).single())
+            .unwrap_or_else(chrono::Local::now)
+            .to_rfc3339();
+
+        let dest = util::reserve_grave(
+            util::join_absolute(graveyard, &orig),
+            metadata.is_dir(),
+            cli.collision_suffix,
+        )?;
+        let outcome = move_target(&file, &dest, opts, mode, stream);
+        if !matches!(outcome, Ok(MoveOutcome::Moved)) {
+            // Give back the reserved grave
+            fs::remove_dir(&dest)
+                .or_else(|_| fs::remove_file(&dest))
+                .ok();
+        }
+        match outcome? {
+            MoveOutcome::Moved => {}
+            MoveOutcome::Deleted => {
+                fs::remove_file(&info)?;
+                continue;
+            }
+            MoveOutcome::Kept => continue,
+        }
+        record.append(&RecordItem {
+            time,
+            orig: orig.clone(),
+            dest,
+            extras: Extras::new(),
+        })?;
+        fs::remove_file(&info)?;
+        imported += 1;
+        if cli.verbose {
+            writeln!(stream, "Imported {}", orig.display())?;
+        }
+    }
+    writeln!(
+        stream,
+        "Imported {} files from {}",
+        imported,
+        trash.display()
+    )?;
+    Ok(())
+}
+
+/// Replace grave files with the same contents, permissions, owner, and
+/// modification time by hardlinks to a single copy, leaving the record as
+/// it is. Unbury gives each grave its own copy back before restoring it.
+fn dedup_graves(record: &Record, verbose: bool, stream: &mut impl Write) -> Result<(), Error> {
+    // Only files of the same size can be identical, so most are never read
+    type Key = (u64, Option<(u32, u32, u32)>, Option<SystemTime>);
+    let mut candidates: HashMap<Key, Vec<PathBuf>> = HashMap::new();
+    for entry in record.entries()? {
+        for file in WalkDir::new(&entry.dest).into_iter().filter_map(|e| e.ok()) {
+            let metadata = file.metadata()?;
+            if metadata.is_file() {
+                let key = (
+                    metadata.len(),
+                    util::mode_and_owner(&metadata),
+                    metadata.modified().ok(),
+                );
+                candidates
+                    .entry(key)
+                    .or_default()
+                    .push(file.path().to_path_buf());
+            }
+        }
+    }
+
+    let (mut linked, mut reclaimed) = (0, 0);
+    for ((size, _, _), files) in candidates.into_iter().filter(|(_, files)| files.len() > 1) {
+        let mut hashes: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+        for file in files {
+            hashes
+                .entry(util::content_hash(&file)?)
+                .or_default()
+                .push(file);
+        }
+        for (_, mut files) in hashes {
+            let keep = files.remove(0);
+            let keep_id = util::file_id(&fs::metadata(&keep)?);
+            for file in files {
+                // Already linked, or a hash collision
+                if keep_id.is_some() && util::file_id(&fs::metadata(&file)?) == keep_id
+                    || !util::same_contents(&keep, &file)?
+                {
+                    continue;
+                }
+                // Link beside the grave, then swap it in, so the grave is
+                // never missing
+                let link = PathBuf::from(format!("{}.rip-dedup", file.display()));
+                fs::hard_link(&keep, &link)?;
+                fs::rename(&link, &file)?;
+                if verbose {
+                    writeln!(stream, "Linked {} to {}", file.display(), keep.display())?;
+                }
+                linked += 1;
+                reclaimed += size;
+            }
+        }
+    }
+    writeln!(
+        stream,
+        "Deduplicated {} files, reclaimed {}",
+        linked,
+        util::humanize_bytes(reclaimed)
+    )?;
+    Ok(())
+}
+
+/// Write the given graves into a tar archive under their original paths,
+/// then remove them from the graveyard.
+fn archive_graves(
+    archive_path: &Path,
+    entries: &[RecordItem],
+    stream: &mut impl Write,
+) -> Result<(), Error> {
+    let mut builder = archive::TarWriter::new(fs::File::create(archive_path)?);
+    for entry in entries {
+        builder
+            .append_path(&entry.dest, &archive::archive_name(&entry.orig))
+            .map_err(|e| {
+                Error::new(
+                    e.kind(),
+                    format!(
+                        "Failed to archive {} into {}: {}",
+                        entry.dest.display(),
+                        archive_path.display(),
+                        e
+                    ),
+                )
+            })?;
+    }
+    builder.finish()?;
+
+    // Only remove the graves once the archive is complete
+    for entry in entries {
+        if fs::symlink_metadata(&entry.dest)?.is_dir() {
+            fs::remove_dir_all(&entry.dest)?;
+        } else {
+            fs::remove_file(&entry.dest)?;
+        }
+        writeln!(
+            stream,
+            "Archived {} to {}",
+            entry.dest.display(),
+            archive_path.display()
+        )?;
+    }
+    Ok(())
+}
+
+/// Write the targets into a tar archive on `stream`, under their absolute
+/// paths, then remove them. Nothing else is written to `stream`, so that
+/// it can be piped into another tool.
+fn bury_to_stream(
+    targets: &[PathBuf],
+    mode: &impl util::TestingMode,
+    stream: &mut impl Write,
+) -> Result<(), Error> {
+    if !mode.is_test() && std::io::stdout().is_terminal() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Refusing to write a tar archive to a terminal; redirect or pipe stdout",
+        ));
+    }
+    let cwd = &env::current_dir()?;
+    let mut sources = Vec::new();
+    for target in targets {
+        if fs::symlink_metadata(target).is_err() {
+            return Err(util::path_error(
+                ErrorKind::NotFound,
+                target,
+                format!(
+                    "Cannot remove {}: no such file or directory",
+                    target.display()
+                ),
+            ));
+        }
+        sources.push(resolve_through_parent(cwd, target));
+    }
+
+    let mut builder = archive::TarWriter::new(&mut *stream);
+    for source in &sources {
+        builder
+            .append_path(source, &archive::archive_name(source))
+            .map_err(|e| {
+                Error::new(
+                    e.kind(),
+                    format!("Failed to write {} to stdout: {}", source.display(), e),
+                )
+            })?;
+    }
+    builder.finish()?;
+
+    // Only remove the targets once the archive is complete
+    for source in &sources {
+        if fs::symlink_metadata(source)?.is_dir() {
+            fs::remove_dir_all(source)?;
+        } else {
+            fs::remove_file(source)?;
+        }
+    }
+    Ok(())
+}
+
+/// The member paths of a tar archive, skipping any whose parent directory
+/// is also a member since burying the parent takes them along.
+fn archive_targets(archive_path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let file = fs::File::open(archive_path).map_err(|e| {
+        Error::new(
+            e.kind(),
+            format!("Failed to open archive {}", archive_path.display()),
+        )
+    })?;
+    let paths: Vec<PathBuf> = archive::read_names(file)?
+        .into_iter()
+        .map(|entry| entry.path)
+        .filter(|path| !path.as_os_str().is_empty())
+        .collect();
+    Ok(paths
+        .iter()
+        .filter(|path| {
+            !paths
+                .iter()
+                .any(|other| other != *path && path.starts_with(other))
+        })
+        .cloned()
+        .collect())
+}
+
+/// Directory that graves are placed under: the graveyard itself, or the
+/// `@ROOM` subdirectory when `--room` is given.
+fn grave_root(graveyard: &Path, cli: &Args) -> Result<PathBuf, Error> {
+    room_root(graveyard, cli.room.as_deref())
+}
+
+fn room_root(graveyard: &Path, room: Option<&str>) -> Result<PathBuf, Error> {
+    match room {
+        Some(room) => {
+            if room.is_empty() || room.contains(['/', '\\']) || room == "." || room == ".." {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Invalid room name: {}", room),
+                ));
+            }
+            Ok(graveyard.join(format!("@{}", room)))
+        }
+        None => Ok(graveyard.to_path_buf()),
+    }
+}
+
+/// Paths from `--protect`, then from `$RIP_PROTECT`, resolved against
+/// `cwd` so they compare with canonical targets
+fn protected_paths(cli: &Args, cwd: &Path) -> Vec<PathBuf> {
+    let from_env = env::var_os("RIP_PROTECT").unwrap_or_default();
+    cli.protect
+        .iter()
+        .cloned()
+        .chain(env::split_paths(&from_env).filter(|path| !path.as_os_str().is_empty()))
+        .map(|path| dunce::canonicalize(cwd.join(&path)).unwrap_or_else(|_| cwd.join(path)))
+        .collect()
+}
+
+/// Routing rules from `--route`, then from `$RIP_ROUTES` (separated by
+/// `;`), each as a glob on the file name and the room to bury it in
+fn routes(cli: &Args) -> Result<Vec<(regex::Regex, String)>, Error> {
+    let from_env = env::var("RIP_ROUTES").unwrap_or_default();
+    cli.route
+        .iter()
+        .map(String::as_str)
+        .chain(from_env.split(';').filter(|rule| !rule.trim().is_empty()))
+        .map(|rule| {
+            let (pattern, room) = rule.rsplit_once('=').ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Invalid route: {} (expected PATTERN=ROOM)", rule),
+                )
+            })?;
+            Ok((util::glob_regex(pattern.trim())?, room.trim().to_string()))
+        })
+        .collect()
+}
+
+/// The room a target is buried in: `--room`, or else the room of the
+/// first route matching its file name
+fn target_room(source: &Path, cli: &Args) -> Result<Option<String>, Error> {
+    if cli.room.is_some() {
+        return Ok(cli.room.clone());
+    }
+    let Some(name) = source.file_name() else {
+        return Ok(None);
+    };
+    let name = name.to_string_lossy();
+    Ok(routes(cli)?
+        .into_iter()
+        .find(|(pattern, _)| pattern.is_match(&name))
+        .map(|(_, room)| room))
+}
+
+/// Permanently delete a single room and forget its graves
+fn decompose_room(
+    room_root: &Path,
+    record: &Record,
+    cli: &Args,
+    mode: &impl util::TestingMode,
+    stream: &mut impl Write,
+) -> Result<(), Error> {
+    let opts = CopyOptions::from(cli);
+    let count = record.seance(&room_root.to_path_buf())?.count();
+    let prompt = format!("Really unlink {} graves in {}?", count, room_root.display());
+    if opts.prompts.ask(prompt, false, true, mode, stream)? {
+        if room_root.exists() {
+            let freed = remove_tree(room_root, cli.verbose, stream)?;
+            writeln!(
+                stream,
+                "Freed {} from {}",
+                util::humanize_bytes(freed),
+                room_root.display()
+            )?;
+        }
+        record.rewrite_lines(|line| match RecordItem::parse(line) {
+            Some(item) if item.dest.starts_with(room_root) => None,
+            _ => Some(line.to_string()),
+        })?;
+    }
+    Ok(())
+}
+
+/// Permanently delete `root` and everything in it, returning the number
+/// of bytes freed. With `verbose`, the removal is walked entry by entry
+/// and its progress reported.
+fn remove_tree(root: &Path, verbose: bool, stream: &mut impl Write) -> Result<u64, Error> {
+    let size = util::grave_size(root).unwrap_or(0);
+    if !verbose {
+        fs::remove_dir_all(root)?;
+        return Ok(size);
+    }
+
+    let entries = WalkDir::new(root)
+        .into_iter()
+        .map(|entry| entry.map(|entry| (entry.path().to_path_buf(), entry.file_type().is_dir())))
+        .collect::<Result<Vec<_>, _>>()?;
+    let total = entries.len();
+    writeln!(
+        stream,
+        "Removing {} entries ({}) from {}",
+        total,
+        util::humanize_bytes(size),
+        root.display()
+    )?;
+    // Deepest entries come last in the walk, so go backwards to empty
+    // each directory before removing it
+    for (i, (path, is_dir)) in entries.iter().rev().enumerate() {
+        if *is_dir {
+            fs::remove_dir(path)?;
+        } else {
+            fs::remove_file(path)?;
+        }
+        let removed = i + 1;
+        if removed % DECOMPOSE_PROGRESS_INTERVAL == 0 || removed == total {
+            writeln!(stream, "Removed {}/{}", removed, total)?;
+        }
+    }
+    Ok(size)
+}
+
+/// Move the whole graveyard to `new_graveyard` and rewrite the record's
+/// destination paths to match. Original paths are left untouched.
+fn relocate_graveyard(
+    graveyard: &Path,
+    new_graveyard: &Path,
+    record_name: &str,
+    opts: &CopyOptions,
+    mode: &impl util::TestingMode,
+    stream: &mut impl Write,
+) -> Result<(), Error> {
+    if !graveyard.is_dir() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("No graveyard to relocate at {}", graveyard.display()),
+        ));
+    }
+    if util::symlink_exists(new_graveyard) {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!(
+                "Cannot relocate to {}: it already exists",
+                new_graveyard.display()
+            ),
+        ));
+    }
+    // Record paths need absolute locations to stay valid
+    let new_graveyard = &if new_graveyard.is_absolute() {
+        new_graveyard.to_path_buf()
+    } else {
+        env::current_dir()?.join(new_graveyard)
+    };
+    // Moving a directory into itself would never finish
+    if resolve_existing_ancestor(new_graveyard).starts_with(dunce::canonicalize(graveyard)?) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Cannot relocate {} into itself at {}",
+                graveyard.display(),
+                new_graveyard.display()
+            ),
+        ));
+    }
+
+    // The record moves along with the graves, and still points at the old
+    // location until it is rewritten below
+    move_target(graveyard, new_graveyard, opts, mode, stream)?;
+    Record::new(new_graveyard, record_name).rebase_dests(graveyard, new_graveyard)?;
+
+    writeln!(
+        stream,
+        "Moved graveyard from {} to {}",
+        graveyard.display(),
+        new_graveyard.display()
+    )?;
+    writeln!(
+        stream,
+        "Update --graveyard or RIP_GRAVEYARD to keep using it"
+    )?;
+    Ok(())
+}
+
+/// Remove the record entries for `paths`, given as original or grave
+/// paths, and with `purge` permanently delete their graves too
+fn forget_graves(
+    record: &Record,
+    paths: &[PathBuf],
+    purge: bool,
+    cwd: &Path,
+    stream: &mut impl Write,
+) -> Result<(), Error> {
+    // The originals are usually gone, so resolve through the parent
+    let paths: Vec<PathBuf> = paths
+        .iter()
+        .map(|path| resolve_through_parent(cwd, path))
+        .collect();
+    record.open()?;
+    let forgotten = record.forget(&paths)?;
+    if forgotten.is_empty() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!(
+                "No record entry for {}",
+                paths
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        ));
+    }
+    let mut freed = 0;
+    for item in &forgotten {
+        if purge && util::symlink_exists(&item.dest) {
+            freed += util::grave_size(&item.dest).unwrap_or(0);
+            if fs::remove_dir_all(&item.dest).is_err() {
+                fs::remove_file(&item.dest)?;
+            }
+            writeln!(stream, "Permanently deleted {}", item.dest.display())?;
+        }
+        writeln!(stream, "Forgot {}", item.orig.display())?;
+    }
+    if purge {
+        writeln!(stream, "Freed {}", util::humanize_bytes(freed))?;
+    }
+    Ok(())
+}
+
+/// Bury every file directly in `dir` that matches `--match` and is
+/// older than `--older-than`
+fn watch_once(
+    dir: &Path,
+    graveyard: &PathBuf,
+    record: &Record,
+    cwd: &Path,
+    cli: &Args,
+    mode: &impl util::TestingMode,
+    stream: &mut impl Write,
+) -> Result<(), Error> {
+    let pattern = cli
+        .watch_match
+        .as_deref()
+        .map(util::glob_regex)
+        .transpose()?;
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .collect();
+    entries.sort();
+    for path in entries {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if pattern
+            .as_ref()
+            .is_some_and(|pattern| !pattern.is_match(&name))
+        {
+            continue;
+        }
+        // Skip anything that vanished in the meantime
+        let Ok(metadata) = fs::symlink_metadata(&path) else {
+            continue;
+        };
+        let age = metadata
+            .modified()
+            .ok()
+            .and_then(|mtime| mtime.elapsed().ok())
+            .unwrap_or_default();
+        if cli.older_than.is_some_and(|older_than| age < older_than) {
+            continue;
+        }
+        bury_target(&path, graveyard, record, cwd, cli, mode, stream)?;
+    }
+    Ok(())
+}
+
+/// Make `path` absolute against `cwd`, canonicalizing its parent (where
+/// that exists) but not the path itself, which may not exist
+fn resolve_through_parent(cwd: &Path, path: &Path) -> PathBuf {
+    let path = cwd.join(path);
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => dunce::canonicalize(parent)
+            .map(|parent| parent.join(name))
+            .unwrap_or(path),
+        _ => path,
+    }
+}
+
+/// Canonicalize the nearest existing ancestor of `path`, keeping the
+/// missing components after it as they are
+fn resolve_existing_ancestor(path: &Path) -> PathBuf {
+    let mut missing = Vec::new();
+    let mut ancestor = path;
+    loop {
+        if let Ok(canonical) = dunce::canonicalize(ancestor) {
+            return missing
+                .iter()
+                .rev()
+                .fold(canonical, |acc, name| acc.join(name));
+        }
+        match (ancestor.parent(), ancestor.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name);
+                ancestor = parent;
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
+/// Move every grave of `other` into the graveyard, renaming on conflicts,
+/// and carry their entries over into `record` with the new grave paths
+fn merge_graveyard(
+    graveyard: &Path,
+    other: &Path,
+    record: &Record,
+    record_name: &str,
+    cli: &Args,
+    mode: &impl util::TestingMode,
+    stream: &mut impl Write,
+) -> Result<(), Error> {
+    let opts = &CopyOptions::from(cli);
+    if !other.join(record_name).is_file() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("No graveyard to merge at {}", other.display()),
+        ));
+    }
+    if dunce::canonicalize(other)? == dunce::canonicalize(graveyard)? {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Cannot merge a graveyard into itself",
+        ));
+    }
+    let other_record = Record::new(other, record_name);
+    // Grave paths in the record are absolute, and may have been written
+    // through either spelling of the graveyard
+    let prefixes = [env::current_dir()?.join(other), dunce::canonicalize(other)?];
+    let entries: Vec<(PathBuf, RecordItem)> = other_record
+        .entries()?
+        .into_iter()
+        .filter_map(|item| {
+            let rest = prefixes
+                .iter()
+                .find_map(|prefix| item.dest.strip_prefix(prefix).ok())?
+                .to_path_buf();
+            Some((rest, item))
+        })
+        .collect();
+    if entries.is_empty() && !other_record.entries()?.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "None of the graves recorded in {} lie under it",
+                other.join(record_name).display()
+            ),
+        ));
+    }
+
+    let mut merged = HashSet::new();
+    let result = (|| -> Result<(), Error> {
+        for (rest, item) in entries {
+            if !util::symlink_exists(&item.dest) {
+                continue;
+            }
+            let mut dest = graveyard.join(rest);
+            if util::symlink_exists(&dest) {
+                dest = util::rename_grave(dest, cli.collision_suffix);
+            }
+            let outcome = move_target(&item.dest, &dest, opts, mode, stream)?;
+            if outcome == MoveOutcome::Kept {
+                continue;
+            }
+            merged.insert(item.dest.clone());
+            if outcome == MoveOutcome::Moved {
+                record.append(&RecordItem { dest, ..item })?;
+            }
+        }
+        Ok(())
+    })();
+
+    // Even after a failure, the graves already moved must leave the other
+    // record so they aren't listed twice
+    other_record.rewrite_lines(|line| match RecordItem::parse(line) {
+        Some(item) if merged.contains(&item.dest) => None,
+        _ => Some(line.to_string()),
+    })?;
+    result?;
+    if other_record.entries()?.is_empty() {
+        other_record.remove()?;
+    }
+    // Clear out the directories the graves were moved out of
+    let dirs: Vec<PathBuf> = WalkDir::new(other)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_dir())
+        .map(|entry| entry.path().to_path_buf())
+        .collect();
+    for dir in dirs.iter().rev() {
+        // Only empty directories go
+        fs::remove_dir(dir).ok();
+    }
+
+    writeln!(
+        stream,
+        "Merged {} graves from {}",
+        merged.len(),
+        other.display()
+    )?;
+    Ok(())
+}
+
+/// Move each target into `dir` like `mv`, using the same machinery and
+/// conflict renaming as burying but without touching the record
+fn move_into(
+    targets: &[PathBuf],
+    dir: &Path,
+    suffix: CollisionSuffix,
+    opts: &CopyOptions,
+    mode: &impl util::TestingMode,
+    stream: &mut impl Write,
+) -> Result<(), Error> {
+    if !dir.is_dir() {
+        return Err(util::path_error(
+            ErrorKind::NotFound,
+            dir,
+            format!("Cannot move into {}: not a directory", dir.display()),
+        ));
+    }
+    for target in targets {
+        let name = target.file_name().ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Cannot move {}: no file name", target.display()),
+            )
+        })?;
+        if fs::symlink_metadata(target).is_err() {
+            return Err(util::path_error(
+                ErrorKind::NotFound,
+                target,
+                format!(
+                    "Cannot move {}: no such file or directory",
+                    target.display()
+                ),
+            ));
+        }
+        let dest = dir.join(name);
+        let dest = if util::symlink_exists(&dest) {
+            util::rename_grave(dest, suffix)
+        } else {
+            dest
+        };
+        move_target(target, &dest, opts, mode, stream)?;
+    }
+    Ok(())
+}
+
+/// Turn unbury arguments into grave paths. Each may be a grave or an
+/// original path as printed by seance, and relative ones are resolved
+/// against `base`. Original paths map to their latest grave, and with
+/// `--recursive` so does every original path under them. A bare name
+/// that is neither falls back to the graves of that name; several of them
+/// are an error unless `--all-matches` is given.
+fn resolve_grave_refs(
+    refs: &[PathBuf],
+    records: &[(PathBuf, Record)],
+    base: &Path,
+    cli: &Args,
+    stream: &mut impl Write,
+) -> Result<Vec<PathBuf>, Error> {
+    let entries: Vec<RecordItem> = records
+        .iter()
+        .flat_map(|(_, record)| record.entries().unwrap_or_default())
+        .collect();
+    // The latest grave of each original path accepted by `matches`
+    let latest = |matches: &dyn Fn(&RecordItem) -> bool| {
+        let mut latest: Vec<&RecordItem> = Vec::new();
+        for entry in entries.iter().filter(|entry| matches(entry)) {
+            latest.retain(|other| other.orig != entry.orig);
+            latest.push(entry);
+        }
+        latest
+    };
+    let mut graves = Vec::new();
+    for reference in refs {
+        let path = base.join(reference);
+        if entries.iter().any(|entry| entry.dest == path) {
+            graves.push(path);
+            continue;
+        }
+        let found = if cli.recursive {
+            latest(&|entry| entry.orig.starts_with(&path))
+        } else {
+            latest(&|entry| entry.orig == path)
+        };
+        let is_bare_name = reference.components().count() == 1
+            && matches!(reference.components().next(), Some(Component::Normal(_)));
+        let found = match found.is_empty() && is_bare_name {
+            true => latest(&|entry| entry.orig.file_name() == Some(reference.as_os_str())),
+            false => found,
+        };
+        if found.len() > 1 && is_bare_name && !cli.recursive {
+            let origs: Vec<String> = found
+                .iter()
+                .map(|entry| format!("  {}", entry.orig.display()))
+                .collect();
+            if !cli.all_matches {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "{} matches several graves; give the full path, or use --all-matches to restore them all:\n{}",
+                        reference.display(),
+                        origs.join("\n")
+                    ),
+                ));
+            }
+            writeln!(
+                stream,
+                "Warning: {} matches {} graves; restoring all of them:\n{}",
+                reference.display(),
+                found.len(),
+                origs.join("\n")
+            )?;
+        }
+        match found.is_empty() {
+            true => graves.push(path),
+            false => graves.extend(found.into_iter().map(|entry| entry.dest.clone())),
+        }
+    }
+    Ok(graves)
+}
+
+/// Records to search when reading graves: the primary graveyard first,
+/// then any existing graveyards found under `XDG_DATA_DIRS`, and with
+/// `--all-hosts` those of the other hosts.
+fn read_records(graveyard: &Path, record_name: &str, cli: &Args) -> Vec<(PathBuf, Record)> {
+    let mut records = vec![(graveyard.to_path_buf(), Record::new(graveyard, record_name))];
+    let mut others = data_dirs_graveyards();
+    if cli.all_hosts {
+        // The graveyards of the other hosts sit next to this one
+        if let Some(Ok(entries)) = graveyard.parent().map(fs::read_dir) {
+            let mut hosts: Vec<PathBuf> = entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.is_dir())
+                .collect();
+            hosts.sort();
+            others.extend(hosts);
+        }
+    }
+    for other in others {
+        if other != graveyard && other.join(record_name).is_file() {
+            records.push((other.clone(), Record::new(&other, record_name)));
+        }
+    }
+    records
+}
+
+/// Every grave deleted from under `cwd`, across all the given records
+fn seance_graves(
+    records: &[(PathBuf, Record)],
+    cli: &Args,
+    cwd: &Path,
+) -> Result<Vec<RecordItem>, Error> {
+    let cwd = dunce::canonicalize(cwd)?;
+    let mut graves = Vec::new();
+    for (graveyard, record) in records {
+        let root = grave_root(graveyard, cli)?;
+        let gravepath = util::join_absolute(&root, &cwd);
+        graves.extend(record.seance(&root)?.filter(|grave| {
+            // Layouts other than mirror only keep the original path in
+            // the record
+            grave.orig.starts_with(&cwd)
+                || match grave.partition() {
+                    Some(partition) => grave
+                        .dest
+                        .starts_with(util::join_absolute(root.join(partition), &cwd)),
+                    None => grave.dest.starts_with(&gravepath),
+                }
+        }));
+    }
+    Ok(graves)
+}
+
+/// Print the graves whose original path matches `pattern`, as a substring
+/// or with `--regex` as a regular expression
+fn find_graves(
+    records: &[(PathBuf, Record)],
+    pattern: &str,
+    cli: &Args,
+    cwd: &Path,
+    stream: &mut impl Write,
+) -> Result<(), Error> {
+    let regex = if cli.regex {
+        Some(regex::Regex::new(pattern).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid --find pattern: {}", e),
+            )
+        })?)
+    } else {
+        None
+    };
+    let cwd = dunce::canonicalize(cwd)?;
+
+    writeln!(stream, "{: <19}\toriginal\tgrave", "deletion_time")?;
+    for (graveyard, record) in records {
+        for grave in record.seance(graveyard)? {
+            if !cli.all && !grave.orig.starts_with(&cwd) {
+                continue;
+            }
+            let orig = grave.orig.to_string_lossy();
+            let found = match &regex {
+                Some(regex) => regex.is_match(&orig),
+                None => orig.contains(pattern),
+            };
+            if found {
+                let parsed_time = chrono::DateTime::parse_from_rfc3339(&grave.time)
+                    .expect("Failed to parse time from RFC3339 format")
+                    .format("%Y-%m-%dT%H:%M:%S");
+                writeln!(
+                    stream,
+                    "{}\t{}\t{}",
+                    parsed_time,
+                    grave.orig.display(),
+                    grave.dest.display()
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print every grave given as a table, with type, size, and note columns
+/// when `--long` is given, and the original path alongside the grave when
+/// `--show-dest` is given. With a `base`, each grave is shown by its
+/// original path relative to it, or in full if it lies elsewhere.
+fn print_seance(
+    graves: &[RecordItem],
+    cli: &Args,
+    base: Option<&Path>,
+    stream: &mut impl Write,
+) -> Result<(), Error> {
+    if cli.long {
+        writeln!(
+            stream,
+            "{: <19}\tid\ttype\tsize\tpath\tnote",
+            "deletion_time"
+        )?;
+    } else {
+        writeln!(stream, "{: <19}\tid\tpath", "deletion_time")?;
+    }
+    for grave in graves {
+        let parsed_time = chrono::DateTime::parse_from_rfc3339(&grave.time)
+            .expect("Failed to parse time from RFC3339 format")
+            .format("%Y-%m-%dT%H:%M:%S")
+            .to_string();
+        let orig = match base.map(|base| grave.orig.strip_prefix(base)) {
+            Some(Ok(relative)) if relative.as_os_str().is_empty() => PathBuf::from("."),
+            Some(Ok(relative)) => relative.to_path_buf(),
+            _ => grave.orig.clone(),
+        };
+        let path = match (cli.show_dest, base) {
+            (true, _) => format!("{}  =>  {}", orig.display(), grave.dest.display()),
+            (false, Some(_)) => orig.display().to_string(),
+            (false, None) => grave.dest.display().to_string(),
+        };
+        if cli.long {
+            let size = util::grave_size(&grave.dest)
+                .map(util::humanize_bytes)
+                .unwrap_or_else(|| "-".to_string());
+            writeln!(
+                stream,
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                parsed_time,
+                grave.id(),
+                util::grave_type(&grave.dest),
+                size,
+                path,
+                grave.note().unwrap_or("")
+            )?;
+        } else {
+            writeln!(stream, "{}\t{}\t{}", parsed_time, grave.id(), path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Print the graves under `root` as an indented tree, each entry annotated
+/// with its size. The record, and the files kept next to it, are left out.
+fn print_tree(
+    root: &Path,
+    record_path: &Path,
+    max_depth: Option<usize>,
+    stream: &mut impl Write,
+) -> Result<(), Error> {
+    let mut sizes: HashMap<PathBuf, u64> = HashMap::new();
+    let mut children: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+    // A single walk gathers both the layout and the sizes; directory sizes
+    // are the sum of everything beneath them
+    for entry in WalkDir::new(root).sort_by(|a, b| a.cmp(b)) {
+        let entry = entry?;
+        let path = entry.path();
+        let beside_record = path.parent() == record_path.parent()
+            && record_path.file_name().is_some_and(|name| {
+                path.file_name().is_some_and(|file| {
+                    file.as_encoded_bytes().starts_with(name.as_encoded_bytes())
+                })
+            });
+        if beside_record {
+            continue;
+        }
+        if entry.depth() > 0 {
+            if let Some(parent) = path.parent() {
+                children
+                    .entry(parent.to_path_buf())
+                    .or_default()
+                    .push(path.to_path_buf());
+            }
+        }
+        sizes.entry(path.to_path_buf()).or_insert(0);
+        if !entry.file_type().is_dir() {
+            let len = entry.metadata()?.len();
+            for ancestor in path.ancestors() {
+                *sizes.entry(ancestor.to_path_buf()).or_insert(0) += len;
+                if ancestor == root {
+                    break;
+                }
+            }
+        }
+    }
+
+    writeln!(
+        stream,
+        "{} ({})",
+        root.display(),
+        util::humanize_bytes(sizes.get(root).copied().unwrap_or(0))
+    )?;
+    print_subtree(root, "", 1, max_depth, &sizes, &children, stream)
+}
+
+fn print_subtree(
+    dir: &Path,
+    prefix: &str,
+    depth: usize,
+    max_depth: Option<usize>,
+    sizes: &HashMap<PathBuf, u64>,
+    children: &HashMap<PathBuf, Vec<PathBuf>>,
+    stream: &mut impl Write,
+) -> Result<(), Error> {
+    if max_depth.is_some_and(|max| depth > max) {
+        return Ok(());
+    }
+    let Some(entries) = children.get(dir) else {
+        return Ok(());
+    };
+    for (i, path) in entries.iter().enumerate() {
+        let last = i + 1 == entries.len();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        writeln!(
+            stream,
+            "{}{}{} ({})",
+            prefix,
+            if last { "└── " } else { "├── " },
+            name,
+            util::humanize_bytes(sizes.get(path).copied().unwrap_or(0))
+        )?;
+        let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+        print_subtree(path, &prefix, depth + 1, max_depth, sizes, children, stream)?;
+    }
+    Ok(())
+}
+
+/// Write `output` through the pager with `--pager` when stdout is a
+/// terminal, and straight to the stream otherwise. The pager is taken
+/// from `$RIP_PAGER`, then `$PAGER`, falling back to `less`.
+fn page(
+    output: &[u8],
+    cli: &Args,
+    mode: &impl util::TestingMode,
+    stream: &mut impl Write,
+) -> Result<(), Error> {
+    if cli.pager && (mode.is_test() || std::io::stdout().is_terminal()) {
+        let pager = ["RIP_PAGER", "PAGER"]
+            .iter()
+            .filter_map(|var| env::var(var).ok())
+            .find(|pager| !pager.trim().is_empty())
+            .unwrap_or_else(|| "less".to_string());
+        let mut words = pager.split_whitespace();
+        let child = std::process::Command::new(words.next().unwrap_or_default())
+            .args(words)
+            .stdin(std::process::Stdio::piped())
+            .spawn();
+        // Without a working pager, just print as usual
+        if let Ok(mut child) = child {
+            if let Some(mut stdin) = child.stdin.take() {
+                // The pager may quit before reading everything
+                stdin.write_all(output).ok();
+            }
+            child.wait()?;
+            return Ok(());
+        }
+    }
+    stream.write_all(output)
+}
+
+/// What a bury or unbury did, for `--summary-json`
+#[derive(Debug, Default)]
+struct RunSummary {
+    buried: usize,
+    unburied: usize,
+    /// Total size of the buried or unburied files
+    bytes: u64,
+    /// Each target (or grave, when unburying) and what became of it
+    targets: Vec<(PathBuf, &'static str)>,
+}
 
+/// Write the run summary as a JSON object to `--summary-json`, or to the
+/// output stream if no path was given
+fn write_summary_json(
+    cli: &Args,
+    summary: &RunSummary,
+    elapsed: Duration,
+    stream: &mut impl Write,
+) -> Result<(), Error> {
+    let Some(path) = &cli.summary_json else {
+        return Ok(());
+    };
+    let targets: Vec<String> = summary
+        .targets
+        .iter()
+        .map(|(target, status)| {
+            format!(
+                "{{\"path\":{},\"status\":{}}}",
+                util::json_string(&target.display().to_string()),
+                util::json_string(status)
+            )
+        })
+        .collect();
+    let json = format!(
+        "{{\"buried\":{},\"unburied\":{},\"bytes\":{},\"elapsed_secs\":{:.3},\"targets\":[{}]}}\n",
+        summary.buried,
+        summary.unburied,
+        summary.bytes,
+        elapsed.as_secs_f64(),
+        targets.join(",")
+    );
+    match path {
+        Some(path) => fs::write(path, json),
+        None => stream.write_all(json.as_bytes()),
+    }
+}
+
+/// Whether the invocation only reads the graveyard and its record
+fn reads_only(cli: &Args) -> bool {
+    (cli.seance && cli.unbury.is_none())
+        || cli.find.is_some()
+        || cli.tree
+        || (cli.recent && !cli.undo_recent)
+}
+
+/// Create the graveyard, readable only by its owner, if it doesn't exist
+/// yet
+fn create_graveyard(graveyard: &Path) -> Result<(), Error> {
     if !graveyard.exists() {
-        fs::create_dir_all(graveyard)?;
+        fs::create_dir_all(graveyard).map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!(
+                    "Failed to create graveyard {} ({}); choose another location with --graveyard",
+                    graveyard.display(),
+                    e
+                ),
+            )
+        })?;
 
         #[cfg(unix)]
         {
             let metadata = graveyard.metadata()?;
             let mut permissions = metadata.permissions();
             permissions.set_mode(0o700);
+            fs::set_permissions(graveyard, permissions)?;
         }
         // TODO: Default permissions on windows should be good, but need to double-check.
     }
+    Ok(())
+}
 
-    // Stores the deleted files
-    let record = Record::new(graveyard);
-    let cwd = &env::current_dir()?;
-
-    // If the user wishes to restore everything
-    if cli.decompose {
-        if util::prompt_yes("Really unlink the entire graveyard?", &mode, stream)? {
-            fs::remove_dir_all(graveyard)?;
-        }
-    } else if let Some(mut graves_to_exhume) = cli.unbury {
-        // Vector to hold the grave path of items we want to unbury.
-        // This will be used to determine which items to remove from the
-        // record following the unbury.
-        // Initialize it with the targets passed to -r
-
-        // If -s is also passed, push all files found by seance onto
-        // the graves_to_exhume.
-        if cli.seance && record.open().is_ok() {
-            let gravepath = util::join_absolute(graveyard, dunce::canonicalize(cwd)?);
-            for grave in record.seance(&gravepath)? {
-                graves_to_exhume.push(grave.dest);
-            }
-        }
+/// The `.graveyard` directory of `dir` or its nearest ancestor that has
+/// one, or of `dir` itself if none does
+fn local_graveyard(dir: &Path) -> PathBuf {
+    dir.ancestors()
+        .map(|ancestor| ancestor.join(LOCAL_GRAVEYARD))
+        .find(|graveyard| graveyard.is_dir())
+        .unwrap_or_else(|| dir.join(LOCAL_GRAVEYARD))
+}
 
-        // Otherwise, add the last deleted file
-        if graves_to_exhume.is_empty() {
-            if let Ok(s) = record.get_last_bury() {
-                graves_to_exhume.push(s);
-            }
-        }
+/// The layout from `--grave-layout`, or its `--flat` and
+/// `--date-partition` shorthands
+fn grave_layout(cli: &Args) -> GraveLayout {
+    match cli.grave_layout {
+        Some(layout) => layout,
+        None if cli.flat => GraveLayout::Flat,
+        None if cli.date_partition => GraveLayout::Date,
+        None => GraveLayout::Mirror,
+    }
+}
 
-        // Go through the graveyard and exhume all the graves
-        for line in record.lines_of_graves(&graves_to_exhume) {
-            let entry = RecordItem::new(&line);
-            let orig: PathBuf = match util::symlink_exists(&entry.orig) {
-                true => util::rename_grave(&entry.orig),
-                false => PathBuf::from(&entry.orig),
-            };
-            move_target(&entry.dest, &orig, &mode, stream).map_err(|e| {
-                Error::new(
-                    e.kind(),
-                    format!(
-                        "Unbury failed: couldn't copy files from {} to {}",
-                        entry.dest.display(),
-                        orig.display()
-                    ),
-                )
-            })?;
-            writeln!(
-                stream,
-                "Returned {} to {}",
-                entry.dest.display(),
-                orig.display()
-            )?;
-        }
-        record.log_exhumed_graves(&graves_to_exhume)?;
-    } else if cli.seance {
-        let gravepath = util::join_absolute(graveyard, dunce::canonicalize(cwd)?);
-        writeln!(stream, "{: <19}\tpath", "deletion_time")?;
-        for grave in record.seance(&gravepath)? {
-            let parsed_time = chrono::DateTime::parse_from_rfc3339(&grave.time)
-                .expect("Failed to parse time from RFC3339 format")
-                .format("%Y-%m-%dT%H:%M:%S")
-                .to_string();
-            // Get the path separator:
-            writeln!(stream, "{}\t{}", parsed_time, grave.dest.display())?;
-        }
-    } else if cli.targets.is_empty() {
-        Args::command().print_help()?;
-    } else {
-        for target in cli.targets {
-            bury_target(&target, graveyard, &record, cwd, cli.inspect, &mode, stream)?;
-        }
+/// Write how many targets were buried or unburied to `--count-file`, for
+/// scripts that want the number without parsing the output
+fn write_count_file(cli: &Args, count: usize) -> Result<(), Error> {
+    match &cli.count_file {
+        Some(path) => fs::write(path, format!("{}\n", count)),
+        None => Ok(()),
     }
+}
 
+/// Warn if the graveyard's filesystem is at least `--warn-disk-usage`
+/// percent full
+fn warn_disk_usage(graveyard: &Path, cli: &Args, stream: &mut impl Write) -> Result<(), Error> {
+    let Some(limit) = cli.warn_disk_usage else {
+        return Ok(());
+    };
+    let Some((available, total)) = util::disk_space(graveyard).filter(|(_, total)| *total > 0)
+    else {
+        return Ok(());
+    };
+    let used = 100 - available * 100 / total;
+    if used >= u64::from(limit) {
+        writeln!(
+            stream,
+            "Warning: the graveyard's filesystem is {}% full ({} free); free up space with --expire or --decompose",
+            used,
+            util::humanize_bytes(available)
+        )?;
+    }
     Ok(())
 }
 
+/// Send a target to the graveyard, returning whether it was buried rather
+/// than skipped or permanently deleted
 fn bury_target(
     target: &PathBuf,
     graveyard: &PathBuf,
     record: &Record,
     cwd: &Path,
-    inspect: bool,
+    cli: &Args,
     mode: &impl util::TestingMode,
     stream: &mut impl Write,
-) -> Result<(), Error> {
+) -> Result<bool, Error> {
     // Check if source exists
     let metadata = &fs::symlink_metadata(target).map_err(|_| {
-        Error::new(
+        util::path_error(
             ErrorKind::NotFound,
+            target,
             format!(
                 "Cannot remove {}: no such file or directory",
                 target.to_str().unwrap()
             ),
         )
     })?;
+    // A grave ripped again can only be unlinked, so when its path already
+    // lies in the graveyard, skip canonicalizing it and go straight there
+    let canonical_graveyard = &dunce::canonicalize(graveyard).unwrap_or_else(|_| graveyard.clone());
+    let path = cwd.join(target);
+    if !cli.inspect
+        && !path.components().any(|c| c == Component::ParentDir)
+        && (path.starts_with(graveyard) || path.starts_with(canonical_graveyard))
+    {
+        unlink_grave(&path, &CopyOptions::from(cli), mode, stream)?;
+        return Ok(false);
+    }
     // Canonicalize the path unless it's a symlink
     let source = &if !metadata.file_type().is_symlink() {
         dunce::canonicalize(cwd.join(target))
             .map_err(|e| Error::new(e.kind(), "Failed to canonicalize path"))?
     } else {
-        cwd.join(target)
+        // Resolve `.` and `..` through the parent, without following the
+        // link itself
+        let path = cwd.join(target);
+        match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => dunce::canonicalize(parent)
+                .map_err(|e| Error::new(e.kind(), "Failed to canonicalize path"))?
+                .join(name),
+            _ => path,
+        }
+    };
+    if cli.verbose && metadata.file_type().is_symlink() && !source.exists() {
+        writeln!(
+            stream,
+            "Note: {} is a broken symlink; burying the link itself",
+            target.display()
+        )?;
+    }
+
+    if let Some(protected) = protected_paths(cli, cwd)
+        .into_iter()
+        .find(|path| source.starts_with(path) || path.starts_with(source))
+    {
+        return Err(util::path_error(
+            ErrorKind::PermissionDenied,
+            source,
+            format!(
+                "Cannot remove {}: {} is protected",
+                source.display(),
+                protected.display()
+            ),
+        ));
+    }
+
+    let opts = &CopyOptions::from(cli);
+
+    // An existing grave of the very same file, e.g. through a hardlink
+    let duplicate = match (cli.duplicates, util::file_id(metadata)) {
+        (DuplicatePolicy::Allow, _) | (_, None) => None,
+        // Directories can't be hardlinked, and a file with a single link
+        // can't be a grave too, so most buries never read the record
+        (_, Some(_)) if metadata.is_dir() || !util::has_other_links(metadata) => None,
+        (_, Some(file_id)) => record.find_file_id(file_id)?,
     };
 
-    if inspect && !should_we_bury_this(target, source, metadata, mode, stream)? {
+    if cli.inspect
+        && !should_we_bury_this(
+            target,
+            source,
+            metadata,
+            cli.inspect_tail,
+            opts,
+            mode,
+            stream,
+        )?
+    {
         // User chose to not bury the file
-    } else if source.starts_with(graveyard) {
-        // If rip is called on a file already in the graveyard, prompt
-        // to permanently delete it instead.
-        writeln!(stream, "{} is already in the graveyard.", source.display())?;
-        if util::prompt_yes("Permanently unlink it?", mode, stream)? {
-            if fs::remove_dir_all(source).is_err() {
-                fs::remove_file(source).map_err(|e| {
-                    Error::new(e.kind(), format!("Couldn't unlink {}", source.display()))
+    } else if source.starts_with(canonical_graveyard) {
+        unlink_grave(source, opts, mode, stream)?;
+    } else if metadata.is_dir()
+        && cli
+            .confirm_large_dir
+            .is_some_and(|limit| util::has_more_entries_than(source, limit))
+        && !opts.prompts.ask(
+            format!(
+                "{} contains more than {} entries. Send it to the graveyard?",
+                target.display(),
+                cli.confirm_large_dir.unwrap_or_default()
+            ),
+            true,
+            false,
+            mode,
+            stream,
+        )?
+    {
+        writeln!(stream, "Skipping {}", source.display())?;
+    } else if let (Some(grave), DuplicatePolicy::Skip) = (&duplicate, cli.duplicates) {
+        writeln!(
+            stream,
+            "Skipping {}: same file as {}",
+            source.display(),
+            grave.display()
+        )?;
+    } else if cli
+        .truncate_after
+        .is_some_and(|keep| !metadata.is_file() || metadata.len() <= keep)
+    {
+        writeln!(stream, "Skipping {}: nothing to truncate", source.display())?;
+    } else {
+        if let Some(grave) = &duplicate {
+            writeln!(
+                stream,
+                "Warning: {} is the same file as {}",
+                source.display(),
+                grave.display()
+            )?;
+        }
+        // Immutable and append-only files can't be moved, not even by root,
+        // so refuse before touching the graveyard. Only regular files and
+        // directories are opened to read their flags.
+        if metadata.is_file() || metadata.is_dir() {
+            if let Some(flag) = util::protection_flag(source) {
+                if !opts.prompts.force {
+                    return Err(util::path_error(
+                        ErrorKind::PermissionDenied,
+                        source,
+                        format!(
+                            "Cannot remove {}: it is {}; clear the flag with chattr or use --force",
+                            source.display(),
+                            flag
+                        ),
+                    ));
+                }
+                util::clear_protection(source).map_err(|e| {
+                    Error::new(
+                        e.kind(),
+                        format!(
+                            "Couldn't clear the {} flag of {}: {}",
+                            flag,
+                            source.display(),
+                            e
+                        ),
+                    )
                 })?;
+                writeln!(stream, "Cleared the {} flag of {}", flag, source.display())?;
             }
-        } else {
-            writeln!(stream, "Skipping {}", source.display())?;
-            // TODO: In the original code, this was a hard return from the entire
-            // method (i.e., `run`). I think it should just be a return from the bury
-            // (meaning a `continue` in the original code's loop). But I'm not sure.
         }
-    } else {
-        let dest: &Path = &{
-            let dest = util::join_absolute(graveyard, source);
-            // Resolve a name conflict if necessary
-            if util::symlink_exists(&dest) {
-                util::rename_grave(dest)
+        if cli.empty_permanently {
+            let kind = if metadata.is_file() && metadata.len() == 0 {
+                Some("file")
+            } else if metadata.is_dir() && fs::read_dir(source)?.next().is_none() {
+                Some("directory")
             } else {
-                dest
+                None
+            };
+            if let Some(kind) = kind {
+                if metadata.is_dir() {
+                    fs::remove_dir(source)?;
+                } else {
+                    fs::remove_file(source)?;
+                }
+                writeln!(
+                    stream,
+                    "Permanently deleted empty {} {}",
+                    kind,
+                    source.display()
+                )?;
+                return Ok(false);
+            }
+        }
+        let layout = grave_layout(cli);
+        let now = chrono::Local::now();
+        let partition = layout.partition(&now);
+        let dest: &Path = &{
+            let root = room_root(graveyard, target_room(source, cli)?.as_deref())?;
+            let dest = layout.grave_path(&root, source, &now);
+            let exists = util::symlink_exists(&dest);
+            match cli.grave_collision {
+                GraveCollision::Overwrite if exists => {
+                    if fs::remove_dir_all(&dest).is_err() {
+                        fs::remove_file(&dest)?;
+                    }
+                    if record.open().is_ok() {
+                        record.log_exhumed_graves(std::slice::from_ref(&dest))?;
+                    }
+                    writeln!(stream, "Replacing {}", dest.display())?;
+                }
+                GraveCollision::Skip if exists => {
+                    writeln!(
+                        stream,
+                        "Skipping {}: {} already exists",
+                        source.display(),
+                        dest.display()
+                    )?;
+                    return Ok(false);
+                }
+                _ => {}
             }
+            // Claim the grave up front, renaming on a conflict, so that a
+            // concurrent bury can't take the same name before the move
+            util::reserve_grave(dest, metadata.is_dir(), cli.collision_suffix)?
         };
 
-        let moved = move_target(source, dest, mode, stream).map_err(|e| {
-            fs::remove_dir_all(dest).ok();
+        // Clean up any partial buries due to permission error
+        let outcome = match cli.truncate_after {
+            Some(keep) => bury_head(source, dest, keep, opts, mode, stream),
+            None => move_target(source, dest, opts, mode, stream),
+        }
+        .map_err(|e| {
+            // After truncating, the grave is the only full copy left
+            if cli.truncate_after.is_none() {
+                fs::remove_dir_all(dest)
+                    .or_else(|_| fs::remove_file(dest))
+                    .ok();
+            }
+            // These already explain what happened
+            if e.kind() == ErrorKind::Interrupted
+                || e.get_ref()
+                    .is_some_and(|inner| inner.is::<util::PathError>())
+            {
+                return e;
+            }
             Error::new(e.kind(), "Failed to bury file")
         })?;
 
-        if moved {
-            // Clean up any partial buries due to permission error
-            record.write_log(source, dest)?;
+        if outcome != MoveOutcome::Moved {
+            // Give back the reserved grave
+            fs::remove_dir(dest).or_else(|_| fs::remove_file(dest)).ok();
+        }
+        match outcome {
+            MoveOutcome::Kept => writeln!(stream, "Skipping {}", source.display())?,
+            // Nothing was buried, so there is nothing to record
+            MoveOutcome::Deleted => {}
+            MoveOutcome::Moved => {
+                let mut extras = Extras::new();
+                if let Some(note) = &cli.note {
+                    extras.insert(record::NOTE_KEY.to_string(), note.clone());
+                }
+                if cli.stage {
+                    extras.insert(record::PENDING_KEY.to_string(), "true".to_string());
+                }
+                if let Some(expire_after) = cli.expire_after {
+                    let expires = chrono::Local::now()
+                        + chrono::Duration::from_std(expire_after).map_err(Error::other)?;
+                    extras.insert(record::EXPIRES_KEY.to_string(), expires.to_rfc3339());
+                }
+                if let Some(partition) = partition {
+                    extras.insert(record::PARTITION_KEY.to_string(), partition);
+                }
+                if let Some(crtime) = metadata.created().ok().and_then(record::format_crtime) {
+                    extras.insert(record::CRTIME_KEY.to_string(), crtime);
+                }
+                #[cfg(unix)]
+                if cli.private_graveyard && !metadata.is_symlink() {
+                    // Closing off a directory is enough to hide everything
+                    // inside it, so its contents keep their own modes
+                    let private = if metadata.is_dir() { 0o700 } else { 0o600 };
+                    fs::set_permissions(dest, fs::Permissions::from_mode(private))?;
+                    extras.insert(
+                        record::MODE_KEY.to_string(),
+                        format!("{:o}", metadata.permissions().mode() & 0o7777),
+                    );
+                }
+                if let Some(device) = util::DeviceNode::of(metadata) {
+                    extras.insert(record::DEVICE_KEY.to_string(), device.to_string());
+                }
+                if let Some(keep) = cli.truncate_after {
+                    extras.insert(record::TRUNCATED_KEY.to_string(), keep.to_string());
+                }
+                let orig = match &cli.name {
+                    Some(name) => resolve_through_parent(cwd, name),
+                    None => source.clone(),
+                };
+                record.write_log(orig, dest, &extras)?;
+                return Ok(true);
+            }
         }
     }
 
+    Ok(false)
+}
+
+/// Prompt to permanently delete a file that is already in the graveyard
+fn unlink_grave(
+    source: &Path,
+    opts: &CopyOptions,
+    mode: &impl util::TestingMode,
+    stream: &mut impl Write,
+) -> Result<(), Error> {
+    writeln!(stream, "{} is already in the graveyard.", source.display())?;
+    if opts
+        .prompts
+        .ask("Permanently unlink it?", false, true, mode, stream)?
+    {
+        if fs::remove_dir_all(source).is_err() {
+            fs::remove_file(source).map_err(|e| {
+                Error::new(e.kind(), format!("Couldn't unlink {}", source.display()))
+            })?;
+        }
+    } else {
+        writeln!(stream, "Skipping {}", source.display())?;
+        // TODO: In the original code, this was a hard return from the entire
+        // method (i.e., `run`). I think it should just be a return from the bury
+        // (meaning a `continue` in the original code's loop). But I'm not sure.
+    }
     Ok(())
 }
 
+/// Copy a whole file into the graveyard, then cut the original down to
+/// its last `keep` bytes so it can keep growing in place
+fn bury_head(
+    source: &Path,
+    dest: &Path,
+    keep: u64,
+    opts: &CopyOptions,
+    mode: &impl util::TestingMode,
+    stream: &mut impl Write,
+) -> Result<MoveOutcome, Error> {
+    fs::create_dir_all(
+        dest.parent()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "Could not get parent of dest!"))?,
+    )?;
+    let outcome = copy_file(source, dest, opts, mode, stream).inspect_err(|_| {
+        fs::remove_file(dest).ok();
+    })?;
+    if outcome != MoveOutcome::Moved {
+        // Leave the original alone unless the copy is safely in place
+        fs::remove_file(dest).ok();
+        return Ok(MoveOutcome::Kept);
+    }
+
+    // Shift the tail to the start of the file, a chunk at a time
+    let mut file = fs::OpenOptions::new().read(true).write(true).open(source)?;
+    let len = file.metadata()?.len();
+    let mut read_pos = len.saturating_sub(keep);
+    let mut write_pos = 0;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        file.seek(SeekFrom::Start(read_pos))?;
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        file.seek(SeekFrom::Start(write_pos))?;
+        file.write_all(&buf[..n])?;
+        read_pos += n as u64;
+        write_pos += n as u64;
+    }
+    file.set_len(write_pos)?;
+    writeln!(
+        stream,
+        "Truncated {} to its last {}",
+        source.display(),
+        util::humanize_bytes(write_pos)
+    )?;
+    Ok(MoveOutcome::Moved)
+}
+
 fn should_we_bury_this(
     target: &Path,
     source: &PathBuf,
     metadata: &Metadata,
+    tail: bool,
+    opts: &CopyOptions,
     mode: &impl util::TestingMode,
     stream: &mut impl Write,
 ) -> Result<bool, Error> {
     if metadata.is_dir() {
-        // Get the size of the directory and all its contents
-        {
-            let num_bytes = get_size(source).map_err(|_| {
-                Error::new(
-                    ErrorKind::Other,
-                    format!("Failed to get size of directory: {}", source.display()),
-                )
-            })?;
-            writeln!(
-                stream,
-                "{}: directory, {} including:",
-                target.to_str().unwrap(),
-                util::humanize_bytes(num_bytes)
-            )?;
-        }
-
-        // Print the first few top-level files in the directory
-        for entry in WalkDir::new(source)
-            .sort_by(|a, b| a.cmp(b))
-            .min_depth(1)
-            .max_depth(1)
-            .into_iter()
-            .filter_map(|entry| entry.ok())
-            .take(FILES_TO_INSPECT)
-        {
-            writeln!(stream, "{}", entry.path().display())?;
+        // Get the size of the directory and its first few top-level
+        // entries in one pass
+        let (num_bytes, entries) = util::dir_summary(
+            WalkDir::new(source).sort_by(|a, b| a.cmp(b)),
+            FILES_TO_INSPECT,
+        )
+        .map_err(|_| {
+            Error::other(format!(
+                "Failed to get size of directory: {}",
+                source.display()
+            ))
+        })?;
+        writeln!(
+            stream,
+            "{}: directory, {} including:",
+            target.to_str().unwrap(),
+            util::humanize_bytes(num_bytes)
+        )?;
+        for entry in entries {
+            writeln!(stream, "{}", entry.display())?;
         }
     } else {
         writeln!(
@@ -231,39 +2356,62 @@ fn should_we_bury_this(
             &target.to_str().unwrap(),
             util::humanize_bytes(metadata.len())
         )?;
-        // Read the file and print the first few lines
-        if let Ok(source_file) = fs::File::open(source) {
-            for line in BufReader::new(source_file)
-                .lines()
-                .take(LINES_TO_INSPECT)
-                .filter_map(|line| line.ok())
-            {
+        // Read the file and print the first or last few lines
+        if let Ok(mut source_file) = fs::File::open(source) {
+            let lines: Vec<String> = if tail {
+                util::tail_lines(&mut source_file, LINES_TO_INSPECT).unwrap_or_default()
+            } else {
+                BufReader::new(source_file)
+                    .lines()
+                    .take(LINES_TO_INSPECT)
+                    .map_while(Result::ok)
+                    .collect()
+            };
+            for line in lines {
                 writeln!(stream, "> {}", line)?;
             }
         } else {
             writeln!(stream, "Error reading {}", source.display())?;
         }
     }
-    util::prompt_yes(
+    opts.prompts.ask(
         format!("Send {} to the graveyard?", target.to_str().unwrap()),
+        true,
+        false,
         mode,
         stream,
     )
 }
 
 /// Move a target to a given destination, copying if necessary.
-/// Returns true if the target was moved, false if it was not (due to
-/// user input)
+/// Returns whether the target was moved, or instead permanently deleted
+/// or kept in place due to user input.
 pub fn move_target(
     target: &Path,
     dest: &Path,
+    opts: &CopyOptions,
     mode: &impl util::TestingMode,
     stream: &mut impl Write,
-) -> Result<bool, Error> {
+) -> Result<MoveOutcome, Error> {
     // Try a simple rename, which will only work within the same mount point.
     // Trying to rename across filesystems will throw errno 18.
-    if util::allow_rename() && fs::rename(target, dest).is_ok() {
-        return Ok(true);
+    if util::allow_rename() && util::retry_transient(|| fs::rename(target, dest)).is_ok() {
+        if opts.drop_acl {
+            // A rename keeps the ACLs, so strip them from the moved tree
+            for entry in WalkDir::new(dest).into_iter().filter_map(|e| e.ok()) {
+                if !entry.file_type().is_symlink() {
+                    if let Err(e) = util::strip_acl(entry.path()) {
+                        writeln!(
+                            stream,
+                            "Warning: couldn't drop ACLs of {} ({})",
+                            entry.path().display(),
+                            e
+                        )?;
+                    }
+                }
+            }
+        }
+        return Ok(MoveOutcome::Moved);
     }
 
     // If that didn't work, then we need to copy and rm.
@@ -273,9 +2421,24 @@ pub fn move_target(
     )?;
 
     if fs::symlink_metadata(target)?.is_dir() {
-        move_dir(target, dest, mode, stream)
+        move_dir(target, dest, opts, mode, stream)
     } else {
-        let moved = copy_file(target, dest, mode, stream).map_err(|e| {
+        // The copy couldn't be removed from a directory we can't write to,
+        // which would leave the file in both places
+        let parent = target.parent().unwrap_or(Path::new("."));
+        let keep_target = !util::can_write(parent);
+        if keep_target && !opts.prompts.force {
+            return Err(util::path_error(
+                ErrorKind::PermissionDenied,
+                target,
+                format!(
+                    "Cannot remove {}: {} is not writable; use --force to copy it and leave the original in place",
+                    target.display(),
+                    parent.display()
+                ),
+            ));
+        }
+        let outcome = copy_file(target, dest, opts, mode, stream).map_err(|e| {
             Error::new(
                 e.kind(),
                 format!(
@@ -285,33 +2448,84 @@ pub fn move_target(
                 ),
             )
         })?;
-        fs::remove_file(target).map_err(|e| {
-            Error::new(
-                e.kind(),
-                format!("Failed to remove file: {}", target.display()),
-            )
-        })?;
-        Ok(moved)
+        if keep_target && outcome == MoveOutcome::Moved {
+            writeln!(
+                stream,
+                "Warning: {} is not writable; left {} in place",
+                parent.display(),
+                target.display()
+            )?;
+        } else if outcome != MoveOutcome::Kept {
+            util::retry_transient(|| fs::remove_file(target)).map_err(|e| {
+                Error::new(
+                    e.kind(),
+                    format!("Failed to remove file: {}", target.display()),
+                )
+            })?;
+        }
+        if outcome == MoveOutcome::Deleted {
+            writeln!(stream, "Permanently deleted {}", target.display())?;
+        }
+        Ok(outcome)
+    }
+}
+
+/// Copy the ACLs of `source` onto its copy at `dest` unless asked not to,
+/// warning rather than failing where the filesystem doesn't support them
+fn carry_acl(
+    source: &Path,
+    dest: &Path,
+    opts: &CopyOptions,
+    stream: &mut impl Write,
+) -> Result<(), Error> {
+    if opts.drop_acl {
+        return Ok(());
+    }
+    if let Err(e) = util::copy_acl(source, dest) {
+        writeln!(
+            stream,
+            "Warning: couldn't preserve ACLs of {} ({})",
+            source.display(),
+            e
+        )?;
     }
+    Ok(())
 }
 
 /// Move a target which is a directory to a given destination, copying if necessary.
-/// Returns true *always*, as the creation of the directory is enough to mark it as successful.
+/// Returns `Moved` once the directory is created, even if some files in it
+/// were permanently deleted instead, or `Kept` if the user cancelled on any
+/// file, in which case the partial copy is removed and the source is
+/// left untouched.
 pub fn move_dir(
     target: &Path,
     dest: &Path,
+    opts: &CopyOptions,
     mode: &impl util::TestingMode,
     stream: &mut impl Write,
-) -> Result<bool, Error> {
+) -> Result<MoveOutcome, Error> {
+    // Permissions and mtimes of the created directories, applied once their
+    // contents are written so that read-only or setgid directories round-trip
+    // and writing children doesn't bump the mtimes back to now
+    let mut dir_metadata = Vec::new();
+
+    let mut copied = 0;
+
     // Walk the source, creating directories and copying files as needed
     for entry in WalkDir::new(target).into_iter().filter_map(|e| e.ok()) {
+        // Nothing has been removed from the source yet, so stopping here
+        // only leaves the partial copy, which the caller cleans up
+        if util::interrupted() {
+            return Err(Error::new(
+                ErrorKind::Interrupted,
+                format!("Interrupted; {} was left in place", target.display()),
+            ));
+        }
         // Path without the top-level directory
-        let orphan = entry.path().strip_prefix(target).map_err(|_| {
-            Error::new(
-                ErrorKind::Other,
-                "Parent directory isn't a prefix of child directories?",
-            )
-        })?;
+        let orphan = entry
+            .path()
+            .strip_prefix(target)
+            .map_err(|_| Error::other("Parent directory isn't a prefix of child directories?"))?;
 
         if entry.file_type().is_dir() {
             fs::create_dir_all(dest.join(orphan)).map_err(|e| {
@@ -324,18 +2538,49 @@ pub fn move_dir(
                     ),
                 )
             })?;
+            if let Ok(metadata) = entry.metadata() {
+                dir_metadata.push((entry.path().to_path_buf(), dest.join(orphan), metadata));
+            }
         } else {
-            copy_file(entry.path(), &dest.join(orphan), mode, stream).map_err(|e| {
-                Error::new(
-                    e.kind(),
-                    format!(
-                        "Failed to copy file from {} to {}",
-                        entry.path().display(),
-                        dest.join(orphan).display()
-                    ),
-                )
-            })?;
+            // Don't count on the walk having created the parent already
+            if let Some(parent) = dest.join(orphan).parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let outcome =
+                copy_file(entry.path(), &dest.join(orphan), opts, mode, stream).map_err(|e| {
+                    Error::new(
+                        e.kind(),
+                        format!(
+                            "Failed to copy file from {} to {}",
+                            entry.path().display(),
+                            dest.join(orphan).display()
+                        ),
+                    )
+                })?;
+            match outcome {
+                MoveOutcome::Moved => {}
+                // Left out of the copy, so it goes away with the source
+                MoveOutcome::Deleted => {
+                    writeln!(stream, "Permanently deleted {}", entry.path().display())?
+                }
+                MoveOutcome::Kept => {
+                    fs::remove_dir_all(dest)?;
+                    return Ok(MoveOutcome::Kept);
+                }
+            }
+            copied += 1;
+            if util::interrupt_after() == Some(copied) {
+                util::interrupt();
+            }
+        }
+    }
+    // Deepest directories first
+    for (source, dir, metadata) in dir_metadata.into_iter().rev() {
+        if let Ok(mtime) = metadata.modified() {
+            util::set_mtime(&dir, mtime)?;
         }
+        fs::set_permissions(&dir, metadata.permissions())?;
+        carry_acl(&source, &dir, opts, stream)?;
     }
     fs::remove_dir_all(target).map_err(|e| {
         Error::new(
@@ -344,15 +2589,16 @@ pub fn move_dir(
         )
     })?;
 
-    Ok(true)
+    Ok(MoveOutcome::Moved)
 }
 
 pub fn copy_file(
     source: &Path,
     dest: &Path,
+    opts: &CopyOptions,
     mode: &impl util::TestingMode,
     stream: &mut impl Write,
-) -> Result<bool, Error> {
+) -> Result<MoveOutcome, Error> {
     let metadata = fs::symlink_metadata(source)?;
     let filetype = metadata.file_type();
 
@@ -363,31 +2609,65 @@ pub fn copy_file(
             source.display(),
             util::humanize_bytes(metadata.len())
         )?;
-        if util::prompt_yes("Permanently delete this file instead?", mode, stream)? {
-            return Ok(false);
+        match util::prompt_big_file(opts.prompts, mode, stream)? {
+            util::BigFileChoice::Move => {}
+            util::BigFileChoice::Delete => return Ok(MoveOutcome::Deleted),
+            util::BigFileChoice::Cancel => return Ok(MoveOutcome::Kept),
         }
     }
 
     if filetype.is_file() {
-        fs::copy(source, dest)?;
-        return Ok(true);
+        match opts.throttle {
+            // The command line never gives 0, which would never finish
+            Some(bytes_per_sec) if bytes_per_sec > 0 => {
+                throttled_copy(source, dest, bytes_per_sec)?
+            }
+            _ => {
+                util::retry_transient(|| fs::copy(source, dest))?;
+            }
+        }
+        if let Ok(mtime) = metadata.modified() {
+            util::set_mtime(dest, mtime)?;
+        }
+        // Re-apply the full mode, as the copy may drop setuid/setgid/sticky bits
+        fs::set_permissions(dest, metadata.permissions())?;
+        carry_acl(source, dest, opts, stream)?;
+        return Ok(MoveOutcome::Moved);
+    }
+
+    if let Some(device) = util::DeviceNode::of(&metadata) {
+        clear_dest(dest)?;
+        if let Err(e) = device.create(dest) {
+            writeln!(
+                stream,
+                "Warning: couldn't recreate device node {} ({}); leaving a marker file instead",
+                dest.display(),
+                e
+            )?;
+            fs::write(dest, format!("{}\n", device))?;
+        }
+        return Ok(MoveOutcome::Moved);
     }
 
     #[cfg(unix)]
     if filetype.is_fifo() {
+        clear_dest(dest)?;
         let metadata_mode = metadata.permissions().mode();
         std::process::Command::new("mkfifo")
             .arg(dest)
             .arg("-m")
             .arg(metadata_mode.to_string())
             .output()?;
-        return Ok(true);
+        return Ok(MoveOutcome::Moved);
     }
 
     if filetype.is_symlink() {
+        // Recreate the link from its contents alone, never following it,
+        // so dangling links are buried as they are
         let target = fs::read_link(source)?;
+        clear_dest(dest)?;
         symlink(target, dest)?;
-        return Ok(true);
+        return Ok(MoveOutcome::Moved);
     }
 
     match fs::copy(source, dest) {
@@ -399,14 +2679,90 @@ pub fn copy_file(
                 source.display()
             )?;
 
-            if util::prompt_yes("Permanently delete the file?", mode, stream)? {
-                Ok(false)
+            if opts
+                .prompts
+                .ask("Permanently delete the file?", false, true, mode, stream)?
+            {
+                Ok(MoveOutcome::Deleted)
             } else {
                 Err(e)
             }
         }
-        Ok(_) => Ok(true),
+        Ok(_) => Ok(MoveOutcome::Moved),
+    }
+}
+
+/// Remove a reserved placeholder or a leftover from an earlier partial
+/// bury at `dest`, like `fs::copy` would overwrite it. Directories are
+/// left alone.
+fn clear_dest(dest: &Path) -> Result<(), Error> {
+    if fs::symlink_metadata(dest).is_ok_and(|m| !m.is_dir()) {
+        fs::remove_file(dest)?;
+    }
+    Ok(())
+}
+
+/// Name of the record file inside the graveyard: `--record-name`, then
+/// `$RIP_RECORD`, then the default `.record`.
+pub fn get_record_name(record_name: Option<String>) -> Result<String, Error> {
+    let name = record_name
+        .or_else(|| env::var("RIP_RECORD").ok())
+        .unwrap_or_else(|| record::RECORD.to_string());
+    if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid record name: {}", name),
+        ));
+    }
+    Ok(name)
+}
+
+/// The graveyard rip would use for these arguments and the current
+/// environment: `--graveyard`, then `$RIP_GRAVEYARD`, then
+/// `$XDG_DATA_HOME/graveyard`, then `$TMPDIR/graveyard-$USER`.
+pub fn resolve_graveyard(args: &Args) -> PathBuf {
+    let graveyard = get_graveyard(args.graveyard.clone());
+    if args.graveyard_per_host {
+        graveyard.join(util::hostname())
+    } else {
+        graveyard
+    }
+}
+
+/// Copy a regular file in chunks, sleeping as needed so the average rate
+/// stays below `bytes_per_sec`.
+fn throttled_copy(source: &Path, dest: &Path, bytes_per_sec: u64) -> Result<(), Error> {
+    let mut reader = fs::File::open(source)?;
+    let mut writer = fs::File::create(dest)?;
+    let mut buffer = vec![0; COPY_CHUNK_SIZE];
+    let start = Instant::now();
+    let mut copied: u64 = 0;
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        writer.write_all(&buffer[..n])?;
+        copied += n as u64;
+        let target_elapsed = Duration::from_secs_f64(copied as f64 / bytes_per_sec as f64);
+        if let Some(remaining) = target_elapsed.checked_sub(start.elapsed()) {
+            std::thread::sleep(remaining);
+        }
     }
+    Ok(())
+}
+
+/// `graveyard` directories under each entry of `XDG_DATA_DIRS`. These are
+/// searched by seance and unbury, but never buried into.
+pub fn data_dirs_graveyards() -> Vec<PathBuf> {
+    env::var_os("XDG_DATA_DIRS")
+        .map(|dirs| {
+            env::split_paths(&dirs)
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .map(|dir| dir.join("graveyard"))
+                .collect()
+        })
+        .unwrap_or_default()
 }
 
 pub fn get_graveyard(graveyard: Option<PathBuf>) -> PathBuf {
@@ -3,7 +3,7 @@ use std::env;
 use std::io;
 use std::process::ExitCode;
 
-use rip2::args::Commands;
+use rip2::args::{Commands, ErrorFormat};
 use rip2::{args, completions, util};
 
 fn main() -> ExitCode {
@@ -12,15 +12,16 @@ fn main() -> ExitCode {
     let cli = args::Args::from_arg_matches(&cmd.get_matches()).unwrap();
 
     match &cli.command {
-        Some(Commands::Completions { shell }) => {
-            let result = completions::generate_shell_completions(shell, &mut io::stdout());
-            if result.is_err() {
-                eprintln!("{}", result.unwrap_err());
+        Some(Commands::Completions { shell, output }) => {
+            let result =
+                completions::write_shell_completions(shell, output.as_deref(), &mut io::stdout());
+            if let Err(e) = result {
+                eprintln!("{}", e);
                 return ExitCode::FAILURE;
             }
         }
         Some(Commands::Graveyard { seance }) => {
-            let graveyard = rip2::get_graveyard(None);
+            let graveyard = rip2::resolve_graveyard(&cli);
             if *seance {
                 let cwd = env::current_dir().expect("Failed to get current directory");
                 let gravepath = util::join_absolute(
@@ -35,6 +36,7 @@ fn main() -> ExitCode {
         None => {
             let mut stream = io::stdout();
             let mode = util::ProductionMode;
+            let error_format = cli.error_format;
 
             ////////////////////////////////////////////////////////////
             // Main code ///////////////////////////////////////////////
@@ -42,7 +44,13 @@ fn main() -> ExitCode {
             ////////////////////////////////////////////////////////////
 
             if let Err(ref e) = result {
-                println!("Exception: {}", e);
+                if let Some(code) = util::empty_seance_code(e) {
+                    return ExitCode::from(code);
+                }
+                match error_format {
+                    ErrorFormat::Human => println!("Exception: {}", e),
+                    ErrorFormat::Json => eprintln!("{}", util::error_json(e)),
+                }
                 return ExitCode::FAILURE;
             }
         }
@@ -1,56 +1,266 @@
-use chrono::Local;
+use chrono::{DateTime, FixedOffset, Local};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 use std::io::{BufRead, BufReader, Error, ErrorKind, Write};
 use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{fs, io};
 
 use crate::util;
 
 pub const RECORD: &str = ".record";
 
+/// First line of every record file
+const HEADER: &str = "Time\tOriginal\tDestination\n";
+
+/// Suffix of the file next to the record that entries for vanished
+/// graves are rotated out to
+pub const HISTORY_SUFFIX: &str = ".old";
+
+/// Suffix of the lockfile next to the record that writers take turns on
+pub const LOCK_SUFFIX: &str = ".lock";
+
+/// Suffix of the file next to the record holding its size when it was
+/// last checked for rotation
+const ROTATED_SUFFIX: &str = ".rotated";
+
+/// Key of the extra record column holding a user-supplied note
+pub const NOTE_KEY: &str = "note";
+
+/// Key of the extra record column marking a staged grave awaiting
+/// `--commit` or `--rollback`
+pub const PENDING_KEY: &str = "pending";
+
+/// Key of the extra record column naming the dated subdirectory a
+/// grave was buried under with `--date-partition`
+pub const PARTITION_KEY: &str = "partition";
+
+/// Key of the extra record column holding the birth time of the
+/// buried file, as `seconds.nanoseconds` since the epoch
+pub const CRTIME_KEY: &str = "crtime";
+
+/// Key of the extra record column holding the RFC 3339 time after which
+/// `--expire` permanently deletes the grave
+pub const EXPIRES_KEY: &str = "expires";
+
+/// Key of the extra record column marking a grave buried with
+/// `--truncate-after`, holding the number of bytes left in place
+pub const TRUNCATED_KEY: &str = "truncated";
+
+/// Key of the extra record column describing a buried block or character
+/// device node, so it can be recreated on restore
+pub const DEVICE_KEY: &str = "device";
+
+/// Key of the extra record column holding the original permission bits
+/// (in octal) of a grave masked by `--private-graveyard`
+pub const MODE_KEY: &str = "mode";
+
+/// Optional `key=value` columns stored after the destination column
+pub type Extras = BTreeMap<String, String>;
+
 #[derive(Debug)]
 pub struct RecordItem {
     pub time: String,
     pub orig: PathBuf,
     pub dest: PathBuf,
+    pub extras: Extras,
 }
 
 impl RecordItem {
     /// Parse a line in the record into a `RecordItem`
     pub fn new(line: &str) -> RecordItem {
+        RecordItem::parse(line).expect("Bad format: record line")
+    }
+
+    /// Parse a line in the record, returning `None` if it is malformed
+    pub fn parse(line: &str) -> Option<RecordItem> {
         let mut tokens = line.split('\t');
-        let time = tokens.next().expect("Bad format: column 1").to_string();
-        let orig = tokens.next().expect("Bad format: column 2").to_string();
-        let dest = tokens.next().expect("Bad format: column 3").to_string();
-        RecordItem {
+        let time = tokens.next()?.to_string();
+        let orig = tokens.next()?.to_string();
+        let dest = tokens.next()?.to_string();
+        if time.is_empty() || orig.is_empty() || dest.is_empty() {
+            return None;
+        }
+        // Any further columns are optional metadata
+        let extras = tokens
+            .filter_map(|token| token.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        Some(RecordItem {
             time,
             orig: PathBuf::from(orig),
             dest: PathBuf::from(dest),
+            extras,
+        })
+    }
+
+    pub fn note(&self) -> Option<&str> {
+        self.extras.get(NOTE_KEY).map(String::as_str)
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.extras.contains_key(PENDING_KEY)
+    }
+
+    pub fn partition(&self) -> Option<&str> {
+        self.extras.get(PARTITION_KEY).map(String::as_str)
+    }
+
+    pub fn crtime(&self) -> Option<SystemTime> {
+        let (secs, nanos) = self.extras.get(CRTIME_KEY)?.split_once('.')?;
+        Some(UNIX_EPOCH + Duration::new(secs.parse().ok()?, nanos.parse().ok()?))
+    }
+
+    /// Whether the grave's expiry, if any, is at or before `now`
+    pub fn is_expired(&self, now: DateTime<FixedOffset>) -> bool {
+        self.extras
+            .get(EXPIRES_KEY)
+            .and_then(|expires| DateTime::parse_from_rfc3339(expires).ok())
+            .is_some_and(|expires| expires <= now)
+    }
+
+    /// Short id of the grave, stable for as long as the entry is in the
+    /// record: an FNV-1a hash of the deletion time and grave path
+    pub fn id(&self) -> String {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let dest = self.dest.to_string_lossy();
+        for byte in self.time.bytes().chain([b'\t']).chain(dest.bytes()) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x100000001b3);
         }
+        format!("{:08x}", hash as u32 ^ (hash >> 32) as u32)
+    }
+
+    /// The permission bits to give back on restore, if they were masked
+    pub fn mode(&self) -> Option<u32> {
+        u32::from_str_radix(self.extras.get(MODE_KEY)?, 8).ok()
+    }
+
+    /// The device node the grave stands for, if one was buried
+    pub fn device(&self) -> Option<util::DeviceNode> {
+        util::DeviceNode::parse(self.extras.get(DEVICE_KEY)?)
     }
 }
 
+impl fmt::Display for RecordItem {
+    /// Format the item as a line of the record, without the trailing newline
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}",
+            self.time,
+            self.orig.display(),
+            self.dest.display()
+        )?;
+        for (key, value) in &self.extras {
+            write!(f, "\t{}={}", key, sanitize_extra(value))?;
+        }
+        Ok(())
+    }
+}
+
+/// Format a time for the `crtime` record column
+pub fn format_crtime(time: SystemTime) -> Option<String> {
+    let since_epoch = time.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!(
+        "{}.{:09}",
+        since_epoch.as_secs(),
+        since_epoch.subsec_nanos()
+    ))
+}
+
+/// Make a value safe to store in a single record column
+pub fn sanitize_extra(value: &str) -> String {
+    value.replace(['\t', '\n', '\r'], " ")
+}
+
 #[derive(Debug)]
+/// Holds a lock on a record, released when dropped along with the file
+struct RecordLock {
+    _file: fs::File,
+}
+
 pub struct Record {
     path: PathBuf,
+    /// Whether the graveyard's filesystem ignores case, probed on first use
+    case_insensitive: OnceLock<bool>,
+    /// The grave of each `(dev, ino)` pair, gathered by `find_file_id`
+    grave_ids: Mutex<Option<HashMap<(u64, u64), PathBuf>>>,
+}
+
+/// The `(dev, ino)` pair of a grave, unless it is a directory, which
+/// can't be hardlinked
+fn grave_file_id(grave: &Path) -> Option<(u64, u64)> {
+    fs::symlink_metadata(grave)
+        .ok()
+        .filter(|metadata| !metadata.is_dir())
+        .and_then(|metadata| util::file_id(&metadata))
 }
 
 impl Record {
-    pub fn new(graveyard: &Path) -> Record {
-        let path = graveyard.join(RECORD);
-        // Create the record file if it doesn't exist
-        if !path.exists() {
-            // Write a header to the record file
-            let mut record_file = fs::OpenOptions::new()
-                .truncate(true)
-                .create(true)
-                .write(true)
-                .open(&path)
-                .expect("Failed to open record file");
-            record_file
-                .write_all(b"Time\tOriginal\tDestination\n")
-                .expect("Failed to write header to record file");
+    /// Open the record called `name` in the graveyard, normally `RECORD`.
+    /// The file itself is only created by `create` or the first entry
+    /// written to it, so reading a graveyard never writes to it.
+    pub fn new(graveyard: &Path, name: &str) -> Record {
+        Record {
+            path: graveyard.join(name),
+            case_insensitive: OnceLock::new(),
+            grave_ids: Mutex::new(None),
+        }
+    }
+
+    /// Whether grave paths should be matched ignoring case
+    fn case_insensitive(&self) -> bool {
+        *self
+            .case_insensitive
+            .get_or_init(|| self.path.parent().is_some_and(util::is_case_insensitive))
+    }
+
+    /// Path next to the record with `suffix` appended to its name
+    fn sibling(&self, suffix: &str) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(suffix);
+        PathBuf::from(path)
+    }
+
+    /// Lock the record until the returned guard is dropped: shared for
+    /// appending, which O_APPEND keeps atomic, and exclusive for rewriting
+    /// it in place, so no append is written to a copy about to be replaced
+    fn lock(&self, exclusive: bool) -> Result<RecordLock, Error> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.sibling(LOCK_SUFFIX))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::io::AsRawFd;
+            let operation = if exclusive {
+                libc::LOCK_EX
+            } else {
+                libc::LOCK_SH
+            };
+            // SAFETY: flock only uses the descriptor, which outlives the call
+            while unsafe { libc::flock(file.as_raw_fd(), operation) } != 0 {
+                let e = Error::last_os_error();
+                if e.kind() != ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
         }
-        Record { path }
+        #[cfg(not(unix))]
+        let _ = exclusive;
+        Ok(RecordLock { _file: file })
+    }
+
+    /// Delete the record along with the files kept next to it, other than
+    /// its history
+    pub fn remove(&self) -> Result<(), Error> {
+        for suffix in [LOCK_SUFFIX, ROTATED_SUFFIX] {
+            fs::remove_file(self.sibling(suffix)).ok();
+        }
+        fs::remove_file(&self.path)
     }
 
     pub fn open(&self) -> Result<fs::File, Error> {
@@ -58,28 +268,46 @@ impl Record {
             .map_err(|_| Error::new(ErrorKind::NotFound, "Failed to read record!"))
     }
 
+    /// The whole record, or just its header if nothing was buried yet
+    fn contents(&self) -> Result<String, Error> {
+        match fs::read_to_string(&self.path) {
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(HEADER.to_string()),
+            result => result,
+        }
+    }
+
+    /// The lines of the record after its header, if there is one
+    fn lines(&self) -> Result<impl Iterator<Item = String>, Error> {
+        let file = match fs::File::open(&self.path) {
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            file => Some(file?),
+        };
+        Ok(file
+            .into_iter()
+            .flat_map(|file| BufReader::new(file).lines().skip(1))
+            .map_while(Result::ok))
+    }
+
     /// Return the path in the graveyard of the last file to be buried.
     /// As a side effect, any valid last files that are found in the record but
     /// not on the filesystem are removed from the record.
     pub fn get_last_bury(&self) -> Result<PathBuf, Error> {
-        // record: impl AsRef<Path>
-        let record_file = self.open()?;
-        let contents = {
-            let path_f = PathBuf::from(&self.path);
-            fs::read_to_string(path_f)?
-        };
+        // Read from the end, so only the newest entries are looked at
+        let mut lines = util::ReverseLines::new(self.open()?)?.peekable();
 
-        // This will be None if there is nothing, or Some
-        // if there is items in the vector
         let mut graves_to_exhume: Vec<PathBuf> = Vec::new();
-        let mut lines = contents.lines();
-        lines.next();
-        for entry in lines.rev().map(RecordItem::new) {
+        while let Some(line) = lines.next() {
+            let line = line?;
+            // The first line of the record is the header
+            if lines.peek().is_none() {
+                break;
+            }
+            let entry = RecordItem::new(&line);
             // Check that the file is still in the graveyard.
             // If it is, return the corresponding line.
             if util::symlink_exists(&entry.dest) {
                 if !graves_to_exhume.is_empty() {
-                    self.delete_lines(record_file, &graves_to_exhume)?;
+                    self.delete_lines(&graves_to_exhume)?;
                 }
                 return Ok(entry.dest);
             } else {
@@ -89,40 +317,28 @@ impl Record {
         }
 
         if !graves_to_exhume.is_empty() {
-            self.delete_lines(record_file, &graves_to_exhume)?;
+            self.delete_lines(&graves_to_exhume)?;
         }
         Err(Error::new(ErrorKind::NotFound, "No files in graveyard"))
     }
 
     /// Takes a vector of grave paths and removes the respective lines from the record
-    fn delete_lines(&self, record_file: fs::File, graves: &[PathBuf]) -> Result<(), Error> {
-        let record_path = &self.path;
-        // Get the lines to write back to the record, which is every line except
-        // the ones matching the exhumed graves.  Store them in a vector
-        // since we'll be overwriting the record in-place.
-        let mut reader = BufReader::new(record_file).lines();
-        reader.next();
-        let lines_to_write: Vec<String> = reader
-            .map_while(Result::ok)
-            .filter(|line| !graves.iter().any(|y| *y == RecordItem::new(line).dest))
-            .collect();
-        let mut mutable_record_file = fs::File::create(record_path)?;
-        for line in lines_to_write {
-            writeln!(mutable_record_file, "{}", line)?;
-        }
-        Ok(())
+    fn delete_lines(&self, graves: &[PathBuf]) -> Result<(), Error> {
+        self.rewrite_lines(|line| match RecordItem::parse(line) {
+            Some(item) if graves.contains(&item.dest) => None,
+            _ => Some(line.to_string()),
+        })
     }
 
+    /// Remove the lines of exhumed graves from the record. The rewrite
+    /// holds the exclusive lock, so lines appended meanwhile are kept
     pub fn log_exhumed_graves(&self, graves_to_exhume: &[PathBuf]) -> Result<(), Error> {
-        // Reopen the record and then delete lines corresponding to exhumed graves
-        let record_file = self.open()?;
-        self.delete_lines(record_file, graves_to_exhume)
-            .map_err(|e| {
-                Error::new(
-                    e.kind(),
-                    format!("Failed to remove unburied files from record: {}", e),
-                )
-            })
+        self.delete_lines(graves_to_exhume).map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!("Failed to remove unburied files from record: {}", e),
+            )
+        })
     }
 
     /// Takes a vector of grave paths and returns the respective lines in the record
@@ -130,12 +346,13 @@ impl Record {
         &'a self,
         graves: &'a [PathBuf],
     ) -> impl Iterator<Item = String> + 'a {
-        let record_file = self.open().unwrap();
-        let mut reader = BufReader::new(record_file).lines();
-        reader.next();
-        reader
-            .map_while(Result::ok)
-            .filter(move |line| graves.iter().any(|y| *y == RecordItem::new(line).dest))
+        let case_insensitive = self.case_insensitive();
+        self.lines().into_iter().flatten().filter(move |line| {
+            let dest = RecordItem::new(line).dest;
+            graves
+                .iter()
+                .any(|y| util::paths_match(y, &dest, case_insensitive))
+        })
     }
 
     /// Returns an iterator over all graves in the record that are under gravepath
@@ -143,35 +360,294 @@ impl Record {
         &'a self,
         gravepath: &'a PathBuf,
     ) -> io::Result<impl Iterator<Item = RecordItem> + 'a> {
-        let record_file = self.open()?;
-        let mut reader = BufReader::new(record_file).lines();
-        reader.next();
-        Ok(reader
-            .map_while(Result::ok)
+        Ok(self
+            .lines()?
             .map(|line| RecordItem::new(&line))
             .filter(move |record_item| record_item.dest.starts_with(gravepath)))
     }
 
+    /// Replace every line of the record with the output of `f`, dropping
+    /// lines for which it returns `None`. The new record is written to a
+    /// temporary file and renamed into place so it is never half-written.
+    pub fn rewrite_lines(&self, f: impl FnMut(&str) -> Option<String>) -> Result<(), Error> {
+        let _lock = self.lock(true)?;
+        self.rewrite_lines_locked(f)
+    }
+
+    /// `rewrite_lines` for a caller already holding the exclusive lock
+    fn rewrite_lines_locked(&self, mut f: impl FnMut(&str) -> Option<String>) -> Result<(), Error> {
+        let contents = self.contents()?;
+        let mut lines = contents.lines();
+        let mut new_contents = String::new();
+        if let Some(header) = lines.next() {
+            new_contents.push_str(header);
+            new_contents.push('\n');
+        }
+        for line in lines.filter_map(&mut f) {
+            new_contents.push_str(&line);
+            new_contents.push('\n');
+        }
+        // Named after the whole record name and the process, so records
+        // like `work.log` and `work.txt` don't share a temporary file
+        let tmp_path = self.sibling(&format!(".{}.tmp", std::process::id()));
+        fs::write(&tmp_path, new_contents)?;
+        fs::rename(&tmp_path, &self.path)
+    }
+
+    /// Point every grave under `old_graveyard` at the same place
+    /// under `new_graveyard`
+    pub fn rebase_dests(&self, old_graveyard: &Path, new_graveyard: &Path) -> Result<(), Error> {
+        self.rewrite_lines(|line| match RecordItem::parse(line) {
+            Some(mut item) => {
+                if let Ok(rest) = item.dest.strip_prefix(old_graveyard) {
+                    item.dest = new_graveyard.join(rest);
+                }
+                Some(item.to_string())
+            }
+            None => Some(line.to_string()),
+        })
+    }
+
+    /// The first grave that is the very file with the given `(dev, ino)`
+    /// pair, e.g. through a hardlink. The graves are looked at once, on
+    /// the first call, and those appended afterwards are added as they go.
+    pub fn find_file_id(&self, file_id: (u64, u64)) -> Result<Option<PathBuf>, Error> {
+        let mut grave_ids = self.grave_ids.lock().unwrap_or_else(|e| e.into_inner());
+        if grave_ids.is_none() {
+            let mut ids = HashMap::new();
+            for item in self.entries()? {
+                if let Some(id) = grave_file_id(&item.dest) {
+                    ids.entry(id).or_insert(item.dest);
+                }
+            }
+            *grave_ids = Some(ids);
+        }
+        Ok(grave_ids
+            .as_ref()
+            .and_then(|ids| ids.get(&file_id))
+            .filter(|grave| grave_file_id(grave) == Some(file_id))
+            .cloned())
+    }
+
+    /// Every well-formed entry in the record
+    pub fn entries(&self) -> Result<Vec<RecordItem>, Error> {
+        let contents = self.contents()?;
+        Ok(contents
+            .lines()
+            .skip(1)
+            .filter_map(RecordItem::parse)
+            .collect())
+    }
+
+    /// The entry with the given grave id, as printed by seance
+    pub fn find_id(&self, id: &str) -> Result<Option<RecordItem>, Error> {
+        let contents = self.contents()?;
+        Ok(contents
+            .lines()
+            .skip(1)
+            .filter_map(RecordItem::parse)
+            .find(|item| item.id() == id))
+    }
+
+    /// Grave paths of every entry whose expiry is at or before `now`
+    pub fn expired_graves(&self, now: DateTime<FixedOffset>) -> Result<Vec<PathBuf>, Error> {
+        let contents = self.contents()?;
+        Ok(contents
+            .lines()
+            .skip(1)
+            .filter_map(RecordItem::parse)
+            .filter(|item| item.is_expired(now))
+            .map(|item| item.dest)
+            .collect())
+    }
+
+    /// Grave paths of every staged entry
+    pub fn pending_graves(&self) -> Result<Vec<PathBuf>, Error> {
+        let contents = self.contents()?;
+        Ok(contents
+            .lines()
+            .skip(1)
+            .filter_map(RecordItem::parse)
+            .filter(RecordItem::is_pending)
+            .map(|item| item.dest)
+            .collect())
+    }
+
+    /// Every entry buried at or after `since`
+    pub fn graves_since(&self, since: DateTime<FixedOffset>) -> Result<Vec<RecordItem>, Error> {
+        let contents = self.contents()?;
+        Ok(contents
+            .lines()
+            .skip(1)
+            .filter_map(RecordItem::parse)
+            .filter(|item| DateTime::parse_from_rfc3339(&item.time).is_ok_and(|time| time >= since))
+            .collect())
+    }
+
+    /// Clear the pending marker from every staged entry, returning how
+    /// many were committed
+    pub fn commit_pending(&self) -> Result<usize, Error> {
+        let mut count = 0;
+        self.rewrite_lines(|line| match RecordItem::parse(line) {
+            Some(mut item) if item.is_pending() => {
+                item.extras.remove(PENDING_KEY);
+                count += 1;
+                Some(item.to_string())
+            }
+            _ => Some(line.to_string()),
+        })?;
+        Ok(count)
+    }
+
+    /// Drop the entries whose original or grave path is one of `paths`,
+    /// returning the dropped entries. The graves themselves are untouched.
+    pub fn forget(&self, paths: &[PathBuf]) -> Result<Vec<RecordItem>, Error> {
+        let case_insensitive = self.case_insensitive();
+        let mut forgotten = Vec::new();
+        self.rewrite_lines(|line| match RecordItem::parse(line) {
+            Some(item)
+                if paths.iter().any(|path| {
+                    util::paths_match(path, &item.orig, case_insensitive)
+                        || util::paths_match(path, &item.dest, case_insensitive)
+                }) =>
+            {
+                forgotten.push(item);
+                None
+            }
+            _ => Some(line.to_string()),
+        })?;
+        Ok(forgotten)
+    }
+
+    /// Once the record holds more than `max_lines` entries, move the
+    /// entries whose graves no longer exist to the history file next to
+    /// it, keeping only live ones. Returns how many entries were moved.
+    ///
+    /// The graves are only checked once the record has grown by a tenth
+    /// since the last check, so burying into a large record stays cheap.
+    pub fn rotate(&self, max_lines: usize) -> Result<usize, Error> {
+        let _lock = self.lock(true)?;
+        let rotated_path = self.sibling(ROTATED_SUFFIX);
+        let len = match fs::metadata(&self.path) {
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+            metadata => metadata?.len(),
+        };
+        let checked_len: u64 = fs::read_to_string(&rotated_path)
+            .ok()
+            .and_then(|contents| contents.trim().parse().ok())
+            .unwrap_or(0);
+        if checked_len > 0 && len >= checked_len && len < checked_len + checked_len / 10 {
+            return Ok(0);
+        }
+        let moved = self.rotate_locked(max_lines)?;
+        fs::write(&rotated_path, fs::metadata(&self.path)?.len().to_string())?;
+        Ok(moved)
+    }
+
+    fn rotate_locked(&self, max_lines: usize) -> Result<usize, Error> {
+        let contents = self.contents()?;
+        if contents.lines().skip(1).count() <= max_lines {
+            return Ok(0);
+        }
+        let dead: HashSet<&str> = contents
+            .lines()
+            .skip(1)
+            .filter(|line| {
+                RecordItem::parse(line).is_some_and(|item| !util::symlink_exists(&item.dest))
+            })
+            .collect();
+        if dead.is_empty() {
+            return Ok(0);
+        }
+
+        // Archive before dropping, so a failure can't lose entries
+        let mut history = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.sibling(HISTORY_SUFFIX))?;
+        let mut archived = String::new();
+        if history.metadata()?.len() == 0 {
+            archived.push_str(HEADER);
+        }
+        for line in contents.lines().skip(1).filter(|line| dead.contains(line)) {
+            archived.push_str(line);
+            archived.push('\n');
+        }
+        history.write_all(archived.as_bytes())?;
+
+        self.rewrite_lines_locked(|line| (!dead.contains(line)).then(|| line.to_string()))?;
+        Ok(dead.len())
+    }
+
     /// Write deletion history to record
-    pub fn write_log(&self, source: impl AsRef<Path>, dest: impl AsRef<Path>) -> io::Result<()> {
-        let (source, dest) = (source.as_ref(), dest.as_ref());
+    pub fn write_log(
+        &self,
+        source: impl AsRef<Path>,
+        dest: impl AsRef<Path>,
+        extras: &Extras,
+    ) -> io::Result<()> {
+        self.append(&RecordItem {
+            time: Local::now().to_rfc3339(),
+            orig: source.as_ref().to_path_buf(),
+            dest: dest.as_ref().to_path_buf(),
+            extras: extras.clone(),
+        })
+    }
+
+    /// Create the record with its header, unless it exists already. The
+    /// header is written beside it and linked into place, so no append
+    /// can land in a record that lacks one.
+    pub fn create(&self) -> io::Result<()> {
+        if self.path.exists() {
+            return Ok(());
+        }
+        let tmp_path = self.sibling(&format!(".{}.new", std::process::id()));
+        fs::write(&tmp_path, HEADER)?;
+        let linked = fs::hard_link(&tmp_path, &self.path);
+        fs::remove_file(&tmp_path)?;
+        match linked {
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(()),
+            // Some filesystems have no hardlinks
+            Err(_) => match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&self.path)
+            {
+                Ok(mut record_file) => record_file.write_all(HEADER.as_bytes()),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(()),
+                Err(e) => Err(e),
+            },
+            Ok(()) => Ok(()),
+        }
+    }
+
+    /// Add an existing entry to the end of the record, keeping its time
+    pub fn append(&self, item: &RecordItem) -> io::Result<()> {
+        // Format the whole line up front and hand it to a single write on
+        // an O_APPEND file, so concurrent appends can't interleave
+        let line = format!("{}\n", item);
+        self.create()?;
+        let _lock = self.lock(false)?;
         let mut record_file = fs::OpenOptions::new()
             .create(true)
             .append(true)
             .open(&self.path)?;
-        writeln!(
-            record_file,
-            "{}\t{}\t{}",
-            Local::now().to_rfc3339(),
-            source.display(),
-            dest.display()
-        )
-        .map_err(|e| {
+        record_file.write_all(line.as_bytes()).map_err(|e| {
             Error::new(
                 e.kind(),
                 format!("Failed to write record at {}", &self.path.display()),
             )
         })?;
+        if let Some(ids) = self
+            .grave_ids
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_mut()
+        {
+            if let Some(id) = grave_file_id(&item.dest) {
+                ids.entry(id).or_insert_with(|| item.dest.clone());
+            }
+        }
 
         Ok(())
     }
@@ -1,11 +1,101 @@
+use std::cell::RefCell;
 use std::collections::hash_map::DefaultHasher;
 use std::env;
+use std::fmt;
 use std::fs;
-use std::hash::{Hash, Hasher};
-use std::io::{self, BufReader, Error, Read, Write};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::io::{self, BufRead, BufReader, Error, Read, Seek, SeekFrom, Write};
 use std::path::Prefix::Disk;
 use std::path::{Component, Path, PathBuf};
 use std::str::from_utf8;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::args::{CollisionSuffix, GraveLayout};
+
+/// An error message about a specific path, carried inside an
+/// `io::Error` so callers can recover the path.
+#[derive(Debug)]
+pub struct PathError {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for PathError {}
+
+pub fn path_error(kind: io::ErrorKind, path: impl AsRef<Path>, message: String) -> Error {
+    Error::new(
+        kind,
+        PathError {
+            path: path.as_ref().to_path_buf(),
+            message,
+        },
+    )
+}
+
+/// Carried inside an `io::Error` when a seance matched no graves and
+/// `--exit-code-on-empty` asked for a distinct exit status
+#[derive(Debug)]
+pub struct EmptySeance {
+    pub code: u8,
+}
+
+impl fmt::Display for EmptySeance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "No graves found")
+    }
+}
+
+impl std::error::Error for EmptySeance {}
+
+/// The exit status requested for an empty seance, if that's what `e` is
+pub fn empty_seance_code(e: &Error) -> Option<u8> {
+    e.get_ref()
+        .and_then(|inner| inner.downcast_ref::<EmptySeance>())
+        .map(|empty| empty.code)
+}
+
+/// Quote a string as a JSON string literal
+pub fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Render an error as a single JSON object with its message, kind,
+/// and the path it concerns (if known)
+pub fn error_json(e: &Error) -> String {
+    let path = e
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<PathError>())
+        .map_or("null".to_string(), |p| {
+            json_string(&p.path.display().to_string())
+        });
+    format!(
+        "{{\"error\":{},\"kind\":{},\"path\":{}}}",
+        json_string(&e.to_string()),
+        json_string(&format!("{:?}", e.kind())),
+        path
+    )
+}
 
 fn hash_component(c: &Component) -> String {
     let mut hasher = DefaultHasher::new();
@@ -44,10 +134,724 @@ pub fn join_absolute<A: AsRef<Path>, B: AsRef<Path>>(left: A, right: B) -> PathB
     result
 }
 
+/// Create a new file in the temporary directory that only the owner can
+/// read or write. A fresh name is tried whenever one is already taken, so
+/// a file or symlink planted there by someone else is never opened.
+pub fn create_private_temp(prefix: &str, suffix: &str) -> Result<(PathBuf, fs::File), Error> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.subsec_nanos());
+    for attempt in 0u32..100 {
+        let path = env::temp_dir().join(format!(
+            "{}-{}-{:08x}{}",
+            prefix,
+            std::process::id(),
+            seed.wrapping_add(attempt.wrapping_mul(0x9e37_79b9)),
+            suffix
+        ));
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(Error::new(
+        io::ErrorKind::AlreadyExists,
+        "Couldn't find a free name for a temporary file",
+    ))
+}
+
+/// Check that files can be created inside `dir` by writing and removing
+/// a small probe file.
+pub fn check_writable(dir: &Path) -> Result<(), Error> {
+    let probe = dir.join(format!(".rip-probe-{}", std::process::id()));
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+        .and_then(|_| fs::remove_file(&probe))
+        .map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!(
+                    "Graveyard {} is not writable ({}); choose another location with --graveyard",
+                    dir.display(),
+                    e
+                ),
+            )
+        })
+}
+
+/// Whether the filesystem holding `dir` treats names differing only in
+/// case as the same file, found by creating a lowercase probe file and
+/// looking it up in uppercase.
+pub fn is_case_insensitive(dir: &Path) -> bool {
+    let probe = dir.join(format!(".rip-case-probe-{}", std::process::id()));
+    if fs::File::create(&probe).is_err() {
+        return false;
+    }
+    let upper = dir.join(format!(".RIP-CASE-PROBE-{}", std::process::id()));
+    let insensitive = symlink_exists(upper);
+    fs::remove_file(&probe).ok();
+    insensitive
+}
+
+/// Compare paths, ignoring case if `case_insensitive`
+pub fn paths_match(a: &Path, b: &Path, case_insensitive: bool) -> bool {
+    if case_insensitive {
+        a.to_string_lossy().to_lowercase() == b.to_string_lossy().to_lowercase()
+    } else {
+        a == b
+    }
+}
+
 pub fn symlink_exists<P: AsRef<Path>>(path: P) -> bool {
     fs::symlink_metadata(path).is_ok()
 }
 
+/// Set the modification time of `path` without following symlinks,
+/// leaving the access time untouched
+#[cfg(unix)]
+pub fn set_mtime(path: &Path, mtime: SystemTime) -> Result<(), Error> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let since_epoch = mtime
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| Error::new(io::ErrorKind::InvalidInput, "mtime before the epoch"))?;
+    let times = [
+        libc::timespec {
+            tv_sec: 0,
+            tv_nsec: libc::UTIME_OMIT,
+        },
+        libc::timespec {
+            tv_sec: since_epoch.as_secs() as libc::time_t,
+            tv_nsec: since_epoch.subsec_nanos() as _,
+        },
+    ];
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| Error::new(io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: c_path is NUL-terminated and times holds two timespecs
+    let result = unsafe {
+        libc::utimensat(
+            libc::AT_FDCWD,
+            c_path.as_ptr(),
+            times.as_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    };
+    if result != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Set the modification time of `path`, which may be a directory
+#[cfg(not(unix))]
+pub fn set_mtime(path: &Path, mtime: SystemTime) -> Result<(), Error> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    // Needed to open a handle to a directory
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x02000000;
+    fs::OpenOptions::new()
+        .write(true)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(path)?
+        .set_modified(mtime)
+}
+
+/// A block or character device node, which can't be copied like a file
+/// and has to be recreated with `mknod` instead
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceNode {
+    pub block: bool,
+    /// Device number, holding the major and minor numbers
+    pub rdev: u64,
+    /// Permission bits
+    pub mode: u32,
+}
+
+impl DeviceNode {
+    #[cfg(unix)]
+    pub fn of(metadata: &fs::Metadata) -> Option<DeviceNode> {
+        use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+        let filetype = metadata.file_type();
+        if !filetype.is_block_device() && !filetype.is_char_device() {
+            return None;
+        }
+        Some(DeviceNode {
+            block: filetype.is_block_device(),
+            rdev: metadata.rdev(),
+            mode: metadata.mode() & 0o7777,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn of(_metadata: &fs::Metadata) -> Option<DeviceNode> {
+        None
+    }
+
+    /// Parse the `b:RDEV:MODE` or `c:RDEV:MODE` form written by `Display`
+    pub fn parse(s: &str) -> Option<DeviceNode> {
+        let mut parts = s.splitn(3, ':');
+        let block = match parts.next()? {
+            "b" => true,
+            "c" => false,
+            _ => return None,
+        };
+        let rdev = parts.next()?.parse().ok()?;
+        let mode = u32::from_str_radix(parts.next()?, 8).ok()?;
+        Some(DeviceNode { block, rdev, mode })
+    }
+
+    /// Create the node at `path`, which needs privilege on most systems
+    #[cfg(unix)]
+    pub fn create(&self, path: &Path) -> Result<(), Error> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let kind = if self.block {
+            libc::S_IFBLK
+        } else {
+            libc::S_IFCHR
+        };
+        let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| Error::new(io::ErrorKind::InvalidInput, e))?;
+        // SAFETY: c_path is NUL-terminated
+        let result = unsafe {
+            libc::mknod(
+                c_path.as_ptr(),
+                kind | self.mode as libc::mode_t,
+                self.rdev as libc::dev_t,
+            )
+        };
+        if result != 0 {
+            return Err(Error::last_os_error());
+        }
+        // mknod is subject to the umask
+        fs::set_permissions(
+            path,
+            std::os::unix::fs::PermissionsExt::from_mode(self.mode),
+        )
+    }
+
+    #[cfg(not(unix))]
+    pub fn create(&self, _path: &Path) -> Result<(), Error> {
+        Err(Error::new(
+            io::ErrorKind::Unsupported,
+            "Device nodes are only supported on Unix",
+        ))
+    }
+}
+
+impl fmt::Display for DeviceNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = if self.block { 'b' } else { 'c' };
+        write!(f, "{}:{}:{:o}", kind, self.rdev, self.mode)
+    }
+}
+
+/// Extended attributes that hold a POSIX ACL, the second only on directories
+#[cfg(target_os = "linux")]
+const ACL_XATTRS: [&str; 2] = ["system.posix_acl_access", "system.posix_acl_default"];
+
+#[cfg(unix)]
+fn c_path(path: &Path) -> Result<std::ffi::CString, Error> {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// Read the raw value of the extended attribute `name`, or `None` if it isn't set
+#[cfg(target_os = "linux")]
+pub fn get_xattr(path: &Path, name: &str) -> Result<Option<Vec<u8>>, Error> {
+    let c_path = c_path(path)?;
+    let c_name = std::ffi::CString::new(name).unwrap();
+    loop {
+        // SAFETY: both strings are NUL-terminated; a null buffer asks for the size
+        let size =
+            unsafe { libc::getxattr(c_path.as_ptr(), c_name.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            let e = Error::last_os_error();
+            return match e.raw_os_error() {
+                Some(libc::ENODATA) | Some(libc::ENOTSUP) => Ok(None),
+                _ => Err(e),
+            };
+        }
+        let mut value = vec![0_u8; size as usize];
+        // SAFETY: value is writable for value.len() bytes
+        let read = unsafe {
+            libc::getxattr(
+                c_path.as_ptr(),
+                c_name.as_ptr(),
+                value.as_mut_ptr().cast(),
+                value.len(),
+            )
+        };
+        if read >= 0 {
+            value.truncate(read as usize);
+            return Ok(Some(value));
+        }
+        // The attribute grew between the two calls, so ask again
+        if Error::last_os_error().raw_os_error() != Some(libc::ERANGE) {
+            return Err(Error::last_os_error());
+        }
+    }
+}
+
+/// Whether the filesystem holding `path` supports extended attributes
+#[cfg(target_os = "linux")]
+pub fn xattrs_supported(path: &Path) -> Result<bool, Error> {
+    let c_path = c_path(path)?;
+    // Listing succeeds even where attributes can't be stored, but reading
+    // an unset one tells the two apart
+    let c_name = std::ffi::CString::new("user.rip").unwrap();
+    // SAFETY: both strings are NUL-terminated; a null buffer asks for the size
+    let size = unsafe { libc::getxattr(c_path.as_ptr(), c_name.as_ptr(), std::ptr::null_mut(), 0) };
+    if size >= 0 {
+        return Ok(true);
+    }
+    let e = Error::last_os_error();
+    match e.raw_os_error() {
+        Some(libc::ENODATA) => Ok(true),
+        Some(libc::ENOTSUP) => Ok(false),
+        _ => Err(e),
+    }
+}
+
+/// Set the extended attribute `name` to the raw `value`
+#[cfg(target_os = "linux")]
+pub fn set_xattr(path: &Path, name: &str, value: &[u8]) -> Result<(), Error> {
+    let c_path = c_path(path)?;
+    let c_name = std::ffi::CString::new(name).unwrap();
+    // SAFETY: both strings are NUL-terminated and value is readable for its length
+    let result = unsafe {
+        libc::setxattr(
+            c_path.as_ptr(),
+            c_name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        )
+    };
+    if result != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Copy the POSIX ACLs of `source` onto `dest`. Does nothing off Linux,
+/// and fails with `Unsupported` if `dest` is on a filesystem without ACLs.
+/// Must run after any chmod of `dest`, which would rewrite the ACL mask.
+pub fn copy_acl(source: &Path, dest: &Path) -> Result<(), Error> {
+    #[cfg(target_os = "linux")]
+    for name in ACL_XATTRS {
+        if let Some(value) = get_xattr(source, name)? {
+            set_xattr(dest, name, &value)?;
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (source, dest);
+    Ok(())
+}
+
+/// Remove any POSIX ACLs from `path`, leaving just its mode bits
+pub fn strip_acl(path: &Path) -> Result<(), Error> {
+    #[cfg(target_os = "linux")]
+    {
+        let c_path = c_path(path)?;
+        for name in ACL_XATTRS {
+            let c_name = std::ffi::CString::new(name).unwrap();
+            // SAFETY: both strings are NUL-terminated
+            if unsafe { libc::removexattr(c_path.as_ptr(), c_name.as_ptr()) } != 0 {
+                let e = Error::last_os_error();
+                match e.raw_os_error() {
+                    Some(libc::ENODATA) | Some(libc::ENOTSUP) => {}
+                    _ => return Err(e),
+                }
+            }
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = path;
+    Ok(())
+}
+
+/// Set the birth time of `path` on platforms that allow it (macOS and
+/// Windows). Elsewhere this does nothing, as it does for symlinks since
+/// setting times follows them.
+pub fn set_crtime(path: &Path, crtime: SystemTime) -> Result<(), Error> {
+    if fs::symlink_metadata(path)?.is_symlink() {
+        return Ok(());
+    }
+    #[cfg(target_os = "macos")]
+    {
+        use std::os::macos::fs::FileTimesExt;
+        fs::File::open(path)?.set_times(fs::FileTimes::new().set_created(crtime))?;
+    }
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::fs::{FileTimesExt, OpenOptionsExt};
+        const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x02000000;
+        fs::OpenOptions::new()
+            .write(true)
+            .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+            .open(path)?
+            .set_times(fs::FileTimes::new().set_created(crtime))?;
+    }
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    let _ = (path, crtime);
+    Ok(())
+}
+
+/// The `(dev, ino)` pair identifying a file, on platforms that have one
+pub fn file_id(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some((metadata.dev(), metadata.ino()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// Whether the file has hardlinks besides this one, so that it may share
+/// its inode with a grave
+pub fn has_other_links(metadata: &fs::Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.nlink() > 1
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        false
+    }
+}
+
+/// Bytes available to unprivileged users and total bytes of the
+/// filesystem holding `path`
+pub fn disk_space(path: &Path) -> Option<(u64, u64)> {
+    #[cfg(unix)]
+    {
+        let c_path = c_path(path).ok()?;
+        // SAFETY: statvfs is plain old data, filled in by the call below
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        // SAFETY: c_path is NUL-terminated and stat is a valid statvfs
+        if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+            return None;
+        }
+        let fragment = stat.f_frsize as u64;
+        Some((
+            stat.f_bavail as u64 * fragment,
+            stat.f_blocks as u64 * fragment,
+        ))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+/// Decode the `%XX` escapes of a URL-encoded path
+pub fn percent_decode(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| s.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// The original path and deletion date (local time, without a zone) of a
+/// FreeDesktop `.trashinfo` file
+pub fn parse_trashinfo(contents: &str) -> Option<(PathBuf, Option<String>)> {
+    let mut path = None;
+    let mut date = None;
+    for line in contents.lines() {
+        if let Some(value) = line.strip_prefix("Path=") {
+            let decoded = percent_decode(value.trim());
+            #[cfg(unix)]
+            {
+                use std::os::unix::ffi::OsStringExt;
+                path = Some(PathBuf::from(std::ffi::OsString::from_vec(decoded)));
+            }
+            #[cfg(not(unix))]
+            {
+                path = Some(PathBuf::from(
+                    String::from_utf8_lossy(&decoded).into_owned(),
+                ));
+            }
+        } else if let Some(value) = line.strip_prefix("DeletionDate=") {
+            date = Some(value.trim().to_string());
+        }
+    }
+    Some((path?, date))
+}
+
+/// Whether entries can be added to or removed from the directory `dir`
+/// by this process, e.g. false for a read-only or immutable directory
+pub fn can_write(dir: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        let Ok(c_path) = c_path(dir) else {
+            return false;
+        };
+        // SAFETY: c_path is NUL-terminated
+        unsafe { libc::access(c_path.as_ptr(), libc::W_OK) == 0 }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = dir;
+        true
+    }
+}
+
+/// Permission bits and owner of a file, which files must share before
+/// they can be hardlinked together
+pub fn mode_and_owner(metadata: &fs::Metadata) -> Option<(u32, u32, u32)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some((metadata.mode(), metadata.uid(), metadata.gid()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// Hash the contents of a file
+pub fn content_hash(path: &Path) -> Result<u64, Error> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    let mut hasher = DefaultHasher::new();
+    let mut buf = vec![0; 1 << 16];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            return Ok(hasher.finish());
+        }
+        hasher.write(&buf[..n]);
+    }
+}
+
+/// Short label describing what kind of file a grave is
+pub fn grave_type(path: impl AsRef<Path>) -> &'static str {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => "[dir]",
+        Ok(metadata) if metadata.is_file() => "[file]",
+        Ok(metadata) if metadata.is_symlink() => "[link]",
+        Ok(_) => "[special]",
+        Err(_) => "[missing]",
+    }
+}
+
+/// Whether a directory holds more than `limit` entries in total,
+/// stopping the walk as soon as the limit is passed
+pub fn has_more_entries_than(dir: impl AsRef<Path>, limit: u64) -> bool {
+    walkdir::WalkDir::new(dir)
+        .min_depth(1)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .nth(limit as usize)
+        .is_some()
+}
+
+/// Total size of everything a directory walk yields, along with the
+/// first `count` top-level entries, so inspection only walks the tree once
+pub fn dir_summary(
+    walker: impl IntoIterator<Item = walkdir::Result<walkdir::DirEntry>>,
+    count: usize,
+) -> Result<(u64, Vec<PathBuf>), Error> {
+    let mut size = 0;
+    let mut first = Vec::new();
+    for entry in walker {
+        let entry = entry?;
+        if entry.depth() == 1 && first.len() < count {
+            first.push(entry.path().to_path_buf());
+        }
+        // Like fs_extra's get_size, only non-directories count
+        if !entry.file_type().is_dir() {
+            size += entry.metadata()?.len();
+        }
+    }
+    Ok((size, first))
+}
+
+/// Size of a grave in bytes, recursing into directories
+pub fn grave_size(path: impl AsRef<Path>) -> Option<u64> {
+    let path = path.as_ref();
+    let metadata = fs::symlink_metadata(path).ok()?;
+    if metadata.is_dir() {
+        fs_extra::dir::get_size(path).ok()
+    } else {
+        Some(metadata.len())
+    }
+}
+
+/// Compile a glob supporting `*` and `?` into a regex matching the whole
+/// string
+pub fn glob_regex(glob: &str) -> Result<regex::Regex, Error> {
+    let mut pattern = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    regex::Regex::new(&pattern).map_err(|e| Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// Whether two regular files have the same contents, checking the sizes
+/// before reading either
+pub fn same_contents(a: &Path, b: &Path) -> Result<bool, Error> {
+    if fs::metadata(a)?.len() != fs::metadata(b)?.len() {
+        return Ok(false);
+    }
+    let mut a = BufReader::new(fs::File::open(a)?);
+    let mut b = BufReader::new(fs::File::open(b)?);
+    let mut buf_a = vec![0; 1 << 16];
+    let mut buf_b = vec![0; 1 << 16];
+    loop {
+        let n = a.read(&mut buf_a)?;
+        if n == 0 {
+            return Ok(true);
+        }
+        b.read_exact(&mut buf_b[..n])?;
+        if buf_a[..n] != buf_b[..n] {
+            return Ok(false);
+        }
+    }
+}
+
+/// Iterates over the lines of a file from last to first, reading
+/// backwards from the end in chunks so the whole file is never loaded.
+/// CRLF endings are stripped like LF ones.
+pub struct ReverseLines<R> {
+    reader: R,
+    /// Offset up to which the file hasn't been read yet
+    pos: u64,
+    /// Read but not yet returned, always starting at `pos`
+    buf: Vec<u8>,
+    started: bool,
+}
+
+impl<R: Read + Seek> ReverseLines<R> {
+    pub fn new(mut reader: R) -> Result<Self, Error> {
+        let pos = reader.seek(SeekFrom::End(0))?;
+        Ok(ReverseLines {
+            reader,
+            pos,
+            buf: Vec::new(),
+            started: false,
+        })
+    }
+
+    fn read_chunk(&mut self) -> Result<(), Error> {
+        const CHUNK_SIZE: u64 = 4096;
+        let start = self.pos.saturating_sub(CHUNK_SIZE);
+        let mut chunk = vec![0; (self.pos - start) as usize];
+        self.reader.seek(SeekFrom::Start(start))?;
+        self.reader.read_exact(&mut chunk)?;
+        // A trailing newline doesn't start another line
+        if !self.started && chunk.last() == Some(&b'\n') {
+            chunk.pop();
+        }
+        self.started = true;
+        chunk.append(&mut self.buf);
+        self.buf = chunk;
+        self.pos = start;
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Iterator for ReverseLines<R> {
+    type Item = Result<String, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.buf.iter().rposition(|&b| b == b'\n') {
+                Some(newline) => {
+                    let line = self.buf.split_off(newline + 1);
+                    self.buf.pop();
+                    line
+                }
+                None if self.pos == 0 && self.buf.is_empty() => return None,
+                None if self.pos == 0 => std::mem::take(&mut self.buf),
+                None => {
+                    if let Err(e) = self.read_chunk() {
+                        self.pos = 0;
+                        self.buf.clear();
+                        return Some(Err(e));
+                    }
+                    continue;
+                }
+            };
+            let line = String::from_utf8_lossy(&line);
+            return Some(Ok(line.strip_suffix('\r').unwrap_or(&line).to_string()));
+        }
+    }
+}
+
+/// The last `count` lines of `file`, in order
+pub fn tail_lines(file: &mut (impl Read + Seek), count: usize) -> Result<Vec<String>, Error> {
+    let mut lines = ReverseLines::new(file)?
+        .take(count)
+        .collect::<Result<Vec<_>, Error>>()?;
+    lines.reverse();
+    Ok(lines)
+}
+
+/// Name of this machine, so hosts sharing a home directory can keep
+/// separate graveyards
+pub fn hostname() -> String {
+    #[cfg(unix)]
+    {
+        let mut buf = [0_u8; 256];
+        // SAFETY: buf is writable for buf.len() bytes
+        let result = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+        if result == 0 {
+            let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+            if let Ok(name) = from_utf8(&buf[..len]) {
+                if !name.is_empty() {
+                    return name.to_string();
+                }
+            }
+        }
+    }
+    env::var("COMPUTERNAME")
+        .or_else(|_| env::var("HOSTNAME"))
+        .unwrap_or_else(|_| String::from("localhost"))
+}
+
 pub fn get_user() -> String {
     #[cfg(unix)]
     {
@@ -62,11 +866,27 @@ pub fn get_user() -> String {
 // Allows injection of test-specific behavior
 pub trait TestingMode {
     fn is_test(&self) -> bool;
+
+    /// Call `f` with the reader that answers to prompts come from, which
+    /// is stdin unless the mode supplies its own
+    fn with_input<T>(&self, f: impl FnOnce(&mut dyn BufRead) -> T) -> T {
+        f(&mut io::stdin().lock())
+    }
 }
 
 pub struct ProductionMode;
 pub struct TestMode;
 
+/// Behaves like `ProductionMode`, but reads answers to prompts from
+/// `input` instead of stdin, so scripts and tests can drive them
+pub struct InputMode<R>(RefCell<R>);
+
+impl<R: BufRead> InputMode<R> {
+    pub fn new(input: R) -> Self {
+        Self(RefCell::new(input))
+    }
+}
+
 impl TestingMode for ProductionMode {
     fn is_test(&self) -> bool {
         false
@@ -77,6 +897,200 @@ impl TestingMode for TestMode {
         true
     }
 }
+impl<R: BufRead> TestingMode for InputMode<R> {
+    fn is_test(&self) -> bool {
+        false
+    }
+
+    fn with_input<T>(&self, f: impl FnOnce(&mut dyn BufRead) -> T) -> T {
+        f(&mut *self.0.borrow_mut())
+    }
+}
+
+/// Move rip to the idle I/O scheduling class so it yields to other work.
+/// This is a no-op on platforms other than Linux.
+pub fn set_idle_io_priority() -> Result<(), Error> {
+    #[cfg(target_os = "linux")]
+    {
+        const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+        const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+        const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+        // SAFETY: ioprio_set only reads its integer arguments
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_ioprio_set,
+                IOPRIO_WHO_PROCESS,
+                0,
+                IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+            )
+        };
+        if result != 0 {
+            return Err(Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+const FS_IMMUTABLE_FL: libc::c_int = 0x10;
+#[cfg(target_os = "linux")]
+const FS_APPEND_FL: libc::c_int = 0x20;
+
+/// Read the inode flags (as shown by `lsattr`) of a file or directory
+#[cfg(target_os = "linux")]
+fn inode_flags(path: &Path) -> Result<(fs::File, libc::c_int), Error> {
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::AsRawFd;
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK | libc::O_NOFOLLOW)
+        .open(path)?;
+    let mut flags: libc::c_int = 0;
+    // SAFETY: FS_IOC_GETFLAGS writes a single int through the pointer
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut flags) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok((file, flags))
+}
+
+/// Which flag, if any, stops `path` from being moved or deleted even by
+/// root: "immutable" or "append-only" (see `chattr`). Filesystems that
+/// don't support the flags, and other platforms, report `None`.
+pub fn protection_flag(path: &Path) -> Option<&'static str> {
+    #[cfg(target_os = "linux")]
+    {
+        let (_, flags) = inode_flags(path).ok()?;
+        if flags & FS_IMMUTABLE_FL != 0 {
+            return Some("immutable");
+        }
+        if flags & FS_APPEND_FL != 0 {
+            return Some("append-only");
+        }
+    }
+    let _ = path;
+    None
+}
+
+/// Clear the immutable and append-only flags of `path`. This needs
+/// CAP_LINUX_IMMUTABLE, so usually root.
+pub fn clear_protection(path: &Path) -> Result<(), Error> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+        let (file, flags) = inode_flags(path)?;
+        let flags = flags & !(FS_IMMUTABLE_FL | FS_APPEND_FL);
+        // SAFETY: FS_IOC_SETFLAGS only reads a single int through the pointer
+        if unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_SETFLAGS, &flags) } != 0 {
+            return Err(Error::last_os_error());
+        }
+    }
+    let _ = path;
+    Ok(())
+}
+
+const RETRY_ATTEMPTS: u32 = 5;
+const RETRY_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Whether an error is likely to go away if the operation is retried,
+/// e.g. a signal interrupting a syscall or a busy network filesystem
+pub fn is_transient(e: &Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::ResourceBusy
+    )
+}
+
+/// Run `f`, retrying with exponential backoff while it fails with a
+/// transient error. The last error is returned once attempts run out.
+pub fn retry_transient<T>(mut f: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match f() {
+            Err(e) if is_transient(&e) && attempt + 1 < RETRY_ATTEMPTS => {
+                std::thread::sleep(RETRY_BACKOFF * 2_u32.pow(attempt));
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether SIGINT arrived while an `InterruptGuard` was alive
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Act as if SIGINT arrived
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Test behavior: simulate an interrupt once this many files were copied
+pub fn interrupt_after() -> Option<usize> {
+    env::var("__RIP_INTERRUPT_AFTER").ok()?.parse().ok()
+}
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_: libc::c_int) {
+    const MESSAGE: &[u8] =
+        b"Interrupted; finishing the current file (press Ctrl-C again to abort)\n";
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        // SAFETY: signal and raise are async-signal-safe
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+            libc::raise(libc::SIGINT);
+        }
+    }
+    // SAFETY: write is async-signal-safe and MESSAGE outlives the call
+    unsafe { libc::write(libc::STDERR_FILENO, MESSAGE.as_ptr().cast(), MESSAGE.len()) };
+}
+
+/// While alive, SIGINT only sets a flag, checked between files, so that a
+/// bury can stop without leaving a partial grave behind. A second SIGINT
+/// aborts as usual.
+pub struct InterruptGuard {
+    #[cfg(unix)]
+    previous: libc::sighandler_t,
+}
+
+impl InterruptGuard {
+    pub fn new() -> Self {
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        InterruptGuard {
+            // SAFETY: on_interrupt only touches an atomic and makes
+            // async-signal-safe calls
+            #[cfg(unix)]
+            previous: unsafe {
+                libc::signal(
+                    libc::SIGINT,
+                    on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+                )
+            },
+        }
+    }
+}
+
+impl Default for InterruptGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        // SAFETY: restores the handler that was installed before
+        #[cfg(unix)]
+        unsafe {
+            libc::signal(libc::SIGINT, self.previous);
+        }
+        INTERRUPTED.store(false, Ordering::SeqCst);
+    }
+}
 
 pub fn allow_rename() -> bool {
     // Test behavior to skip simple rename
@@ -86,38 +1100,63 @@ pub fn allow_rename() -> bool {
         .unwrap()
 }
 
+/// The choices shown after a prompt, with the default capitalized
+pub fn prompt_choices(default: bool) -> &'static str {
+    if default {
+        "(Y/n)"
+    } else {
+        "(y/N)"
+    }
+}
+
 /// Prompt for user input, returning True if the first character is 'y' or 'Y'
+/// and `default` if the input is empty.
 /// Will create an error if given a 'q' or 'Q', equivalent to if the user
 /// had passed a SIGINT.
 pub fn prompt_yes(
     prompt: impl AsRef<str>,
+    default: bool,
     source: &impl TestingMode,
     stream: &mut impl Write,
 ) -> Result<bool, Error> {
-    write!(stream, "{} (y/N) ", prompt.as_ref())?;
+    let choices = prompt_choices(default);
+    write!(stream, "{} {} ", prompt.as_ref(), choices)?;
     if stream.flush().is_err() {
         // If stdout wasn't flushed properly, fallback to println
-        writeln!(stream, "{} (y/N)", prompt.as_ref())?;
+        writeln!(stream, "{} {}", prompt.as_ref(), choices)?;
     }
 
     if source.is_test() {
         return Ok(true);
     }
 
-    yes_no_quit(io::stdin())
+    source.with_input(|input| yes_no_quit(input, default))
+}
+
+/// Read the first character of a line of `in_stream`. The whole line is
+/// consumed so that the next prompt starts on a fresh answer, e.g. with
+/// `yes | rip ...`.
+///
+/// SIGINT at the prompt only sets the interrupt flag while the read goes
+/// on, so the answer is thrown away once the line comes in.
+fn read_choice(mut in_stream: impl BufRead) -> Result<Option<char>, Error> {
+    let mut line = String::new();
+    let read = in_stream.read_line(&mut line);
+    if interrupted() {
+        return Err(Error::new(io::ErrorKind::Interrupted, "Interrupted"));
+    }
+    Ok(read.ok().and_then(|_| line.chars().next()))
 }
 
-pub fn yes_no_quit(in_stream: impl Read) -> Result<bool, Error> {
-    let buffered = BufReader::new(in_stream);
-    let char_result = buffered
-        .bytes()
-        .next()
-        .and_then(|c| c.ok())
-        .map(|c| c as char);
+pub fn yes_no_quit(in_stream: impl BufRead, default: bool) -> Result<bool, Error> {
+    let char_result = read_choice(in_stream)?;
 
     match char_result {
         Some('y') | Some('Y') => Ok(true),
-        Some('n') | Some('N') | Some('\n') | None => Ok(false),
+        Some('n') | Some('N') => Ok(false),
+        Some('\n') => Ok(default),
+        // Closed or empty input never agrees to anything
+        None => Ok(false),
         Some('q') | Some('Q') => Err(Error::new(
             io::ErrorKind::Interrupted,
             "User requested to quit",
@@ -126,21 +1165,296 @@ pub fn yes_no_quit(in_stream: impl Read) -> Result<bool, Error> {
     }
 }
 
-/// Add a numbered extension to duplicate filenames to avoid overwriting files.
-pub fn rename_grave(grave: impl AsRef<Path>) -> PathBuf {
-    let grave = grave.as_ref();
-    let name = grave.to_str().expect("Filename must be valid unicode.");
-    (1_u64..)
-        .map(|i| PathBuf::from(format!("{}~{}", name, i)))
+/// What to do with a file too big to copy cheaply
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BigFileChoice {
+    /// Copy it anyway
+    Move,
+    /// Permanently delete it instead
+    Delete,
+    /// Leave it where it is
+    Cancel,
+}
+
+/// Ask what to do with a big file. `--yes` and `--force` pick the
+/// default of moving it anyway; in tests it is always deleted.
+pub fn prompt_big_file(
+    policy: PromptPolicy,
+    source: &impl TestingMode,
+    stream: &mut impl Write,
+) -> Result<BigFileChoice, Error> {
+    let prompt = "Move it anyway, permanently delete it, or cancel? (M/d/c)";
+    if policy.yes || policy.force {
+        writeln!(stream, "{} m", prompt)?;
+        return Ok(BigFileChoice::Move);
+    }
+    write!(stream, "{} ", prompt)?;
+    if stream.flush().is_err() {
+        // If stdout wasn't flushed properly, fallback to println
+        writeln!(stream, "{}", prompt)?;
+    }
+
+    if source.is_test() {
+        return Ok(BigFileChoice::Delete);
+    }
+
+    source.with_input(|input| big_file_choice(input))
+}
+
+pub fn big_file_choice(in_stream: impl BufRead) -> Result<BigFileChoice, Error> {
+    let char_result = read_choice(in_stream)?;
+
+    match char_result {
+        Some('m') | Some('M') | Some('\n') | None => Ok(BigFileChoice::Move),
+        Some('d') | Some('D') => Ok(BigFileChoice::Delete),
+        Some('c') | Some('C') => Ok(BigFileChoice::Cancel),
+        Some('q') | Some('Q') => Err(Error::new(
+            io::ErrorKind::Interrupted,
+            "User requested to quit",
+        )),
+        _ => Err(Error::new(io::ErrorKind::InvalidInput, "Invalid input")),
+    }
+}
+
+/// Which prompts to answer without asking. `--yes` takes the default
+/// answer of prompts that can't lose data; `--force` answers yes to
+/// every prompt, including those that permanently delete.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PromptPolicy {
+    pub yes: bool,
+    pub force: bool,
+}
+
+impl PromptPolicy {
+    /// Ask `prompt` via `prompt_yes`, unless the policy already decides
+    /// the answer, in which case it is printed after the prompt
+    pub fn ask(
+        &self,
+        prompt: impl AsRef<str>,
+        default: bool,
+        destructive: bool,
+        source: &impl TestingMode,
+        stream: &mut impl Write,
+    ) -> Result<bool, Error> {
+        let assumed = if self.force {
+            Some(true)
+        } else if self.yes && !destructive {
+            Some(default)
+        } else {
+            None
+        };
+        match assumed {
+            Some(answer) => {
+                writeln!(
+                    stream,
+                    "{} {} {}",
+                    prompt.as_ref(),
+                    prompt_choices(default),
+                    if answer { "y" } else { "n" }
+                )?;
+                Ok(answer)
+            }
+            None => prompt_yes(prompt, default, source, stream),
+        }
+    }
+}
+
+impl GraveLayout {
+    /// The date partition that graves buried `now` go under, if any
+    pub fn partition(self, now: &chrono::DateTime<chrono::Local>) -> Option<String> {
+        (self == GraveLayout::Date).then(|| now.format("%Y-%m-%d").to_string())
+    }
+
+    /// Where the grave of `source`, buried `now`, goes under `root`. The
+    /// record always keeps the original path, so unbury doesn't depend on
+    /// the layout.
+    pub fn grave_path(
+        self,
+        root: &Path,
+        source: &Path,
+        now: &chrono::DateTime<chrono::Local>,
+    ) -> PathBuf {
+        let (parent, name) = match (source.parent(), source.file_name()) {
+            (Some(parent), Some(name)) => (parent, name),
+            _ => return join_absolute(root, source),
+        };
+        match self {
+            GraveLayout::Mirror => join_absolute(root, source),
+            GraveLayout::Flat => root.join(name),
+            GraveLayout::Date => join_absolute(root.join(self.partition(now).unwrap()), source),
+            GraveLayout::Hash => {
+                let mut hasher = DefaultHasher::new();
+                parent.hash(&mut hasher);
+                root.join(format!("{:016x}", hasher.finish())).join(name)
+            }
+        }
+    }
+}
+
+/// Names to try, in order, for a grave that collides with `grave`
+fn grave_names(grave: &Path, style: CollisionSuffix) -> Box<dyn Iterator<Item = PathBuf>> {
+    let name = grave
+        .to_str()
+        .expect("Filename must be valid unicode.")
+        .to_string();
+    match style {
+        CollisionSuffix::Numeric => {
+            Box::new((1_u64..).map(move |i| PathBuf::from(format!("{}~{}", name, i))))
+        }
+        CollisionSuffix::Timestamp => {
+            // Count up within the second if it is taken too
+            let stamp = chrono::Local::now().format("%Y%m%dT%H%M%S").to_string();
+            Box::new(
+                std::iter::once(PathBuf::from(format!("{}~{}", name, stamp))).chain(
+                    (1_u64..).map(move |i| PathBuf::from(format!("{}~{}~{}", name, stamp, i))),
+                ),
+            )
+        }
+        CollisionSuffix::Uuid => Box::new(std::iter::repeat_with(move || {
+            PathBuf::from(format!("{}~{}", name, random_uuid()))
+        })),
+    }
+}
+
+/// A random (version 4) UUID. The bits come from the randomly keyed
+/// std hasher, which is plenty to keep grave names apart.
+pub fn random_uuid() -> String {
+    let mut bits = [0_u64; 2];
+    for (i, half) in bits.iter_mut().enumerate() {
+        *half = std::collections::hash_map::RandomState::new().hash_one((
+            i,
+            std::process::id(),
+            SystemTime::now(),
+        ));
+    }
+    let value = (u128::from(bits[0]) << 64) | u128::from(bits[1]);
+    // Set the version (4) and variant (RFC 4122) bits
+    let value = (value & !(0xf << 76) & !(0x3 << 62)) | (0x4 << 76) | (0x2 << 62);
+    let hex = format!("{:032x}", value);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Add an extension in the given style to duplicate filenames to avoid
+/// overwriting files.
+pub fn rename_grave(grave: impl AsRef<Path>, style: CollisionSuffix) -> PathBuf {
+    grave_names(grave.as_ref(), style)
         .find(|p| !symlink_exists(p))
         .expect("Failed to rename duplicate file or directory")
 }
 
-const UNITS: [(&str, u64); 4] = [
+/// Claim a free grave at `grave`, or at the first free name that
+/// `rename_grave` would pick, by creating an empty placeholder there (a
+/// directory if `is_dir`). Creation fails if the name is taken, so two
+/// concurrent buries can never end up with the same grave.
+pub fn reserve_grave(
+    grave: impl AsRef<Path>,
+    is_dir: bool,
+    style: CollisionSuffix,
+) -> Result<PathBuf, Error> {
+    let grave = grave.as_ref();
+    if let Some(parent) = grave.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let candidates = std::iter::once(grave.to_path_buf()).chain(grave_names(grave, style));
+    for candidate in candidates {
+        let created = if is_dir {
+            fs::create_dir(&candidate)
+        } else {
+            fs::File::create_new(&candidate).map(|_| ())
+        };
+        match created {
+            Ok(()) => return Ok(candidate),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!("Ran out of grave names")
+}
+
+/// Name to move an existing file to before it is replaced, resolving
+/// conflicts with older backups like `rename_grave`.
+pub fn backup_path(path: impl AsRef<Path>) -> PathBuf {
+    let path = path.as_ref();
+    let name = path.to_str().expect("Filename must be valid unicode.");
+    let backup = PathBuf::from(format!("{}.bak", name));
+    if symlink_exists(&backup) {
+        rename_grave(backup, CollisionSuffix::Numeric)
+    } else {
+        backup
+    }
+}
+
+/// Parse a duration like `90s`, `15m`, `12h`, `30d`, or `2w`
+pub fn parse_duration(s: &str) -> Result<Duration, Error> {
+    let invalid = || {
+        Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Invalid duration: {} (expected a number followed by s, m, h, d, or w)",
+                s
+            ),
+        )
+    };
+    let (split, _) = s.char_indices().last().ok_or_else(invalid)?;
+    let (count, unit) = s.split_at(split);
+    let count: u64 = count.parse().map_err(|_| invalid())?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    count
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(invalid)
+}
+
+/// Parse a size in bytes, optionally followed by K, M, G, or T
+/// (powers of 1024)
+pub fn parse_size(s: &str) -> Result<u64, Error> {
+    let invalid = || {
+        Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Invalid size: {} (expected a number optionally followed by K, M, G, or T)",
+                s
+            ),
+        )
+    };
+    let (count, multiplier) = match s.char_indices().last().ok_or_else(invalid)? {
+        (split, unit) if unit.is_ascii_alphabetic() => {
+            let multiplier = match unit.to_ascii_uppercase() {
+                'K' => UNITS[0].1,
+                'M' => UNITS[1].1,
+                'G' => UNITS[2].1,
+                'T' => UNITS[3].1,
+                _ => return Err(invalid()),
+            };
+            (&s[..split], multiplier)
+        }
+        _ => (s, 1),
+    };
+    let count: u64 = count.parse().map_err(|_| invalid())?;
+    count.checked_mul(multiplier).ok_or_else(invalid)
+}
+
+const UNITS: [(&str, u64); 6] = [
     ("KiB", 1_u64 << 10),
     ("MiB", 1_u64 << 20),
     ("GiB", 1_u64 << 30),
     ("TiB", 1_u64 << 40),
+    ("PiB", 1_u64 << 50),
+    ("EiB", 1_u64 << 60),
 ];
 
 pub fn humanize_bytes(bytes: u64) -> String {
@@ -2,14 +2,14 @@ use lazy_static::lazy_static;
 use predicates::str::is_match;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
-use rip2::args::Args;
-use rip2::record;
+use rip2::args::{Args, DuplicatePolicy, GraveCollision, GraveLayout, SortOrder};
 use rip2::util::TestMode;
 use rip2::{self, util};
+use rip2::{archive, record};
 use rstest::rstest;
 use std::fs;
 use std::hash::{DefaultHasher, Hash, Hasher};
-use std::io::{BufReader, ErrorKind, Read, Write};
+use std::io::{BufReader, Cursor, ErrorKind, Read, Write};
 use std::path::PathBuf;
 use std::sync::{Mutex, MutexGuard};
 use std::{env, ffi, iter};
@@ -263,7 +263,7 @@ fn test_duplicate_file(
     if inspect && in_folder {
         assert!(log_s.contains("dir: directory"));
         assert!(log_s.contains("including:"));
-        assert!(log_s.contains("to the graveyard? (y/N)"));
+        assert!(log_s.contains("to the graveyard? (Y/n)"));
     }
 
     assert!(expected_graveyard_path1.exists());
@@ -365,8 +365,10 @@ fn test_big_file() {
         dunce::canonicalize(big_file_path).unwrap(),
     );
 
+    // Force the copy path, where big files are prompted about
+    env::set_var("__RIP_ALLOW_RENAME", "false");
     let mut log = Vec::new();
-    rip2::run(
+    let result = rip2::run(
         Args {
             targets: [test_env.src.join("big_file.txt")].to_vec(),
             graveyard: Some(test_env.graveyard.clone()),
@@ -374,8 +376,9 @@ fn test_big_file() {
         },
         TestMode,
         &mut log,
-    )
-    .unwrap();
+    );
+    env::remove_var("__RIP_ALLOW_RENAME");
+    result.unwrap();
 
     // The file should be deleted
     assert!(!test_env.src.join("big_file.txt").exists());
@@ -505,7 +508,7 @@ fn test_cli(
             let output_stdout = String::from_utf8(output.stdout).unwrap();
 
             assert!(
-                output_stdout.contains(format!("{} to the graveyard? (y/N)", names[0]).as_str())
+                output_stdout.contains(format!("{} to the graveyard? (Y/n)", names[0]).as_str())
             );
 
             // One should still have the file, and the other should not:
@@ -593,8 +596,8 @@ fn issue_0018() {
         .write_stdin("\n")
         .assert()
         .stdout(is_match("About to copy a big file").unwrap())
-        .stdout(is_match("delete this file instead?").unwrap())
-        .stdout(is_match("y/N").unwrap());
+        .stdout(is_match("permanently delete it").unwrap())
+        .stdout(is_match("M/d/c").unwrap());
 
         // Expect it to be buried
         assert!(!test_env.src.join("uu_meta.zip").exists());
@@ -638,7 +641,7 @@ fn issue_0018() {
         assert!(!record_contents.contains("gnu_meta.zip"));
 
         // And give this for the last bury
-        let record = record::Record::new(&test_env.graveyard);
+        let record = record::Record::new(&test_env.graveyard, record::RECORD);
         let last_bury = record.get_last_bury().unwrap();
         assert!(last_bury.ends_with("uu_meta.zip"));
     }
@@ -661,11 +664,11 @@ fn issue_0018() {
             ],
             Some(&test_env.src),
         )
-        .write_stdin("y\n")
+        .write_stdin("d\n")
         .assert()
         .stdout(is_match("About to copy a big file").unwrap())
-        .stdout(is_match("delete this file instead?").unwrap())
-        .stdout(is_match("y/N").unwrap());
+        .stdout(is_match("permanently delete it").unwrap())
+        .stdout(is_match("M/d/c").unwrap());
 
         // Expect it to be permanently deleted
         assert!(!test_env.src.join("gnu_meta.zip").exists());
@@ -679,6 +682,84 @@ fn issue_0018() {
     return;
 }
 
+/// Test that a big file permanently deleted during a directory bury is
+/// neither in the grave nor in the record
+#[rstest]
+fn test_big_file_deleted_in_dir() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let dir = test_env.src.join("dir");
+    fs::create_dir(&dir).unwrap();
+    let test_data = TestData::new(&test_env, Some(&PathBuf::from("dir").join("small.txt")));
+    let big = dir.join("big.zip");
+    fs::File::create(&big)
+        .unwrap()
+        .set_len(rip2::BIG_FILE_THRESHOLD + 1)
+        .unwrap();
+    let grave = util::join_absolute(&test_env.graveyard, dunce::canonicalize(&dir).unwrap());
+
+    // Force the copy path; the test prompt chooses permanent deletion
+    env::set_var("__RIP_ALLOW_RENAME", "false");
+    let mut log = Vec::new();
+    let result = rip2::run(
+        Args {
+            targets: vec![dir.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    );
+    env::remove_var("__RIP_ALLOW_RENAME");
+    result.unwrap();
+
+    let log_s = String::from_utf8(log).unwrap();
+    assert!(log_s.contains(&format!("Permanently deleted {}", big.display())));
+    assert!(!dir.exists());
+    assert!(!grave.join("big.zip").exists());
+    assert_eq!(
+        fs::read_to_string(grave.join("small.txt")).unwrap(),
+        test_data.data
+    );
+    let record_contents = fs::read_to_string(test_env.graveyard.join(record::RECORD)).unwrap();
+    let lines: Vec<&str> = record_contents.lines().skip(1).collect();
+    assert_eq!(lines.len(), 1);
+    assert_eq!(record::RecordItem::new(lines[0]).dest, grave);
+    assert!(!record_contents.contains("big.zip"));
+}
+
+/// Test that cancelling at the big file prompt leaves a directory untouched
+#[rstest]
+fn test_big_file_cancel() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let dir = test_env.src.join("dir");
+    fs::create_dir(&dir).unwrap();
+    let test_data = TestData::new(&test_env, Some(&PathBuf::from("dir").join("small.txt")));
+    let big = dir.join("big.zip");
+    fs::File::create(&big)
+        .unwrap()
+        .set_len(rip2::BIG_FILE_THRESHOLD + 1)
+        .unwrap();
+
+    cli_runner(
+        ["--graveyard", test_env.graveyard.to_str().unwrap(), "dir"],
+        Some(&test_env.src),
+    )
+    .write_stdin("c\n")
+    .assert()
+    .success()
+    .stdout(is_match("About to copy a big file").unwrap())
+    .stdout(is_match("Skipping").unwrap());
+
+    assert!(big.exists());
+    assert_eq!(fs::read_to_string(&test_data.path).unwrap(), test_data.data);
+    let grave = util::join_absolute(&test_env.graveyard, dunce::canonicalize(&dir).unwrap());
+    assert!(!grave.exists());
+    let record_contents = fs::read_to_string(test_env.graveyard.join(record::RECORD)).unwrap();
+    assert_eq!(record_contents.lines().count(), 1);
+}
+
 #[rstest]
 fn test_graveyard_subcommand(#[values(false, true)] seance: bool) {
     let _env_lock = aquire_lock();
@@ -708,7 +789,7 @@ fn read_empty_record() {
     let test_env = TestEnv::new();
     let cwd = env::current_dir().unwrap();
     fs::create_dir(&test_env.graveyard).unwrap();
-    let record = record::Record::new(&test_env.graveyard);
+    let record = record::Record::new(&test_env.graveyard, record::RECORD);
     let gravepath = &util::join_absolute(&test_env.graveyard, dunce::canonicalize(cwd).unwrap());
     let result = record.seance(gravepath);
     assert!(result.is_ok());
@@ -876,3 +957,4481 @@ fn many_nest() {
     let new_hash = _hash_dir(&test_env.src);
     assert_eq!(original_hash, new_hash);
 }
+
+/// Test that a note given at bury time is shown by a long seance
+#[rstest]
+fn test_note() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data1 = TestData::new(&test_env, Some(&PathBuf::from("a.txt")));
+    let test_data2 = TestData::new(&test_env, Some(&PathBuf::from("b.txt")));
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            targets: [test_data1.path.clone(), test_data2.path.clone()].to_vec(),
+            graveyard: Some(test_env.graveyard.clone()),
+            note: Some("cleaning up old project X".to_string()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(&test_env.src).unwrap();
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            seance: true,
+            long: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+    env::set_current_dir(cur_dir).unwrap();
+
+    let log_s = String::from_utf8(log).unwrap();
+    assert!(log_s.lines().next().unwrap().ends_with("note"));
+    // The note applies to every target of the invocation
+    assert_eq!(log_s.matches("cleaning up old project X").count(), 2);
+}
+
+#[rstest]
+fn test_stage(#[values(true, false)] commit: bool) {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let staged = TestData::new(&test_env, Some(&PathBuf::from("staged.txt")));
+    let buried = TestData::new(&test_env, Some(&PathBuf::from("buried.txt")));
+
+    for (data, stage) in [(&buried, false), (&staged, true)] {
+        rip2::run(
+            Args {
+                targets: vec![data.path.clone()],
+                graveyard: Some(test_env.graveyard.clone()),
+                stage,
+                ..Args::default()
+            },
+            TestMode,
+            &mut Vec::new(),
+        )
+        .unwrap();
+    }
+    assert!(!staged.path.exists());
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            commit,
+            rollback: !commit,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+    let log_s = String::from_utf8(log).unwrap();
+
+    if commit {
+        assert_eq!(log_s, "Committed 1 pending graves\n");
+        assert!(!staged.path.exists());
+        // Nothing is left to roll back
+        rip2::run(
+            Args {
+                graveyard: Some(test_env.graveyard.clone()),
+                rollback: true,
+                ..Args::default()
+            },
+            TestMode,
+            &mut Vec::new(),
+        )
+        .unwrap();
+        assert!(!staged.path.exists());
+    } else {
+        assert!(log_s.contains("Returned"));
+        assert_eq!(fs::read_to_string(&staged.path).unwrap(), staged.data);
+    }
+    // Graves buried without --stage are untouched
+    assert!(!buried.path.exists());
+}
+
+#[rstest]
+fn test_seance_limit(#[values(None, Some(SortOrder::Newest))] sort: Option<SortOrder>) {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    for name in ["a.txt", "b.txt", "c.txt", "d.txt", "e.txt"] {
+        let test_data = TestData::new(&test_env, Some(&PathBuf::from(name)));
+        rip2::run(
+            Args {
+                targets: vec![test_data.path.clone()],
+                graveyard: Some(test_env.graveyard.clone()),
+                ..Args::default()
+            },
+            TestMode,
+            &mut Vec::new(),
+        )
+        .unwrap();
+        // Keep the deletion times distinct
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(&test_env.src).unwrap();
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            seance: true,
+            limit: Some(2),
+            sort,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+    env::set_current_dir(cur_dir).unwrap();
+
+    let log_s = String::from_utf8(log).unwrap();
+    let lines: Vec<&str> = log_s.lines().collect();
+    // Header, two graves, and the footer
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[3], "... and 3 more");
+    let expected = match sort {
+        None => ["a.txt", "b.txt"],
+        Some(_) => ["e.txt", "d.txt"],
+    };
+    assert!(lines[1].ends_with(expected[0]));
+    assert!(lines[2].ends_with(expected[1]));
+}
+
+/// Test that a custom record name is used for bury, seance, and unbury
+#[rstest]
+fn test_record_name(#[values(false, true)] from_env: bool) {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, None);
+    let record_name = || (!from_env).then(|| "alt.record".to_string());
+    if from_env {
+        env::set_var("RIP_RECORD", "alt.record");
+    }
+
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(&test_env.src).unwrap();
+    let mut bury_log = Vec::new();
+    let bury = rip2::run(
+        Args {
+            targets: vec![test_data.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            record_name: record_name(),
+            ..Args::default()
+        },
+        TestMode,
+        &mut bury_log,
+    );
+    let alt_record = test_env.graveyard.join("alt.record");
+    let buried_record = fs::read_to_string(&alt_record);
+    let mut seance_log = Vec::new();
+    let seance = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            record_name: record_name(),
+            seance: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut seance_log,
+    );
+    let unbury = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            record_name: record_name(),
+            unbury: Some(Vec::new()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    );
+    env::set_current_dir(cur_dir).unwrap();
+    env::remove_var("RIP_RECORD");
+    bury.unwrap();
+    seance.unwrap();
+    unbury.unwrap();
+
+    assert_eq!(buried_record.unwrap().lines().count(), 2);
+    assert_eq!(String::from_utf8(seance_log).unwrap().lines().count(), 2);
+    assert_eq!(fs::read_to_string(&test_data.path).unwrap(), test_data.data);
+    let unburied_record = fs::read_to_string(&alt_record).unwrap();
+    assert!(!unburied_record.contains(&*test_data.path.to_string_lossy()));
+    // The default record is never created
+    assert!(!test_env.graveyard.join(record::RECORD).exists());
+}
+
+/// Test that burying a second hardlink to a buried file is detected
+#[cfg(unix)]
+#[rstest]
+fn test_duplicate_hardlinks(
+    #[values(DuplicatePolicy::Warn, DuplicatePolicy::Skip)] policy: DuplicatePolicy,
+) {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, Some(&PathBuf::from("first")));
+    let link = test_env.src.join("second");
+    fs::hard_link(&test_data.path, &link).unwrap();
+
+    let mut logs = Vec::new();
+    for target in [&test_data.path, &link] {
+        let mut log = Vec::new();
+        rip2::run(
+            Args {
+                targets: vec![target.clone()],
+                graveyard: Some(test_env.graveyard.clone()),
+                duplicates: policy,
+                ..Args::default()
+            },
+            TestMode,
+            &mut log,
+        )
+        .unwrap();
+        logs.push(String::from_utf8(log).unwrap());
+    }
+
+    assert!(logs[0].is_empty());
+    let record = fs::read_to_string(test_env.graveyard.join(record::RECORD)).unwrap();
+    match policy {
+        DuplicatePolicy::Warn => {
+            assert!(logs[1].contains("is the same file as"));
+            assert!(!link.exists());
+            assert_eq!(record.lines().count(), 3);
+        }
+        DuplicatePolicy::Skip => {
+            assert!(logs[1].starts_with("Skipping"));
+            assert!(link.exists());
+            assert_eq!(record.lines().count(), 2);
+        }
+        DuplicatePolicy::Allow => unreachable!(),
+    }
+}
+
+/// Test that a file reusing the inode number recorded for a copied grave
+/// isn't taken for a duplicate, while a hardlink buried in the same run is
+#[cfg(unix)]
+#[rstest]
+fn test_duplicate_inode_reuse() {
+    use std::os::unix::fs::MetadataExt;
+
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let copied = TestData::new(&test_env, Some(&PathBuf::from("copied")));
+    env::set_var("__RIP_ALLOW_RENAME", "false");
+    let result = rip2::run(
+        Args {
+            targets: vec![copied.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    );
+    env::remove_var("__RIP_ALLOW_RENAME");
+    result.unwrap();
+
+    // An older record stored the inode of the original, which a new file
+    // may now have
+    let reused = TestData::new(&test_env, Some(&PathBuf::from("reused")));
+    let metadata = fs::metadata(&reused.path).unwrap();
+    let record_path = test_env.graveyard.join(record::RECORD);
+    let contents = fs::read_to_string(&record_path).unwrap();
+    fs::write(
+        &record_path,
+        format!(
+            "{}\tdev={}\tino={}\n",
+            contents.trim_end(),
+            metadata.dev(),
+            metadata.ino()
+        ),
+    )
+    .unwrap();
+    let link = test_env.src.join("link");
+    fs::hard_link(&reused.path, &link).unwrap();
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            targets: vec![reused.path.clone(), link.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            duplicates: DuplicatePolicy::Skip,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+
+    let log = String::from_utf8(log).unwrap();
+    assert!(!reused.path.exists());
+    assert!(link.exists());
+    assert_eq!(log.matches("Skipping").count(), 1);
+    assert!(log.contains(&format!(
+        "Skipping {}",
+        dunce::canonicalize(&link).unwrap().display()
+    )));
+}
+
+/// Test that date-partitioned graves are found by seance and unbury
+#[rstest]
+fn test_date_partition() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, None);
+
+    rip2::run(
+        Args {
+            targets: vec![test_data.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            date_partition: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    let record = record::Record::new(&test_env.graveyard, record::RECORD);
+    let grave = record.get_last_bury().unwrap();
+    let contents = fs::read_to_string(test_env.graveyard.join(record::RECORD)).unwrap();
+    let entry = record::RecordItem::new(contents.lines().nth(1).unwrap());
+    let partition = entry.partition().unwrap();
+    assert!(chrono::NaiveDate::parse_from_str(partition, "%Y-%m-%d").is_ok());
+    assert_eq!(
+        grave,
+        util::join_absolute(
+            test_env.graveyard.join(partition),
+            dunce::canonicalize(&test_env.src)
+                .unwrap()
+                .join("test_file.txt")
+        )
+    );
+
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(&test_env.src).unwrap();
+    let mut seance_log = Vec::new();
+    let seance = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            seance: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut seance_log,
+    );
+    let unbury = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            seance: true,
+            unbury: Some(Vec::new()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    );
+    env::set_current_dir(cur_dir).unwrap();
+    seance.unwrap();
+    unbury.unwrap();
+
+    assert!(String::from_utf8(seance_log)
+        .unwrap()
+        .contains(&*grave.to_string_lossy()));
+    assert_eq!(fs::read_to_string(&test_data.path).unwrap(), test_data.data);
+}
+
+/// Test that --flat buries at the graveyard root by file name, renaming on
+/// a collision, and that unbury still restores the original paths
+#[rstest]
+fn test_flat() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let first = TestData::new(&test_env, None);
+    let nested = test_env.src.join("nested");
+    fs::create_dir(&nested).unwrap();
+    let second = nested.join("test_file.txt");
+    fs::write(&second, "second").unwrap();
+
+    for target in [&first.path, &second] {
+        rip2::run(
+            Args {
+                targets: vec![target.clone()],
+                graveyard: Some(test_env.graveyard.clone()),
+                flat: true,
+                ..Args::default()
+            },
+            TestMode,
+            &mut Vec::new(),
+        )
+        .unwrap();
+    }
+    let record = record::Record::new(&test_env.graveyard, record::RECORD);
+    let grave = record.get_last_bury().unwrap();
+    assert_eq!(grave.parent().unwrap(), test_env.graveyard);
+    assert_ne!(grave, test_env.graveyard.join("test_file.txt"));
+    assert_eq!(
+        fs::read_to_string(test_env.graveyard.join("test_file.txt")).unwrap(),
+        first.data
+    );
+
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            unbury: Some(vec![grave, test_env.graveyard.join("test_file.txt")]),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+    assert_eq!(fs::read_to_string(&first.path).unwrap(), first.data);
+    assert_eq!(fs::read_to_string(&second).unwrap(), "second");
+}
+
+/// Test that a bury under each --grave-layout shows up in seance and
+/// unburies back to its original path
+#[rstest]
+fn test_grave_layout(
+    #[values(
+        GraveLayout::Mirror,
+        GraveLayout::Flat,
+        GraveLayout::Date,
+        GraveLayout::Hash
+    )]
+    layout: GraveLayout,
+) {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    fs::create_dir(test_env.src.join("sub")).unwrap();
+    let test_data = TestData::new(&test_env, Some(&PathBuf::from("sub").join("file.txt")));
+
+    rip2::run(
+        Args {
+            targets: vec![test_data.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            grave_layout: Some(layout),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+    let record = record::Record::new(&test_env.graveyard, record::RECORD);
+    let grave = record.get_last_bury().unwrap();
+    let source = dunce::canonicalize(&test_env.src)
+        .unwrap()
+        .join("sub")
+        .join("file.txt");
+    let relative = grave.strip_prefix(&test_env.graveyard).unwrap();
+    match layout {
+        GraveLayout::Mirror => assert_eq!(grave, util::join_absolute(&test_env.graveyard, &source)),
+        GraveLayout::Flat => assert_eq!(relative, PathBuf::from("file.txt")),
+        GraveLayout::Date => assert!(grave.ends_with(source.strip_prefix("/").unwrap())),
+        GraveLayout::Hash => assert_eq!(relative.components().count(), 2),
+    }
+    assert_eq!(fs::read_to_string(&grave).unwrap(), test_data.data);
+
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(&test_env.src).unwrap();
+    let mut log = Vec::new();
+    let seance = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            seance: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    );
+    let unbury = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            unbury: Some(Vec::new()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    );
+    env::set_current_dir(cur_dir).unwrap();
+    seance.unwrap();
+    unbury.unwrap();
+
+    assert!(String::from_utf8(log)
+        .unwrap()
+        .contains(&*grave.to_string_lossy()));
+    assert!(!grave.exists());
+    assert_eq!(fs::read_to_string(&test_data.path).unwrap(), test_data.data);
+}
+
+/// Test that --find lists the graves whose original path matches
+#[rstest]
+fn test_find(#[values(false, true)] regex: bool) {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let names = ["report_2023.txt", "report_2024.txt", "notes.txt"];
+    for name in names {
+        let test_data = TestData::new(&test_env, Some(&PathBuf::from(name)));
+        rip2::run(
+            Args {
+                targets: vec![test_data.path.clone()],
+                graveyard: Some(test_env.graveyard.clone()),
+                ..Args::default()
+            },
+            TestMode,
+            &mut Vec::new(),
+        )
+        .unwrap();
+    }
+
+    let pattern = if regex { r"report_\d+\.txt$" } else { "report" };
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(&test_env.src).unwrap();
+    let mut log = Vec::new();
+    let result = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            find: Some(pattern.to_string()),
+            regex,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    );
+    env::set_current_dir(cur_dir).unwrap();
+    result.unwrap();
+
+    let log_s = String::from_utf8(log).unwrap();
+    let lines: Vec<&str> = log_s.lines().skip(1).collect();
+    assert_eq!(lines.len(), 2);
+    for (line, name) in lines.iter().zip(&names[..2]) {
+        let columns: Vec<&str> = line.split('\t').collect();
+        assert!(columns[1].ends_with(name));
+        assert!(columns[2].starts_with(&*test_env.graveyard.to_string_lossy()));
+    }
+}
+
+/// Test that --move-to moves into a directory, renaming on conflicts
+#[rstest]
+fn test_move_to() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, Some(&PathBuf::from("file.txt")));
+    let dir = test_env.src.join("dir");
+    fs::create_dir(&dir).unwrap();
+    fs::write(dir.join("file.txt"), "existing").unwrap();
+
+    rip2::run(
+        Args {
+            targets: vec![test_data.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            move_to: Some(dir.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    assert!(!test_data.path.exists());
+    assert_eq!(
+        fs::read_to_string(dir.join("file.txt")).unwrap(),
+        "existing"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("file.txt~1")).unwrap(),
+        test_data.data
+    );
+    // Nothing is buried
+    assert!(!test_env.graveyard.exists());
+}
+
+/// Test that --dedup hardlinks identical graves together, and that both
+/// still unbury to their original paths
+#[cfg(unix)]
+#[rstest]
+fn test_dedup() {
+    use std::os::unix::fs::MetadataExt;
+    use std::time::{Duration, SystemTime};
+
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let contents = "regenerated output\n".repeat(10);
+    let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    let dirs = ["a", "b", "c"];
+    let targets: Vec<PathBuf> = dirs
+        .iter()
+        .map(|dir| {
+            fs::create_dir(test_env.src.join(dir)).unwrap();
+            let path = test_env.src.join(dir).join("out.txt");
+            fs::write(&path, &contents).unwrap();
+            path
+        })
+        .collect();
+    // The last copy was written at another time, so it is kept apart
+    util::set_mtime(&targets[0], mtime).unwrap();
+    util::set_mtime(&targets[1], mtime).unwrap();
+    rip2::run(
+        Args {
+            targets: targets.clone(),
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+    let src = dunce::canonicalize(&test_env.src).unwrap();
+    let graves: Vec<PathBuf> = dirs
+        .iter()
+        .map(|dir| util::join_absolute(&test_env.graveyard, src.join(dir).join("out.txt")))
+        .collect();
+    let inode = |path: &PathBuf| fs::metadata(path).unwrap().ino();
+    assert_ne!(inode(&graves[0]), inode(&graves[1]));
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            dedup: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        "Deduplicated 1 files, reclaimed 190 B\n"
+    );
+    assert_eq!(inode(&graves[0]), inode(&graves[1]));
+    assert_ne!(inode(&graves[0]), inode(&graves[2]));
+
+    // Editing a restored copy leaves the grave it was linked to alone
+    let unbury = |grave: &PathBuf| {
+        rip2::run(
+            Args {
+                graveyard: Some(test_env.graveyard.clone()),
+                unbury: Some(vec![grave.clone()]),
+                ..Args::default()
+            },
+            TestMode,
+            &mut Vec::new(),
+        )
+        .unwrap();
+    };
+    unbury(&graves[0]);
+    assert_eq!(fs::read_to_string(&targets[0]).unwrap(), contents);
+    fs::write(&targets[0], "edited").unwrap();
+    unbury(&graves[1]);
+    assert_eq!(fs::read_to_string(&targets[1]).unwrap(), contents);
+    assert_eq!(
+        fs::metadata(&targets[1]).unwrap().modified().unwrap(),
+        mtime
+    );
+    assert_eq!(fs::read_to_string(&targets[0]).unwrap(), "edited");
+
+    // A restored copy is no longer taken for the grave it was linked to
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            targets: vec![targets[1].clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            duplicates: DuplicatePolicy::Skip,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+    assert!(String::from_utf8(log).unwrap().is_empty());
+    assert!(!targets[1].exists());
+}
+
+/// Test that --expire removes exactly the graves past their expiry
+#[rstest]
+fn test_expire() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let mut graves = Vec::new();
+    for (name, expire_after) in [
+        ("due.txt", Some(0)),
+        ("later.txt", Some(3600)),
+        ("never.txt", None),
+    ] {
+        let test_data = TestData::new(&test_env, Some(&PathBuf::from(name)));
+        rip2::run(
+            Args {
+                targets: vec![test_data.path.clone()],
+                graveyard: Some(test_env.graveyard.clone()),
+                expire_after: expire_after.map(std::time::Duration::from_secs),
+                ..Args::default()
+            },
+            TestMode,
+            &mut Vec::new(),
+        )
+        .unwrap();
+        graves.push(util::join_absolute(
+            &test_env.graveyard,
+            dunce::canonicalize(&test_env.src).unwrap().join(name),
+        ));
+    }
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            expire: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        format!("Expired {}\nFreed 100 B\n", graves[0].display())
+    );
+    assert!(!graves[0].exists());
+    assert!(graves[1].exists());
+    assert!(graves[2].exists());
+    let record_contents = fs::read_to_string(test_env.graveyard.join(record::RECORD)).unwrap();
+    assert!(!record_contents.contains("due.txt"));
+    assert!(record_contents.contains("later.txt"));
+}
+
+/// Test that unbury matches grave paths ignoring case, but only on
+/// filesystems that ignore case
+#[rstest]
+fn test_unbury_case_insensitive() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, Some(&PathBuf::from("file.txt")));
+    rip2::run(
+        Args {
+            targets: vec![test_data.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+    let grave = util::join_absolute(
+        &test_env.graveyard,
+        dunce::canonicalize(test_data.path.parent().unwrap()).unwrap(),
+    );
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            unbury: Some(vec![grave.join("FILE.TXT")]),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+
+    let case_insensitive = util::is_case_insensitive(&test_env.graveyard);
+    #[cfg(target_os = "linux")]
+    assert!(!case_insensitive);
+    #[cfg(target_os = "windows")]
+    assert!(case_insensitive);
+    if case_insensitive {
+        assert_eq!(fs::read_to_string(&test_data.path).unwrap(), test_data.data);
+        assert!(!grave.join("file.txt").exists());
+    } else {
+        assert!(log.is_empty());
+        assert!(!test_data.path.exists());
+        assert!(grave.join("file.txt").exists());
+    }
+}
+
+/// Test that graveyards under XDG_DATA_DIRS are searched by seance and unbury
+#[rstest]
+fn test_data_dirs_graveyards() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, Some(&PathBuf::from("old.txt")));
+    let data_dir = test_env.src.parent().unwrap().join("share");
+    let old_graveyard = data_dir.join("graveyard");
+
+    // Bury into the older graveyard directly
+    rip2::run(
+        Args {
+            targets: vec![test_data.path.clone()],
+            graveyard: Some(old_graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    let saved = env::var_os("XDG_DATA_DIRS");
+    let missing = test_env.src.parent().unwrap().join("missing");
+    env::set_var(
+        "XDG_DATA_DIRS",
+        env::join_paths([missing, data_dir]).unwrap(),
+    );
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(&test_env.src).unwrap();
+
+    let mut seance_log = Vec::new();
+    let seance = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            seance: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut seance_log,
+    );
+    let mut unbury_log = Vec::new();
+    let unbury = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            seance: true,
+            unbury: Some(Vec::new()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut unbury_log,
+    );
+
+    env::set_current_dir(cur_dir).unwrap();
+    match saved {
+        Some(value) => env::set_var("XDG_DATA_DIRS", value),
+        None => env::remove_var("XDG_DATA_DIRS"),
+    }
+    seance.unwrap();
+    unbury.unwrap();
+
+    let seance_s = String::from_utf8(seance_log).unwrap();
+    assert!(seance_s.contains(&*old_graveyard.to_string_lossy()));
+    assert!(String::from_utf8(unbury_log).unwrap().contains("Returned"));
+    assert_eq!(fs::read_to_string(&test_data.path).unwrap(), test_data.data);
+    // Nothing is ever buried into the primary graveyard's record
+    let record = fs::read_to_string(test_env.graveyard.join(record::RECORD)).unwrap();
+    assert_eq!(record.lines().count(), 1);
+}
+
+/// Test that a read-only graveyard is reported before anything is moved
+#[cfg(unix)]
+#[rstest]
+fn test_readonly_graveyard() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, None);
+    fs::create_dir(&test_env.graveyard).unwrap();
+    fs::set_permissions(&test_env.graveyard, fs::Permissions::from_mode(0o555)).unwrap();
+
+    // Privileged users can write anyway, so there is nothing to detect
+    if fs::File::create(test_env.graveyard.join("probe")).is_ok() {
+        return;
+    }
+
+    let mut log = Vec::new();
+    let err = rip2::run(
+        Args {
+            targets: [test_data.path.clone()].to_vec(),
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("is not writable"));
+    assert!(err.to_string().contains("--graveyard"));
+    // The target is left untouched
+    assert!(test_data.path.exists());
+
+    fs::set_permissions(&test_env.graveyard, fs::Permissions::from_mode(0o700)).unwrap();
+}
+
+/// Test that listing graves works in a graveyard that can't be written
+/// to, while burying into it still fails
+#[cfg(target_os = "linux")]
+#[rstest]
+fn test_readonly_graveyard_seance() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let buried = TestData::new(&test_env, Some(&PathBuf::from("buried.txt")));
+    let kept = TestData::new(&test_env, Some(&PathBuf::from("kept.txt")));
+    rip2::run(
+        Args {
+            targets: vec![buried.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    // Even root can't create files in an immutable directory
+    let chattr = |flag: &str| {
+        std::process::Command::new("chattr")
+            .arg(flag)
+            .arg(&test_env.graveyard)
+            .status()
+            .is_ok_and(|status| status.success())
+    };
+    if !chattr("+i") {
+        println!("Skipping read-only graveyard test: couldn't set the flag");
+        return;
+    }
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(&test_env.src).unwrap();
+    let mut log = Vec::new();
+    let seance = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            seance: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    );
+    env::set_current_dir(cur_dir).unwrap();
+    let bury = rip2::run(
+        Args {
+            targets: vec![kept.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    );
+    chattr("-i");
+
+    seance.unwrap();
+    assert!(String::from_utf8(log).unwrap().contains("buried.txt"));
+    assert!(bury.unwrap_err().to_string().contains("is not writable"));
+    assert!(kept.path.exists());
+}
+
+/// Test that seance of a read-only graveyard with no record yet lists
+/// nothing instead of trying to create the record
+#[cfg(target_os = "linux")]
+#[rstest]
+fn test_readonly_graveyard_without_record() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    fs::create_dir(&test_env.graveyard).unwrap();
+    let chattr = |flag: &str| {
+        std::process::Command::new("chattr")
+            .arg(flag)
+            .arg(&test_env.graveyard)
+            .status()
+            .is_ok_and(|status| status.success())
+    };
+    if !chattr("+i") {
+        println!("Skipping read-only graveyard test: couldn't set the flag");
+        return;
+    }
+    let output = cli_runner(
+        ["--graveyard", test_env.graveyard.to_str().unwrap(), "-s"],
+        Some(&test_env.src),
+    )
+    .output()
+    .unwrap();
+    chattr("-i");
+
+    assert!(output.status.success());
+    // Only the column headings
+    assert_eq!(String::from_utf8(output.stdout).unwrap().lines().count(), 1);
+    assert!(!test_env.graveyard.join(record::RECORD).exists());
+}
+
+/// Test that a graveyard path pointing at a file fails early and clearly
+#[rstest]
+fn test_graveyard_is_file() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, None);
+    fs::write(&test_env.graveyard, "not a graveyard").unwrap();
+
+    let err = rip2::run(
+        Args {
+            targets: vec![test_data.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotADirectory);
+    assert!(err.to_string().contains("Graveyard path is a file"));
+    assert!(test_data.path.exists());
+    assert_eq!(
+        fs::read_to_string(&test_env.graveyard).unwrap(),
+        "not a graveyard"
+    );
+}
+
+/// Test that --relative-to shows graves under the base relative to it,
+/// and graves elsewhere in full
+#[rstest]
+fn test_seance_relative_to() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let tmp = dunce::canonicalize(test_env.src.parent().unwrap()).unwrap();
+    fs::create_dir(test_env.src.join("sub")).unwrap();
+    let inside = TestData::new(&test_env, Some(&PathBuf::from("sub").join("inside.txt")));
+    fs::create_dir(tmp.join("other")).unwrap();
+    let outside = tmp.join("other").join("outside.txt");
+    fs::write(&outside, "outside").unwrap();
+
+    rip2::run(
+        Args {
+            targets: vec![inside.path.clone(), outside.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(&tmp).unwrap();
+    let mut log = Vec::new();
+    let result = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            seance: true,
+            relative_to: Some(PathBuf::from("data")),
+            sort: Some(SortOrder::Path),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    );
+    env::set_current_dir(cur_dir).unwrap();
+    result.unwrap();
+
+    let paths: Vec<String> = String::from_utf8(log)
+        .unwrap()
+        .lines()
+        .skip(1)
+        .map(|line| line.splitn(3, '\t').nth(2).unwrap().to_string())
+        .collect();
+    assert_eq!(
+        paths,
+        vec![
+            PathBuf::from("sub")
+                .join("inside.txt")
+                .display()
+                .to_string(),
+            outside.display().to_string(),
+        ]
+    );
+}
+
+/// Test that a relative path printed by seance can be passed straight to
+/// unbury, from the same directory or with the same --relative-to
+#[rstest]
+fn test_unbury_relative_seance_path(#[values(false, true)] with_base: bool) {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    fs::create_dir(test_env.src.join("sub")).unwrap();
+    let test_data = TestData::new(&test_env, Some(&PathBuf::from("sub").join("inside.txt")));
+    let other = TestData::new(&test_env, Some(&PathBuf::from("other.txt")));
+
+    rip2::run(
+        Args {
+            targets: vec![test_data.path.clone(), other.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    let (dir, relative_to) = if with_base {
+        (
+            test_env.src.parent().unwrap().to_path_buf(),
+            PathBuf::from("data"),
+        )
+    } else {
+        (test_env.src.clone(), PathBuf::from("."))
+    };
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(&dir).unwrap();
+    let mut log = Vec::new();
+    let seance = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            seance: true,
+            relative_to: Some(relative_to.clone()),
+            sort: Some(SortOrder::Path),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    );
+    let printed = String::from_utf8(log)
+        .unwrap()
+        .lines()
+        .find(|line| line.ends_with("inside.txt"))
+        .map(|line| PathBuf::from(line.splitn(3, '\t').nth(2).unwrap()));
+    let unbury = printed.as_ref().map(|path| {
+        rip2::run(
+            Args {
+                graveyard: Some(test_env.graveyard.clone()),
+                unbury: Some(vec![path.clone()]),
+                relative_to: with_base.then_some(relative_to),
+                ..Args::default()
+            },
+            TestMode,
+            &mut Vec::new(),
+        )
+    });
+    env::set_current_dir(cur_dir).unwrap();
+    seance.unwrap();
+
+    assert_eq!(printed.unwrap(), PathBuf::from("sub").join("inside.txt"));
+    unbury.unwrap().unwrap();
+    assert_eq!(fs::read_to_string(&test_data.path).unwrap(), test_data.data);
+    assert!(!other.path.exists());
+}
+
+/// Test that the binary takes the paths after -u as the graves to
+/// restore, rather than burying them or restoring the last grave
+#[rstest]
+fn test_cli_unbury_paths(#[values(false, true)] after_flag: bool) {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    fs::create_dir(test_env.src.join("sub")).unwrap();
+    let test_data = TestData::new(&test_env, Some(&PathBuf::from("sub").join("inside.txt")));
+    let other = TestData::new(&test_env, Some(&PathBuf::from("other.txt")));
+    let graveyard = test_env.graveyard.to_str().unwrap();
+    cli_runner(
+        ["--graveyard", graveyard, "sub/inside.txt", "other.txt"],
+        Some(&test_env.src),
+    )
+    .assert()
+    .success();
+
+    let args = match after_flag {
+        true => ["--graveyard", graveyard, "-u", "sub/inside.txt"],
+        false => ["--graveyard", graveyard, "sub/inside.txt", "-u"],
+    };
+    cli_runner(args, Some(&test_env.src)).assert().success();
+
+    assert_eq!(fs::read_to_string(&test_data.path).unwrap(), test_data.data);
+    assert!(!other.path.exists());
+}
+
+/// Test that a long seance labels directories and files
+#[rstest]
+fn test_seance_types() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    fs::create_dir(test_env.src.join("dir")).unwrap();
+    TestData::new(&test_env, Some(&PathBuf::from("dir").join("inner.txt")));
+    let test_data = TestData::new(&test_env, Some(&PathBuf::from("file.txt")));
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            targets: [test_env.src.join("dir"), test_data.path.clone()].to_vec(),
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(&test_env.src).unwrap();
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            seance: true,
+            long: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+    env::set_current_dir(cur_dir).unwrap();
+
+    let log_s = String::from_utf8(log).unwrap();
+    let dir_line = log_s.lines().find(|l| l.ends_with("dir\t")).unwrap();
+    assert!(dir_line.contains("[dir]\t100 B"));
+    let file_line = log_s.lines().find(|l| l.ends_with("file.txt\t")).unwrap();
+    assert!(file_line.contains("[file]\t100 B"));
+}
+
+/// Test that unbury with --backup keeps the file occupying the original path
+#[rstest]
+fn test_unbury_backup() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, None);
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            targets: [test_data.path.clone()].to_vec(),
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+
+    // Occupy the original path with new content
+    let replacement = TestData::new(&test_env, None);
+    assert_ne!(replacement.data, test_data.data);
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            unbury: Some(Vec::new()),
+            backup: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+    let log_s = String::from_utf8(log).unwrap();
+    assert!(log_s.contains("Backed up"));
+
+    let backup = test_env.src.join("test_file.txt.bak");
+    assert_eq!(fs::read_to_string(backup).unwrap(), replacement.data);
+    assert_eq!(fs::read_to_string(&test_data.path).unwrap(), test_data.data);
+}
+
+/// Test that the doctor flags a corrupted record
+#[rstest]
+fn test_doctor() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, None);
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            targets: [test_data.path.clone()].to_vec(),
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+
+    let doctor_args = || Args {
+        graveyard: Some(test_env.graveyard.clone()),
+        doctor: true,
+        ..Args::default()
+    };
+
+    let mut log = Vec::new();
+    rip2::run(doctor_args(), TestMode, &mut log).unwrap();
+    let log_s = String::from_utf8(log).unwrap();
+    assert!(log_s.contains("record parses cleanly (1 entries)"));
+    assert!(!log_s.contains("FAIL"));
+    // Probed on the graveyard itself
+    #[cfg(target_os = "linux")]
+    assert!(
+        log_s.contains(match util::xattrs_supported(&test_env.graveyard).unwrap() {
+            true => "INFO  extended attributes: supported",
+            false => "INFO  extended attributes: unsupported",
+        })
+    );
+
+    // Corrupt the record
+    let mut record_file = fs::OpenOptions::new()
+        .append(true)
+        .open(test_env.graveyard.join(record::RECORD))
+        .unwrap();
+    writeln!(record_file, "garbage").unwrap();
+
+    let mut log = Vec::new();
+    let result = rip2::run(doctor_args(), TestMode, &mut log);
+    assert!(result.is_err());
+    let log_s = String::from_utf8(log).unwrap();
+    assert!(log_s.contains("FAIL  record has 1 malformed lines"));
+}
+
+/// Test that names starting with a dash can be buried after `--`
+#[rstest]
+fn test_dash_filenames() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let names = ["-r", "--trash", "-"];
+    for name in names {
+        TestData::new(&test_env, Some(&PathBuf::from(name)));
+    }
+
+    let mut args = vec!["--graveyard", test_env.graveyard.to_str().unwrap(), "--"];
+    args.extend(names);
+    cli_runner(args, Some(&test_env.src)).assert().success();
+
+    for name in names {
+        assert!(!test_env.src.join(name).exists());
+        let expected_graveyard_path = util::join_absolute(
+            &test_env.graveyard,
+            dunce::canonicalize(&test_env.src).unwrap().join(name),
+        );
+        assert!(expected_graveyard_path.exists());
+    }
+
+    // Without the separator, they are parsed as flags
+    TestData::new(&test_env, Some(&PathBuf::from("--trash")));
+    cli_runner(
+        [
+            "--graveyard",
+            test_env.graveyard.to_str().unwrap(),
+            "--trash",
+        ],
+        Some(&test_env.src),
+    )
+    .assert()
+    .failure();
+    assert!(test_env.src.join("--trash").exists());
+}
+
+/// Test that special mode bits survive a bury and unbury which copy
+#[cfg(unix)]
+#[rstest]
+fn test_special_mode_bits() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let dir = test_env.src.join("shared");
+    fs::create_dir(&dir).unwrap();
+    let test_data = TestData::new(&test_env, Some(&PathBuf::from("shared").join("tool")));
+    fs::set_permissions(&test_data.path, fs::Permissions::from_mode(0o4755)).unwrap();
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o2775)).unwrap();
+
+    // Force the copy path
+    env::set_var("__RIP_ALLOW_RENAME", "false");
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            targets: [dir.clone()].to_vec(),
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+    let grave = util::join_absolute(
+        &test_env.graveyard,
+        dunce::canonicalize(&test_env.src).unwrap(),
+    )
+    .join("shared");
+    assert_eq!(
+        fs::metadata(&grave).unwrap().permissions().mode() & 0o7777,
+        0o2775
+    );
+
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            unbury: Some(Vec::new()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+    env::remove_var("__RIP_ALLOW_RENAME");
+
+    let file_mode = fs::metadata(&test_data.path).unwrap().permissions().mode();
+    assert_eq!(file_mode & 0o7777, 0o4755);
+    let dir_mode = fs::metadata(&dir).unwrap().permissions().mode();
+    assert_eq!(dir_mode & 0o7777, 0o2775);
+}
+
+/// Test that file and directory mtimes survive a bury which copies
+#[cfg(unix)]
+#[rstest]
+fn test_preserve_mtimes() {
+    use std::time::{Duration, SystemTime};
+
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let dir = test_env.src.join("tree");
+    fs::create_dir_all(dir.join("nested")).unwrap();
+    let test_data = TestData::new(
+        &test_env,
+        Some(&PathBuf::from("tree").join("nested").join("leaf.txt")),
+    );
+
+    let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    for path in [&test_data.path, &dir.join("nested"), &dir] {
+        util::set_mtime(path, old).unwrap();
+    }
+
+    // Force the copy path
+    env::set_var("__RIP_ALLOW_RENAME", "false");
+    let result = rip2::run(
+        Args {
+            targets: vec![dir.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    );
+    env::remove_var("__RIP_ALLOW_RENAME");
+    result.unwrap();
+
+    let grave = util::join_absolute(
+        &test_env.graveyard,
+        dunce::canonicalize(&test_env.src).unwrap(),
+    )
+    .join("tree");
+    for path in [
+        grave.clone(),
+        grave.join("nested"),
+        grave.join("nested").join("leaf.txt"),
+    ] {
+        let mtime = fs::metadata(&path).unwrap().modified().unwrap();
+        assert_eq!(mtime, old, "{}", path.display());
+    }
+}
+
+/// Test that the birth time is recorded and, where the platform allows
+/// setting it, survives a bury and unbury which copy
+#[rstest]
+fn test_crtime_round_trip() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, None);
+    let created = fs::metadata(&test_data.path).unwrap().created();
+    // Make sure a copy would get a different birth time
+    std::thread::sleep(std::time::Duration::from_millis(20));
+
+    // Force the copy path
+    env::set_var("__RIP_ALLOW_RENAME", "false");
+    let bury = rip2::run(
+        Args {
+            targets: vec![test_data.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    );
+    let contents = fs::read_to_string(test_env.graveyard.join(record::RECORD));
+    let unbury = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            unbury: Some(Vec::new()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    );
+    env::remove_var("__RIP_ALLOW_RENAME");
+    bury.unwrap();
+    unbury.unwrap();
+
+    let contents = contents.unwrap();
+    let entry = record::RecordItem::new(contents.lines().nth(1).unwrap());
+    assert_eq!(fs::read_to_string(&test_data.path).unwrap(), test_data.data);
+    match created {
+        Ok(created) => {
+            assert_eq!(entry.crtime(), Some(created));
+            if cfg!(any(target_os = "macos", target_os = "windows")) {
+                let restored = fs::metadata(&test_data.path).unwrap().created().unwrap();
+                assert_eq!(restored, created);
+            }
+        }
+        // Nothing to record without filesystem support
+        Err(_) => assert_eq!(entry.crtime(), None),
+    }
+}
+
+/// Test that graves can still be unburied after relocating the graveyard
+#[rstest]
+fn test_relocate() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data1 = TestData::new(&test_env, Some(&PathBuf::from("a.txt")));
+    let test_data2 = TestData::new(&test_env, Some(&PathBuf::from("b.txt")));
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            targets: [test_data1.path.clone(), test_data2.path.clone()].to_vec(),
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+
+    let new_graveyard = test_env.graveyard.with_file_name("new_graveyard");
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            relocate: Some(new_graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+    assert!(!test_env.graveyard.exists());
+
+    let record_contents = fs::read_to_string(new_graveyard.join(record::RECORD)).unwrap();
+    assert!(!record_contents.contains(test_env.graveyard.to_str().unwrap()));
+
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(&test_env.src).unwrap();
+    let result = rip2::run(
+        Args {
+            graveyard: Some(new_graveyard.clone()),
+            unbury: Some(Vec::new()),
+            seance: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    );
+    env::set_current_dir(cur_dir).unwrap();
+    result.unwrap();
+
+    assert_eq!(
+        fs::read_to_string(&test_data1.path).unwrap(),
+        test_data1.data
+    );
+    assert_eq!(
+        fs::read_to_string(&test_data2.path).unwrap(),
+        test_data2.data
+    );
+}
+
+/// Test that relocating the graveyard into itself is refused before
+/// anything is moved
+#[rstest]
+fn test_relocate_into_itself() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, None);
+    rip2::run(
+        Args {
+            targets: vec![test_data.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+    let record_contents = fs::read_to_string(test_env.graveyard.join(record::RECORD)).unwrap();
+
+    let inner = test_env.graveyard.join("inner").join("deeper");
+    let err = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            relocate: Some(inner.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap_err();
+
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert!(err.to_string().contains("into itself"));
+    assert!(!test_env.graveyard.join("inner").exists());
+    assert_eq!(
+        fs::read_to_string(test_env.graveyard.join(record::RECORD)).unwrap(),
+        record_contents
+    );
+}
+
+/// Test that decomposing a room leaves other rooms alone
+#[rstest]
+fn test_decompose_room() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let screenshot = TestData::new(&test_env, Some(&PathBuf::from("shot.png")));
+    let log_file = TestData::new(&test_env, Some(&PathBuf::from("app.log")));
+
+    let mut log = Vec::new();
+    for (test_data, room) in [(&screenshot, "screenshots"), (&log_file, "logs")] {
+        rip2::run(
+            Args {
+                targets: [test_data.path.clone()].to_vec(),
+                graveyard: Some(test_env.graveyard.clone()),
+                room: Some(room.to_string()),
+                ..Args::default()
+            },
+            TestMode,
+            &mut log,
+        )
+        .unwrap();
+    }
+    assert!(test_env.graveyard.join("@screenshots").exists());
+    assert!(test_env.graveyard.join("@logs").exists());
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            decompose: true,
+            room: Some("screenshots".to_string()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+    let log_s = String::from_utf8(log).unwrap();
+    assert!(log_s.contains("Really unlink 1 graves in"));
+
+    assert!(!test_env.graveyard.join("@screenshots").exists());
+    let record_contents = fs::read_to_string(test_env.graveyard.join(record::RECORD)).unwrap();
+    assert!(!record_contents.contains("shot.png"));
+    assert!(record_contents.contains("app.log"));
+
+    // The other room can still be restored
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            unbury: Some(Vec::new()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+    assert_eq!(fs::read_to_string(&log_file.path).unwrap(), log_file.data);
+}
+
+/// Test unburying graves into a tar archive
+#[rstest]
+fn test_unbury_to_archive() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    fs::create_dir(test_env.src.join("dir")).unwrap();
+    let test_data1 = TestData::new(&test_env, Some(&PathBuf::from("a.txt")));
+    let test_data2 = TestData::new(&test_env, Some(&PathBuf::from("dir").join("b.txt")));
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            targets: [test_data1.path.clone(), test_env.src.join("dir")].to_vec(),
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+
+    let archive_path = test_env.src.join("out.tar");
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(&test_env.src).unwrap();
+    let result = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            unbury: Some(Vec::new()),
+            seance: true,
+            archive: Some(archive_path.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    );
+    env::set_current_dir(cur_dir).unwrap();
+    result.unwrap();
+
+    // Nothing was written back to disk, and the graves are gone
+    assert!(!test_data1.path.exists());
+    assert!(!test_env.src.join("dir").exists());
+    let record_contents = fs::read_to_string(test_env.graveyard.join(record::RECORD)).unwrap();
+    assert!(!record_contents.contains("a.txt"));
+
+    let entries = archive::read_entries(fs::File::open(&archive_path).unwrap()).unwrap();
+    let find = |path: &PathBuf| {
+        let name = archive::archive_name(&dunce::canonicalize(&test_env.src).unwrap().join(path));
+        entries.iter().find(|e| e.path == name).unwrap()
+    };
+    assert_eq!(
+        find(&PathBuf::from("a.txt")).data,
+        test_data1.data.as_bytes()
+    );
+    assert_eq!(
+        find(&PathBuf::from("dir")).kind,
+        archive::EntryKind::Directory
+    );
+    let b = find(&PathBuf::from("dir").join("b.txt"));
+    assert_eq!(b.data, test_data2.data.as_bytes());
+}
+
+/// Test burying the files listed in a tar archive
+#[rstest]
+fn test_bury_from_archive() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    fs::create_dir(test_env.src.join("dir")).unwrap();
+    let listed1 = TestData::new(&test_env, Some(&PathBuf::from("a.txt")));
+    let listed2 = TestData::new(&test_env, Some(&PathBuf::from("dir").join("b.txt")));
+    let kept = TestData::new(&test_env, Some(&PathBuf::from("c.txt")));
+
+    // The archive only serves as a list of paths
+    let manifest = test_env.graveyard.with_file_name("manifest.tar");
+    let mut builder = archive::TarWriter::new(fs::File::create(&manifest).unwrap());
+    builder
+        .append_path(&listed1.path, &PathBuf::from("a.txt"))
+        .unwrap();
+    builder
+        .append_path(&test_env.src.join("dir"), &PathBuf::from("dir"))
+        .unwrap();
+    builder.finish().unwrap();
+
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(&test_env.src).unwrap();
+    let mut log = Vec::new();
+    let result = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            from_archive: Some(manifest),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    );
+    env::set_current_dir(cur_dir).unwrap();
+    result.unwrap();
+
+    assert!(!listed1.path.exists());
+    assert!(!listed2.path.exists());
+    assert!(kept.path.exists());
+    let expected_graveyard_path = util::join_absolute(
+        &test_env.graveyard,
+        dunce::canonicalize(&test_env.src)
+            .unwrap()
+            .join("dir")
+            .join("b.txt"),
+    );
+    assert!(expected_graveyard_path.exists());
+}
+
+/// Test that an archive whose header gives an impossible size is rejected
+/// instead of being read into memory
+#[rstest]
+fn test_bury_from_malformed_archive(#[values(false, true)] overflows: bool) {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, Some(&PathBuf::from("m.txt")));
+
+    // A single header with a base-256 size, followed by no data at all
+    let mut header = [0u8; 512];
+    header[..5].copy_from_slice(b"m.txt");
+    header[124] = 0x80;
+    let size = if overflows { 1 } else { 5 };
+    for b in &mut header[124 + size..136] {
+        *b = 0xff;
+    }
+    header[156] = b'0';
+    let manifest = test_env.graveyard.with_file_name("m.tar");
+    fs::write(&manifest, header).unwrap();
+
+    let err = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            from_archive: Some(manifest),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap_err();
+
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert!(test_data.path.exists());
+}
+
+/// Test that a directory with many entries triggers a confirmation
+#[rstest]
+fn test_confirm_large_dir(#[values(3, 10)] limit: u64) {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    fs::create_dir(test_env.src.join("dir")).unwrap();
+    for i in 0..5 {
+        TestData::new(
+            &test_env,
+            Some(&PathBuf::from("dir").join(format!("{}.txt", i))),
+        );
+    }
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            targets: [test_env.src.join("dir")].to_vec(),
+            graveyard: Some(test_env.graveyard.clone()),
+            confirm_large_dir: Some(limit),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+    let log_s = String::from_utf8(log).unwrap();
+    assert_eq!(
+        log_s.contains(&format!("contains more than {} entries", limit)),
+        limit < 5
+    );
+    assert!(!test_env.src.join("dir").exists());
+}
+
+/// Test that errors can be reported as JSON
+#[rstest]
+fn test_error_format_json() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+
+    let output = cli_runner(
+        [
+            "--graveyard",
+            test_env.graveyard.to_str().unwrap(),
+            "--error-format",
+            "json",
+            "missing\"file",
+        ],
+        Some(&test_env.src),
+    )
+    .output()
+    .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(
+        stderr.trim_end(),
+        r#"{"error":"Cannot remove missing\"file: no such file or directory","kind":"NotFound","path":"missing\"file"}"#
+    );
+}
+
+/// Test that seance can show both the original path and the grave
+#[rstest]
+fn test_seance_show_dest() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, None);
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            targets: [test_data.path.clone()].to_vec(),
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(&test_env.src).unwrap();
+    let mut log = Vec::new();
+    let result = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            seance: true,
+            show_dest: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    );
+    env::set_current_dir(cur_dir).unwrap();
+    result.unwrap();
+
+    let log_s = String::from_utf8(log).unwrap();
+    let line = log_s.lines().nth(1).unwrap();
+    let (orig, dest) = line
+        .splitn(3, '\t')
+        .nth(2)
+        .unwrap()
+        .split_once("  =>  ")
+        .unwrap();
+    assert_eq!(
+        PathBuf::from(orig),
+        dunce::canonicalize(&test_env.src)
+            .unwrap()
+            .join("test_file.txt")
+    );
+    assert!(PathBuf::from(dest).starts_with(&test_env.graveyard));
+    assert!(PathBuf::from(dest).exists());
+}
+
+/// Test that concurrent appends to the record never interleave
+#[rstest]
+fn test_concurrent_record_appends() {
+    let test_env = TestEnv::new();
+    fs::create_dir(&test_env.graveyard).unwrap();
+    let record = record::Record::new(&test_env.graveyard, record::RECORD);
+    let num_threads = 16;
+    let lines_per_thread = 50;
+
+    std::thread::scope(|scope| {
+        for t in 0..num_threads {
+            let record = &record;
+            let src = &test_env.src;
+            let graveyard = &test_env.graveyard;
+            scope.spawn(move || {
+                for i in 0..lines_per_thread {
+                    let name = format!("thread{}_file{}_{}", t, i, "x".repeat(200));
+                    let mut extras = record::Extras::new();
+                    extras.insert(record::NOTE_KEY.to_string(), name.clone());
+                    record
+                        .write_log(src.join(&name), graveyard.join(&name), &extras)
+                        .unwrap();
+                }
+            });
+        }
+    });
+
+    let contents = fs::read_to_string(test_env.graveyard.join(record::RECORD)).unwrap();
+    let lines: Vec<&str> = contents.lines().skip(1).collect();
+    assert_eq!(lines.len(), num_threads * lines_per_thread);
+    for line in lines {
+        let item = record::RecordItem::parse(line).unwrap();
+        let name = item.note().unwrap();
+        assert!(item.orig.ends_with(name));
+        assert!(item.dest.ends_with(name));
+    }
+}
+
+/// Test that appends made while unburied lines are being deleted from the
+/// record are never lost to the rewrite
+#[rstest]
+fn test_record_appends_during_rewrite() {
+    let test_env = TestEnv::new();
+    fs::create_dir(&test_env.graveyard).unwrap();
+    let record = record::Record::new(&test_env.graveyard, record::RECORD);
+    let num_threads = 8;
+    let lines_per_thread = 50;
+    let exhumed: Vec<PathBuf> = (0..lines_per_thread)
+        .map(|i| test_env.graveyard.join(format!("exhumed{}", i)))
+        .collect();
+    for grave in &exhumed {
+        record
+            .write_log(test_env.src.join("exhumed"), grave, &record::Extras::new())
+            .unwrap();
+    }
+
+    std::thread::scope(|scope| {
+        for t in 0..num_threads {
+            let record = &record;
+            let src = &test_env.src;
+            let graveyard = &test_env.graveyard;
+            scope.spawn(move || {
+                for i in 0..lines_per_thread {
+                    let name = format!("thread{}_file{}", t, i);
+                    record
+                        .write_log(
+                            src.join(&name),
+                            graveyard.join(&name),
+                            &record::Extras::new(),
+                        )
+                        .unwrap();
+                }
+            });
+        }
+        let record = &record;
+        let exhumed = &exhumed;
+        scope.spawn(move || {
+            for grave in exhumed {
+                record
+                    .log_exhumed_graves(std::slice::from_ref(grave))
+                    .unwrap();
+            }
+        });
+    });
+
+    let contents = fs::read_to_string(test_env.graveyard.join(record::RECORD)).unwrap();
+    assert!(contents.starts_with("Time\tOriginal\tDestination\n"));
+    let lines: Vec<&str> = contents.lines().skip(1).collect();
+    assert_eq!(lines.len(), num_threads * lines_per_thread);
+    assert!(lines.iter().all(|line| line.contains("thread")));
+}
+
+/// Test that --tree shows the nesting and sizes of a buried directory
+#[rstest]
+fn test_tree() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let dir = test_env.src.join("proj");
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("a.txt"), vec![b'a'; 100]).unwrap();
+    fs::write(dir.join("sub").join("b.txt"), vec![b'b'; 50]).unwrap();
+    rip2::run(
+        Args {
+            targets: vec![dir],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            tree: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+    let log_s = String::from_utf8(log).unwrap();
+    let lines: Vec<&str> = log_s.lines().collect();
+    assert!(lines[0].ends_with("(150 B)"));
+    assert!(!log_s.contains(".record"));
+
+    // Column at which each entry's name starts
+    let column = |name: &str| -> usize {
+        let line = lines.iter().find(|line| line.contains(name)).unwrap();
+        line[..line.find(name).unwrap()].chars().count()
+    };
+    let proj = lines
+        .iter()
+        .position(|l| l.ends_with("proj (150 B)"))
+        .unwrap();
+    assert!(lines[proj + 1].ends_with("├── a.txt (100 B)"));
+    assert!(lines[proj + 2].ends_with("└── sub (50 B)"));
+    assert!(lines[proj + 3].ends_with("└── b.txt (50 B)"));
+    assert_eq!(column("a.txt"), column("proj (") + 4);
+    assert_eq!(column("sub ("), column("proj (") + 4);
+    assert_eq!(column("b.txt"), column("sub (") + 4);
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            tree: true,
+            inspect_depth: Some(1),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+    let log_s = String::from_utf8(log).unwrap();
+    assert_eq!(log_s.lines().count(), 2);
+    assert!(log_s.lines().nth(1).unwrap().ends_with("(150 B)"));
+}
+
+/// Test that --strip-prefix with --to restores graves under a new root
+#[rstest]
+fn test_unbury_strip_prefix() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let old_root = test_env.src.join("old").join("project");
+    let new_root = test_env.src.join("new").join("project");
+    fs::create_dir_all(old_root.join("sub")).unwrap();
+    let files = [PathBuf::from("a.txt"), PathBuf::from("sub").join("b.txt")];
+    for file in &files {
+        fs::write(old_root.join(file), "data").unwrap();
+    }
+    let old_root = dunce::canonicalize(&old_root).unwrap();
+    rip2::run(
+        Args {
+            targets: files.iter().map(|file| old_root.join(file)).collect(),
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(&test_env.src).unwrap();
+    let unbury = |strip_prefix: PathBuf| {
+        rip2::run(
+            Args {
+                graveyard: Some(test_env.graveyard.clone()),
+                unbury: Some(Vec::new()),
+                seance: true,
+                to: Some(new_root.clone()),
+                strip_prefix: Some(strip_prefix),
+                ..Args::default()
+            },
+            TestMode,
+            &mut Vec::new(),
+        )
+    };
+    let mismatched = unbury(test_env.src.join("elsewhere"));
+    let result = unbury(old_root.clone());
+    env::set_current_dir(cur_dir).unwrap();
+
+    let err = mismatched.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert!(err.to_string().contains("--strip-prefix"));
+    result.unwrap();
+    for file in &files {
+        assert!(!old_root.join(file).exists());
+        assert_eq!(fs::read_to_string(new_root.join(file)).unwrap(), "data");
+    }
+}
+
+/// Test that the binary restores the grave named after -u under --to,
+/// leaving the last grave buried
+#[rstest]
+fn test_cli_unbury_to() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, Some(&PathBuf::from("named.txt")));
+    let last = TestData::new(&test_env, Some(&PathBuf::from("last.txt")));
+    let graveyard = test_env.graveyard.to_str().unwrap();
+    cli_runner(
+        ["--graveyard", graveyard, "named.txt", "last.txt"],
+        Some(&test_env.src),
+    )
+    .assert()
+    .success();
+
+    let grave = util::join_absolute(
+        &test_env.graveyard,
+        dunce::canonicalize(&test_env.src)
+            .unwrap()
+            .join("named.txt"),
+    );
+    let new_root = test_env.src.join("restored");
+    cli_runner(
+        [
+            "--graveyard",
+            graveyard,
+            "-u",
+            grave.to_str().unwrap(),
+            "--to",
+            new_root.to_str().unwrap(),
+        ],
+        Some(&test_env.src),
+    )
+    .assert()
+    .success();
+
+    assert_eq!(
+        fs::read_to_string(new_root.join("named.txt")).unwrap(),
+        test_data.data
+    );
+    assert!(!grave.exists());
+    assert!(!new_root.join("last.txt").exists());
+    assert!(!last.path.exists());
+}
+
+/// Test that a dangling symlink is buried as a still-dangling link
+#[cfg(unix)]
+#[rstest]
+fn test_bury_broken_symlink() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let missing = test_env.src.join("missing");
+    let link = dunce::canonicalize(&test_env.src).unwrap().join("link");
+    std::os::unix::fs::symlink(&missing, &link).unwrap();
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            targets: vec![link.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            verbose: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+
+    let log_s = String::from_utf8(log).unwrap();
+    assert!(log_s.contains("is a broken symlink"));
+    assert!(fs::symlink_metadata(&link).is_err());
+    let grave = util::join_absolute(&test_env.graveyard, &link);
+    assert!(fs::symlink_metadata(&grave)
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    assert_eq!(fs::read_link(&grave).unwrap(), missing);
+    assert!(!grave.exists());
+}
+
+/// Test that --truncate-after buries a full copy and keeps only the tail
+#[rstest]
+fn test_truncate_after() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let path = dunce::canonicalize(&test_env.src).unwrap().join("app.log");
+    let contents: Vec<u8> = (0..200_000_u32).map(|i| (i % 251) as u8).collect();
+    fs::write(&path, &contents).unwrap();
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            targets: vec![path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            truncate_after: Some(1000),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+
+    let grave = util::join_absolute(&test_env.graveyard, &path);
+    assert_eq!(fs::read(&grave).unwrap(), contents);
+    assert_eq!(fs::read(&path).unwrap(), &contents[contents.len() - 1000..]);
+    let record = fs::read_to_string(test_env.graveyard.join(record::RECORD)).unwrap();
+    assert!(record.contains("truncated=1000"));
+
+    // Files already within the size are left alone
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            targets: vec![path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            truncate_after: Some(1000),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+    assert!(String::from_utf8(log)
+        .unwrap()
+        .contains("nothing to truncate"));
+    assert_eq!(fs::read(&path).unwrap().len(), 1000);
+}
+
+/// Test that --exit-code-on-empty sets the exit status of an empty seance
+#[rstest]
+fn test_exit_code_on_empty() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let seance = |code: &str| {
+        cli_runner(
+            [
+                "--graveyard",
+                test_env.graveyard.to_str().unwrap(),
+                "-s",
+                "--exit-code-on-empty",
+                code,
+            ],
+            Some(&test_env.src),
+        )
+        .assert()
+    };
+
+    seance("3").code(3).stdout("");
+    // Without the flag, an empty seance still succeeds
+    cli_runner(
+        ["--graveyard", test_env.graveyard.to_str().unwrap(), "-s"],
+        Some(&test_env.src),
+    )
+    .assert()
+    .success();
+
+    let test_data = TestData::new(&test_env, None);
+    rip2::run(
+        Args {
+            targets: vec![test_data.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+    seance("3").success();
+}
+
+/// Test that a device node keeps its major/minor numbers through bury and
+/// unbury (needs privilege to create device nodes)
+#[cfg(unix)]
+#[rstest]
+fn test_device_node() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let null = util::DeviceNode::of(&fs::metadata("/dev/null").unwrap()).unwrap();
+    let path = dunce::canonicalize(&test_env.src).unwrap().join("null");
+    if null.create(&path).is_err() {
+        // Not privileged
+        return;
+    }
+
+    rip2::run(
+        Args {
+            targets: vec![path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+    let grave = util::join_absolute(&test_env.graveyard, &path);
+    assert_eq!(
+        util::DeviceNode::of(&fs::symlink_metadata(&grave).unwrap()),
+        Some(null)
+    );
+    let record = fs::read_to_string(test_env.graveyard.join(record::RECORD)).unwrap();
+    assert!(record.contains(&format!("device={}", null)));
+
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            unbury: Some(vec![grave.clone()]),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+    assert!(!grave.exists());
+    assert_eq!(
+        util::DeviceNode::of(&fs::symlink_metadata(&path).unwrap()),
+        Some(null)
+    );
+}
+
+/// Test each --grave-collision policy when burying the same path twice
+#[rstest]
+fn test_grave_collision(
+    #[values(
+        GraveCollision::Rename,
+        GraveCollision::Overwrite,
+        GraveCollision::Skip
+    )]
+    policy: GraveCollision,
+) {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let path = dunce::canonicalize(&test_env.src)
+        .unwrap()
+        .join("build.log");
+    for contents in ["first", "second"] {
+        fs::write(&path, contents).unwrap();
+        rip2::run(
+            Args {
+                targets: vec![path.clone()],
+                graveyard: Some(test_env.graveyard.clone()),
+                grave_collision: policy,
+                ..Args::default()
+            },
+            TestMode,
+            &mut Vec::new(),
+        )
+        .unwrap();
+    }
+
+    let grave = util::join_absolute(&test_env.graveyard, &path);
+    let renamed = PathBuf::from(format!("{}~1", grave.display()));
+    let record = fs::read_to_string(test_env.graveyard.join(record::RECORD)).unwrap();
+    let entries = record
+        .lines()
+        .filter(|line| line.contains(&*path.to_string_lossy()))
+        .count();
+    match policy {
+        GraveCollision::Rename => {
+            assert_eq!(fs::read_to_string(&grave).unwrap(), "first");
+            assert_eq!(fs::read_to_string(&renamed).unwrap(), "second");
+            assert_eq!(entries, 2);
+        }
+        GraveCollision::Overwrite => {
+            assert_eq!(fs::read_to_string(&grave).unwrap(), "second");
+            assert!(!renamed.exists());
+            assert_eq!(entries, 1);
+        }
+        GraveCollision::Skip => {
+            assert_eq!(fs::read_to_string(&grave).unwrap(), "first");
+            assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+            assert!(!renamed.exists());
+            assert_eq!(entries, 1);
+        }
+    }
+}
+
+/// Test that a grave can be restored by the id seance prints for it
+#[rstest]
+fn test_unbury_by_id() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let names = ["keep.txt", "restore.txt"];
+    for name in names {
+        let test_data = TestData::new(&test_env, Some(&PathBuf::from(name)));
+        rip2::run(
+            Args {
+                targets: vec![test_data.path.clone()],
+                graveyard: Some(test_env.graveyard.clone()),
+                ..Args::default()
+            },
+            TestMode,
+            &mut Vec::new(),
+        )
+        .unwrap();
+    }
+
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(&test_env.src).unwrap();
+    let mut log = Vec::new();
+    let result = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            seance: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    );
+    env::set_current_dir(cur_dir).unwrap();
+    result.unwrap();
+
+    let log_s = String::from_utf8(log).unwrap();
+    let line = log_s.lines().find(|l| l.ends_with("restore.txt")).unwrap();
+    let id = line.split('\t').nth(1).unwrap();
+    assert_eq!(id.len(), 8);
+
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            unbury: Some(Vec::new()),
+            id: vec![id.to_string()],
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+    assert!(test_env.src.join("restore.txt").exists());
+    assert!(!test_env.src.join("keep.txt").exists());
+
+    let err = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            unbury: Some(Vec::new()),
+            id: vec![id.to_string()],
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+}
+
+/// Test that `..` and `.` components don't change where a target is buried
+#[rstest]
+fn test_bury_relative_components(
+    #[values("../", "./sub/../")] form: &str,
+    #[values(false, true)] is_symlink: bool,
+) {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let src = dunce::canonicalize(&test_env.src).unwrap();
+    fs::create_dir(src.join("sub")).unwrap();
+    let path = src.join("file.txt");
+    if is_symlink {
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("missing", &path).unwrap();
+        #[cfg(not(unix))]
+        return;
+    } else {
+        fs::write(&path, "data").unwrap();
+    }
+
+    let cwd = if form == "../" {
+        src.join("sub")
+    } else {
+        src.clone()
+    };
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(&cwd).unwrap();
+    let result = rip2::run(
+        Args {
+            targets: vec![PathBuf::from(format!("{}file.txt", form))],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    );
+    env::set_current_dir(cur_dir).unwrap();
+    result.unwrap();
+
+    let grave = util::join_absolute(&test_env.graveyard, &path);
+    assert!(fs::symlink_metadata(&grave).is_ok());
+    assert!(fs::symlink_metadata(&path).is_err());
+    let record = fs::read_to_string(test_env.graveyard.join(record::RECORD)).unwrap();
+    assert!(record.contains(&format!("\t{}\t", path.display())));
+}
+
+/// Test that --audit passes a fresh graveyard, and flags a grave readable
+/// by others once they can reach it
+#[cfg(unix)]
+#[rstest]
+fn test_audit() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, None);
+    let grave = util::join_absolute(
+        &test_env.graveyard,
+        dunce::canonicalize(&test_data.path).unwrap(),
+    );
+    rip2::run(
+        Args {
+            targets: vec![test_data.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    let audit = |log: &mut Vec<u8>| {
+        rip2::run(
+            Args {
+                graveyard: Some(test_env.graveyard.clone()),
+                audit: true,
+                ..Args::default()
+            },
+            TestMode,
+            log,
+        )
+    };
+    // A graveyard just as rip made it passes, whatever the grave's mode
+    fs::set_permissions(&grave, fs::Permissions::from_mode(0o644)).unwrap();
+    let mut log = Vec::new();
+    audit(&mut log).unwrap();
+    assert!(String::from_utf8(log).unwrap().contains("No issues found"));
+
+    // Once the root is opened up, only the grave itself is reported
+    fs::set_permissions(&test_env.graveyard, fs::Permissions::from_mode(0o755)).unwrap();
+    let mut log = Vec::new();
+    let err = audit(&mut log).unwrap_err();
+    assert_eq!(err.to_string(), "rip audit found 1 issues");
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        format!(
+            "{}: accessible by group or others (mode 644)\n",
+            grave.display()
+        )
+    );
+}
+
+/// Test that --merge-graveyard keeps both graves of the same original path
+#[rstest]
+fn test_merge_graveyard() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let other = test_env.graveyard.with_file_name("other_graveyard");
+    let path = dunce::canonicalize(&test_env.src).unwrap().join("a.txt");
+    for (contents, graveyard) in [("one", &test_env.graveyard), ("two", &other)] {
+        fs::write(&path, contents).unwrap();
+        rip2::run(
+            Args {
+                targets: vec![path.clone()],
+                graveyard: Some(graveyard.clone()),
+                ..Args::default()
+            },
+            TestMode,
+            &mut Vec::new(),
+        )
+        .unwrap();
+    }
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            merge_graveyard: Some(other.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+    assert!(String::from_utf8(log).unwrap().contains("Merged 1 graves"));
+    assert!(!other.exists());
+
+    let grave = util::join_absolute(&test_env.graveyard, &path);
+    let renamed = PathBuf::from(format!("{}~1", grave.display()));
+    assert_eq!(fs::read_to_string(&grave).unwrap(), "one");
+    assert_eq!(fs::read_to_string(&renamed).unwrap(), "two");
+    let record = fs::read_to_string(test_env.graveyard.join(record::RECORD)).unwrap();
+    let dests: Vec<PathBuf> = record
+        .lines()
+        .skip(1)
+        .map(|line| {
+            let item = record::RecordItem::new(line);
+            assert_eq!(item.orig, path);
+            item.dest
+        })
+        .collect();
+    assert_eq!(dests, vec![grave, renamed]);
+}
+
+/// Test that a relative --merge-graveyard finds its graves, and that a
+/// grave failing to move leaves the ones already moved in one record only
+#[rstest]
+fn test_merge_graveyard_relative_partial() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let other = test_env.graveyard.with_file_name("other_graveyard");
+    let src = dunce::canonicalize(&test_env.src).unwrap();
+    let moved = TestData::new(&test_env, Some(&PathBuf::from("a.txt")));
+    fs::create_dir(src.join("sub")).unwrap();
+    let blocked = TestData::new(&test_env, Some(&PathBuf::from("sub").join("b.txt")));
+    rip2::run(
+        Args {
+            targets: vec![moved.path.clone(), blocked.path.clone()],
+            graveyard: Some(other.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    // A file where the second grave needs a directory
+    let obstacle = util::join_absolute(&test_env.graveyard, src.join("sub"));
+    fs::create_dir_all(obstacle.parent().unwrap()).unwrap();
+    fs::write(&obstacle, "in the way").unwrap();
+
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(other.parent().unwrap()).unwrap();
+    let result = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            merge_graveyard: Some(PathBuf::from("other_graveyard")),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    );
+    env::set_current_dir(cur_dir).unwrap();
+    assert!(result.is_err());
+
+    let record = fs::read_to_string(test_env.graveyard.join(record::RECORD)).unwrap();
+    let other_record = fs::read_to_string(other.join(record::RECORD)).unwrap();
+    assert!(record.contains("a.txt"));
+    assert!(!other_record.contains("a.txt"));
+    assert!(other_record.contains("b.txt"));
+    assert!(util::join_absolute(&test_env.graveyard, src.join("a.txt")).exists());
+}
+
+/// Test that --private-graveyard masks the grave but restores the mode
+#[cfg(unix)]
+#[rstest]
+fn test_private_graveyard() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, None);
+    fs::set_permissions(&test_data.path, fs::Permissions::from_mode(0o644)).unwrap();
+    let grave = util::join_absolute(
+        &test_env.graveyard,
+        dunce::canonicalize(&test_data.path).unwrap(),
+    );
+    let mode = |path: &PathBuf| fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+
+    rip2::run(
+        Args {
+            targets: vec![test_data.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            private_graveyard: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+    assert_eq!(mode(&grave), 0o600);
+
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            unbury: Some(vec![grave.clone()]),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+    assert_eq!(mode(&test_data.path), 0o644);
+    assert_eq!(fs::read_to_string(&test_data.path).unwrap(), test_data.data);
+}
+
+/// Test that POSIX ACLs survive a copying bury and unbury, unless
+/// --no-inherit-acl drops them
+#[cfg(target_os = "linux")]
+#[rstest]
+fn test_acl_round_trip(#[values(false, true)] no_inherit_acl: bool) {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, None);
+    let name = "system.posix_acl_access";
+
+    // Version 2 header, then (tag, perm, id) entries: owner rw-, user 1234
+    // r--, group r--, mask r--, other r--
+    let mut acl = 2_u32.to_le_bytes().to_vec();
+    for (tag, perm, id) in [
+        (0x01_u16, 6_u16, u32::MAX),
+        (0x02, 4, 1234),
+        (0x04, 4, u32::MAX),
+        (0x10, 4, u32::MAX),
+        (0x20, 4, u32::MAX),
+    ] {
+        acl.extend(tag.to_le_bytes());
+        acl.extend(perm.to_le_bytes());
+        acl.extend(id.to_le_bytes());
+    }
+    if util::set_xattr(&test_data.path, name, &acl).is_err() {
+        println!("Skipping ACL test: filesystem doesn't support ACLs");
+        return;
+    }
+    let grave = util::join_absolute(
+        &test_env.graveyard,
+        dunce::canonicalize(&test_data.path).unwrap(),
+    );
+
+    env::set_var("__RIP_ALLOW_RENAME", "false");
+    let bury = rip2::run(
+        Args {
+            targets: vec![test_data.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            no_inherit_acl,
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    );
+    let grave_acl = util::get_xattr(&grave, name);
+    let unbury = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            unbury: Some(vec![grave.clone()]),
+            no_inherit_acl,
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    );
+    env::remove_var("__RIP_ALLOW_RENAME");
+    bury.unwrap();
+    unbury.unwrap();
+
+    let expected = if no_inherit_acl { None } else { Some(acl) };
+    assert_eq!(grave_acl.unwrap(), expected);
+    assert_eq!(util::get_xattr(&test_data.path, name).unwrap(), expected);
+    assert_eq!(fs::read_to_string(&test_data.path).unwrap(), test_data.data);
+}
+
+/// Test that decompose reports the space it freed, and its progress with
+/// --verbose
+#[rstest]
+fn test_decompose_summary(#[values(false, true)] verbose: bool) {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let dir = test_env.src.join("dir");
+    fs::create_dir(&dir).unwrap();
+    for name in ["a", "b", "c"] {
+        fs::write(dir.join(name), vec![0_u8; 100]).unwrap();
+    }
+    rip2::run(
+        Args {
+            targets: vec![dir],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+    let size = util::grave_size(&test_env.graveyard).unwrap();
+    let total = walkdir::WalkDir::new(&test_env.graveyard)
+        .into_iter()
+        .count();
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            decompose: true,
+            verbose,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+    let log_s = String::from_utf8(log).unwrap();
+    assert!(!test_env.graveyard.exists());
+    assert!(log_s.ends_with(&format!(
+        "Freed {} from {}\n",
+        util::humanize_bytes(size),
+        test_env.graveyard.display()
+    )));
+    assert_eq!(
+        log_s.contains(&format!("Removed {}/{}", total, total)),
+        verbose
+    );
+}
+
+/// Test that --edit only restores the graves left in the edited list
+#[cfg(unix)]
+#[rstest]
+fn test_unbury_edit() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    for name in ["keep_a.txt", "restore.txt", "keep_b.txt"] {
+        let test_data = TestData::new(&test_env, Some(&PathBuf::from(name)));
+        rip2::run(
+            Args {
+                targets: vec![test_data.path.clone()],
+                graveyard: Some(test_env.graveyard.clone()),
+                ..Args::default()
+            },
+            TestMode,
+            &mut Vec::new(),
+        )
+        .unwrap();
+    }
+
+    // A fake editor that drops every line mentioning "keep"
+    let editor = test_env.graveyard.with_file_name("editor.sh");
+    fs::write(
+        &editor,
+        "#!/bin/sh\ngrep -v keep \"$1\" > \"$1.tmp\" && mv \"$1.tmp\" \"$1\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&editor, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let saved_editor = env::var_os("EDITOR");
+    env::set_var("EDITOR", &editor);
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(&test_env.src).unwrap();
+    let result = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            unbury: Some(Vec::new()),
+            edit: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    );
+    env::set_current_dir(cur_dir).unwrap();
+    match saved_editor {
+        Some(value) => env::set_var("EDITOR", value),
+        None => env::remove_var("EDITOR"),
+    }
+    result.unwrap();
+
+    assert!(test_env.src.join("restore.txt").exists());
+    assert!(!test_env.src.join("keep_a.txt").exists());
+    assert!(!test_env.src.join("keep_b.txt").exists());
+}
+
+/// Test that --count-file holds the number of buried and unburied files
+#[rstest]
+fn test_count_file() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let count_file = test_env.src.join("count");
+    let targets: Vec<PathBuf> = ["a.txt", "b.txt", "c.txt"]
+        .iter()
+        .map(|name| TestData::new(&test_env, Some(&PathBuf::from(name))).path)
+        .collect();
+
+    rip2::run(
+        Args {
+            targets: targets.clone(),
+            graveyard: Some(test_env.graveyard.clone()),
+            count_file: Some(count_file.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+    assert_eq!(fs::read_to_string(&count_file).unwrap(), "3\n");
+
+    let src = dunce::canonicalize(&test_env.src).unwrap();
+    let graves: Vec<PathBuf> = targets[..2]
+        .iter()
+        .map(|target| {
+            util::join_absolute(&test_env.graveyard, src.join(target.file_name().unwrap()))
+        })
+        .collect();
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            unbury: Some(graves),
+            count_file: Some(count_file.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+    assert_eq!(fs::read_to_string(&count_file).unwrap(), "2\n");
+}
+
+/// Test that --forget drops one entry from the record, and with --purge
+/// its grave too, leaving the others in place
+#[rstest]
+fn test_forget(#[values(false, true)] purge: bool) {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let targets: Vec<PathBuf> = ["a.txt", "b.txt", "c.txt"]
+        .iter()
+        .map(|name| TestData::new(&test_env, Some(&PathBuf::from(name))).path)
+        .collect();
+    let src = dunce::canonicalize(&test_env.src).unwrap();
+    let forgotten_grave = util::join_absolute(&test_env.graveyard, src.join("b.txt"));
+
+    rip2::run(
+        Args {
+            targets: targets.clone(),
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            forget: Some(vec![targets[1].clone()]),
+            purge,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+    assert_eq!(forgotten_grave.exists(), !purge);
+    assert_eq!(
+        String::from_utf8(log).unwrap().ends_with("Freed 100 B\n"),
+        purge
+    );
+
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(&test_env.src).unwrap();
+    let mut log = Vec::new();
+    let result = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            seance: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    );
+    env::set_current_dir(cur_dir).unwrap();
+    result.unwrap();
+
+    let log_s = String::from_utf8(log).unwrap();
+    assert!(log_s.contains("a.txt"));
+    assert!(!log_s.contains("b.txt"));
+    assert!(log_s.contains("c.txt"));
+}
+
+/// Test that a record over --record-max-lines moves entries for vanished
+/// graves to the history file, which seance reads with --all-history
+#[rstest]
+fn test_record_rotation() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let src = dunce::canonicalize(&test_env.src).unwrap();
+    let record_path = test_env.graveyard.join(record::RECORD);
+    let history_path =
+        test_env
+            .graveyard
+            .join(format!("{}{}", record::RECORD, record::HISTORY_SUFFIX));
+    let bury = |name: &str| {
+        let target = TestData::new(&test_env, Some(&PathBuf::from(name))).path;
+        rip2::run(
+            Args {
+                targets: vec![target],
+                graveyard: Some(test_env.graveyard.clone()),
+                record_max_lines: Some(2),
+                ..Args::default()
+            },
+            TestMode,
+            &mut Vec::new(),
+        )
+        .unwrap();
+    };
+
+    bury("a.txt");
+    bury("b.txt");
+    // A grave deleted by hand leaves a dead entry behind
+    fs::remove_file(util::join_absolute(&test_env.graveyard, src.join("a.txt"))).unwrap();
+    bury("c.txt");
+
+    let entries = |path: &PathBuf| fs::read_to_string(path).unwrap().lines().count() - 1;
+    assert_eq!(entries(&record_path), 2);
+    assert_eq!(entries(&history_path), 1);
+    assert!(fs::read_to_string(&history_path).unwrap().contains("a.txt"));
+
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(&test_env.src).unwrap();
+    let mut log = Vec::new();
+    let result = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            seance: true,
+            all_history: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    );
+    env::set_current_dir(cur_dir).unwrap();
+    result.unwrap();
+
+    let log_s = String::from_utf8(log).unwrap();
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        assert!(log_s.contains(name));
+    }
+}
+
+/// Test that unburying one grave keeps the record's header and the other
+/// entries, so seance and a later unbury still see them
+#[rstest]
+fn test_unbury_keeps_other_entries() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let names = ["a.txt", "b.txt", "c.txt"];
+    let files: Vec<TestData> = names
+        .iter()
+        .map(|name| TestData::new(&test_env, Some(&PathBuf::from(name))))
+        .collect();
+    let graveyard = test_env.graveyard.to_str().unwrap();
+    let mut args = vec!["--graveyard", graveyard];
+    args.extend(names);
+    cli_runner(args, Some(&test_env.src)).assert().success();
+
+    cli_runner(
+        ["--graveyard", graveyard, "-u", "b.txt"],
+        Some(&test_env.src),
+    )
+    .assert()
+    .success();
+    let record = fs::read_to_string(test_env.graveyard.join(record::RECORD)).unwrap();
+    assert!(record.starts_with("Time\tOriginal\tDestination\n"));
+
+    let seance = cli_runner(["--graveyard", graveyard, "-s"], Some(&test_env.src))
+        .assert()
+        .success();
+    let seance = String::from_utf8(seance.get_output().stdout.clone()).unwrap();
+    assert!(seance.contains("a.txt"));
+    assert!(!seance.contains("b.txt"));
+    assert!(seance.contains("c.txt"));
+
+    // The last two graves come back in turn
+    for test_data in [&files[2], &files[0]] {
+        cli_runner(["--graveyard", graveyard, "-u"], Some(&test_env.src))
+            .assert()
+            .success();
+        assert_eq!(fs::read_to_string(&test_data.path).unwrap(), test_data.data);
+    }
+}
+
+/// Test that rewriting a record leaves the other records of the graveyard
+/// alone, even one whose name differs only by its extension
+#[rstest]
+fn test_rewrite_record_keeps_other_records() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    fs::create_dir_all(&test_env.graveyard).unwrap();
+    let log_record = record::Record::new(&test_env.graveyard, "work.log");
+    let tmp_record = record::Record::new(&test_env.graveyard, "work.tmp");
+    for (record, name) in [(&log_record, "a.txt"), (&tmp_record, "b.txt")] {
+        record
+            .write_log(
+                test_env.src.join(name),
+                test_env.graveyard.join(name),
+                &record::Extras::new(),
+            )
+            .unwrap();
+    }
+
+    log_record.rewrite_lines(|_| None).unwrap();
+
+    assert!(log_record.entries().unwrap().is_empty());
+    let kept = tmp_record.entries().unwrap();
+    assert_eq!(kept.len(), 1);
+    assert_eq!(kept[0].dest, test_env.graveyard.join("b.txt"));
+}
+
+/// Test that a large record is only checked for vanished graves again
+/// once it has grown by a tenth
+#[rstest]
+fn test_record_rotation_deferred() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    fs::create_dir_all(&test_env.graveyard).unwrap();
+    let record = record::Record::new(&test_env.graveyard, record::RECORD);
+    let append = |name: String| {
+        let dest = test_env.graveyard.join(&name);
+        fs::write(&dest, "").unwrap();
+        record
+            .append(&record::RecordItem {
+                time: "2000-01-01T00:00:00+00:00".to_string(),
+                orig: test_env.src.join(&name),
+                dest,
+                extras: record::Extras::new(),
+            })
+            .unwrap();
+    };
+    for i in 0..100 {
+        append(format!("{:03}", i));
+    }
+    assert_eq!(record.rotate(10).unwrap(), 0);
+
+    fs::remove_file(test_env.graveyard.join("000")).unwrap();
+    append("100".to_string());
+    assert_eq!(record.rotate(10).unwrap(), 0);
+
+    for i in 101..111 {
+        append(i.to_string());
+    }
+    assert_eq!(record.rotate(10).unwrap(), 1);
+}
+
+/// Test that --graveyard-init creates a private graveyard with an empty
+/// record and nothing else
+#[cfg(unix)]
+#[rstest]
+fn test_graveyard_init() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            graveyard_init: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        format!("{}\n", test_env.graveyard.display())
+    );
+    let mode = fs::metadata(&test_env.graveyard)
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o700);
+    let record = record::Record::new(&test_env.graveyard, record::RECORD);
+    assert!(record.entries().unwrap().is_empty());
+    assert_eq!(fs::read_dir(&test_env.graveyard).unwrap().count(), 1);
+}
+
+/// Test that --inspect-tail previews the last lines of a file, in order
+#[rstest]
+fn test_inspect_tail() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let path = test_env.src.join("log.txt");
+    let contents: String = (1..=20).map(|i| format!("entry {}\r\n", i)).collect();
+    fs::write(&path, contents).unwrap();
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            targets: vec![path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            inspect: true,
+            inspect_tail: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+
+    let preview: Vec<String> = String::from_utf8(log)
+        .unwrap()
+        .lines()
+        .filter_map(|line| line.strip_prefix("> "))
+        .map(String::from)
+        .collect();
+    let expected: Vec<String> = (15..=20).map(|i| format!("entry {}", i)).collect();
+    assert_eq!(preview, expected);
+    assert!(!path.exists());
+}
+
+/// Test that --to-stdout writes the targets as a tar archive to the stream
+/// and removes them, without creating a graveyard
+#[rstest]
+fn test_to_stdout() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    fs::create_dir(test_env.src.join("dir")).unwrap();
+    let test_data1 = TestData::new(&test_env, Some(&PathBuf::from("a.txt")));
+    let test_data2 = TestData::new(&test_env, Some(&PathBuf::from("dir").join("b.txt")));
+
+    let mut tar = Vec::new();
+    rip2::run(
+        Args {
+            targets: vec![test_data1.path.clone(), test_env.src.join("dir")],
+            graveyard: Some(test_env.graveyard.clone()),
+            to_stdout: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut tar,
+    )
+    .unwrap();
+
+    assert!(!test_data1.path.exists());
+    assert!(!test_env.src.join("dir").exists());
+    assert!(!test_env.graveyard.exists());
+    let entries = archive::read_entries(tar.as_slice()).unwrap();
+    let src = dunce::canonicalize(&test_env.src).unwrap();
+    for (path, data) in [
+        (src.join("a.txt"), &test_data1.data),
+        (src.join("dir").join("b.txt"), &test_data2.data),
+    ] {
+        let name = archive::archive_name(&path);
+        let entry = entries.iter().find(|e| e.path == name).unwrap();
+        assert_eq!(entry.data, data.as_bytes());
+    }
+}
+
+/// Test that prompts read their answers from the mode's input, one line
+/// per prompt
+#[rstest]
+fn test_prompt_input() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let first = TestData::new(&test_env, Some(&PathBuf::from("first.txt")));
+    let second = TestData::new(&test_env, Some(&PathBuf::from("second.txt")));
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            targets: vec![first.path.clone(), second.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            inspect: true,
+            ..Args::default()
+        },
+        util::InputMode::new(Cursor::new("y\nn\n")),
+        &mut log,
+    )
+    .unwrap();
+
+    assert_eq!(
+        String::from_utf8(log)
+            .unwrap()
+            .matches("to the graveyard? (Y/n)")
+            .count(),
+        2
+    );
+    assert!(!first.path.exists());
+    assert!(second.path.exists());
+}
+
+/// Input that gets a Ctrl-C before the user presses Enter
+struct InterruptedInput(Cursor<&'static str>);
+
+impl Read for InterruptedInput {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl std::io::BufRead for InterruptedInput {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        util::interrupt();
+        self.0.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.0.consume(amount)
+    }
+}
+
+/// Test that Ctrl-C at an --inspect prompt stops the bury, even though
+/// Enter (which would accept) comes after it
+#[rstest]
+fn test_prompt_interrupted() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, None);
+
+    let result = rip2::run(
+        Args {
+            targets: vec![test_data.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            inspect: true,
+            ..Args::default()
+        },
+        util::InputMode::new(InterruptedInput(Cursor::new("\n"))),
+        &mut Vec::new(),
+    );
+
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::Interrupted);
+    assert!(!util::interrupted());
+    assert!(test_data.path.exists());
+}
+
+/// Test that --summary-json writes the counts, bytes, and per-target
+/// status of a multi-target bury
+#[rstest]
+fn test_summary_json() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let summary_path = test_env.src.join("summary.json");
+    let targets: Vec<PathBuf> = ["a.txt", "b.txt", "c.txt"]
+        .iter()
+        .map(|name| TestData::new(&test_env, Some(&PathBuf::from(name))).path)
+        .collect();
+
+    rip2::run(
+        Args {
+            targets: targets.clone(),
+            graveyard: Some(test_env.graveyard.clone()),
+            summary_json: Some(Some(summary_path.clone())),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    let json = fs::read_to_string(&summary_path).unwrap();
+    let pattern = regex::Regex::new(
+        r#"^\{"buried":(\d+),"unburied":(\d+),"bytes":(\d+),"elapsed_secs":\d+\.\d+,"targets":\[(.*)\]\}\n$"#,
+    )
+    .unwrap();
+    let captures = pattern.captures(&json).unwrap();
+    assert_eq!(&captures[1], "3");
+    assert_eq!(&captures[2], "0");
+    assert_eq!(&captures[3], "300");
+    let expected: Vec<String> = targets
+        .iter()
+        .map(|target| {
+            format!(
+                "{{\"path\":{},\"status\":\"buried\"}}",
+                util::json_string(&target.display().to_string())
+            )
+        })
+        .collect();
+    assert_eq!(&captures[4], expected.join(","));
+}
+
+/// Test that --pager sends seance output to the pager instead of the
+/// output stream
+#[cfg(unix)]
+#[rstest]
+fn test_pager() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, None);
+    rip2::run(
+        Args {
+            targets: vec![test_data.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    // A fake pager that captures what it is given
+    let tmp = test_env.src.parent().unwrap().to_path_buf();
+    let captured = tmp.join("captured.txt");
+    let pager = tmp.join("pager.sh");
+    fs::write(
+        &pager,
+        format!("#!/bin/sh\ncat > \"{}\"\n", captured.display()),
+    )
+    .unwrap();
+    fs::set_permissions(&pager, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let saved_pager = env::var_os("RIP_PAGER");
+    env::set_var("RIP_PAGER", &pager);
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(&test_env.src).unwrap();
+    let mut log = Vec::new();
+    let result = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            seance: true,
+            pager: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    );
+    env::set_current_dir(cur_dir).unwrap();
+    match saved_pager {
+        Some(value) => env::set_var("RIP_PAGER", value),
+        None => env::remove_var("RIP_PAGER"),
+    }
+    result.unwrap();
+
+    assert!(log.is_empty());
+    let captured = fs::read_to_string(&captured).unwrap();
+    assert!(captured.starts_with("deletion_time"));
+    assert!(captured.contains("test_file.txt"));
+}
+
+/// Test that --replace-if-different leaves an identical file untouched but
+/// still consumes the grave, and replaces a file that differs
+#[rstest]
+fn test_replace_if_different(#[values(false, true)] identical: bool) {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, None);
+    let grave = util::join_absolute(
+        &test_env.graveyard,
+        dunce::canonicalize(&test_data.path).unwrap(),
+    );
+    rip2::run(
+        Args {
+            targets: vec![test_data.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    let existing = if identical {
+        test_data.data.clone()
+    } else {
+        "something else".to_string()
+    };
+    fs::write(&test_data.path, &existing).unwrap();
+    let mtime = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+    util::set_mtime(&test_data.path, mtime).unwrap();
+
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            unbury: Some(vec![grave.clone()]),
+            replace_if_different: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    assert!(!grave.exists());
+    assert_eq!(fs::read_to_string(&test_data.path).unwrap(), test_data.data);
+    // An identical file isn't written to at all
+    let new_mtime = fs::metadata(&test_data.path).unwrap().modified().unwrap();
+    assert_eq!(new_mtime == mtime, identical);
+    // No renamed copy is left next to it
+    assert_eq!(fs::read_dir(&test_env.src).unwrap().count(), 1);
+    let record_contents = fs::read_to_string(test_env.graveyard.join(record::RECORD)).unwrap();
+    assert!(!record_contents.contains("test_file.txt"));
+}
+
+/// Test that --route sends matching files to their room and leaves the
+/// rest in the default location
+#[rstest]
+fn test_route() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let log_file = TestData::new(&test_env, Some(&PathBuf::from("app.log"))).path;
+    let other = TestData::new(&test_env, Some(&PathBuf::from("notes.txt"))).path;
+    let src = dunce::canonicalize(&test_env.src).unwrap();
+
+    rip2::run(
+        Args {
+            targets: vec![log_file, other],
+            graveyard: Some(test_env.graveyard.clone()),
+            route: vec!["*.mp4=media".to_string(), "*.log=logs".to_string()],
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    assert!(util::join_absolute(test_env.graveyard.join("@logs"), src.join("app.log")).exists());
+    assert!(util::join_absolute(&test_env.graveyard, src.join("notes.txt")).exists());
+    assert!(!test_env.graveyard.join("@media").exists());
+}
+
+/// Test that --doctor still reports a graveyard path that is a file
+/// rather than failing before the diagnosis
+#[rstest]
+fn test_doctor_graveyard_is_file() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    fs::write(&test_env.graveyard, "").unwrap();
+
+    let mut log = Vec::new();
+    let result = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            doctor: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    );
+    assert!(result.is_err());
+    assert!(String::from_utf8(log)
+        .unwrap()
+        .contains("graveyard is not a directory"));
+}
+
+/// Test that --check-record counts valid, invalid, and dead entries and
+/// fails when any are invalid, leaving the record alone
+#[rstest]
+fn test_check_record() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, None);
+    rip2::run(
+        Args {
+            targets: vec![test_data.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+    let record_path = test_env.graveyard.join(record::RECORD);
+    let mut record_file = fs::OpenOptions::new()
+        .append(true)
+        .open(&record_path)
+        .unwrap();
+    writeln!(
+        record_file,
+        "2024-01-01T00:00:00+00:00\t/gone.txt\t{}",
+        test_env.graveyard.join("gone.txt").display()
+    )
+    .unwrap();
+    writeln!(record_file, "not a record line").unwrap();
+    let contents = fs::read_to_string(&record_path).unwrap();
+
+    let mut log = Vec::new();
+    let err = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            check_record: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("1 invalid lines"));
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        "valid: 2\ninvalid: 1\ndead: 1\n"
+    );
+    assert_eq!(fs::read_to_string(&record_path).unwrap(), contents);
+}
+
+/// Test that --name records a different original path, which unbury then
+/// restores to, and that it needs exactly one target
+#[rstest]
+fn test_bury_name() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, Some(&PathBuf::from("tmpXk3j9.dat")));
+    let renamed = test_env.src.join("report.txt");
+
+    rip2::run(
+        Args {
+            targets: vec![test_data.path.clone(), test_env.src.join("other")],
+            graveyard: Some(test_env.graveyard.clone()),
+            name: Some(renamed.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap_err();
+
+    rip2::run(
+        Args {
+            targets: vec![test_data.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            name: Some(renamed.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+    // The grave still mirrors the real source
+    let grave = util::join_absolute(
+        &test_env.graveyard,
+        dunce::canonicalize(&test_env.src)
+            .unwrap()
+            .join("tmpXk3j9.dat"),
+    );
+    assert!(grave.exists());
+
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            unbury: Some(Vec::new()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+    assert!(!test_data.path.exists());
+    assert_eq!(fs::read_to_string(&renamed).unwrap(), test_data.data);
+}
+
+/// Test that a --watch pass buries the files matching --match that are
+/// older than --older-than, and nothing else
+#[rstest]
+fn test_watch() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let old = std::time::SystemTime::now() - std::time::Duration::from_secs(7200);
+    let due = TestData::new(&test_env, Some(&PathBuf::from("due.tmp"))).path;
+    let fresh = TestData::new(&test_env, Some(&PathBuf::from("fresh.tmp"))).path;
+    let other = TestData::new(&test_env, Some(&PathBuf::from("other.txt"))).path;
+    util::set_mtime(&due, old).unwrap();
+    util::set_mtime(&other, old).unwrap();
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            watch: Some(test_env.src.clone()),
+            watch_match: Some("*.tmp".to_string()),
+            older_than: Some(std::time::Duration::from_secs(3600)),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+
+    assert!(!due.exists());
+    assert!(fresh.exists());
+    assert!(other.exists());
+    assert!(util::join_absolute(
+        &test_env.graveyard,
+        dunce::canonicalize(&test_env.src).unwrap().join("due.tmp")
+    )
+    .exists());
+}
+
+/// Test that immutable files are refused up front, and that --force clears
+/// the flag and buries them
+#[cfg(target_os = "linux")]
+#[rstest]
+fn test_immutable_file() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, None);
+    let chattr = |flag: &str| {
+        std::process::Command::new("chattr")
+            .arg(flag)
+            .arg(&test_data.path)
+            .status()
+            .is_ok_and(|status| status.success())
+    };
+    if !chattr("+i") {
+        println!("Skipping immutable test: couldn't set the flag");
+        return;
+    }
+    assert_eq!(util::protection_flag(&test_data.path), Some("immutable"));
+    let grave = util::join_absolute(
+        &test_env.graveyard,
+        dunce::canonicalize(&test_data.path).unwrap(),
+    );
+
+    let error = rip2::run(
+        Args {
+            targets: vec![test_data.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap_err();
+    let immutable = test_data.path.exists();
+    chattr("-i");
+    assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+    assert!(error.to_string().contains("it is immutable"));
+    assert!(immutable);
+    assert!(!grave.exists());
+
+    chattr("+i");
+    let mut log = Vec::new();
+    let result = rip2::run(
+        Args {
+            targets: vec![test_data.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            force: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    );
+    if test_data.path.exists() {
+        chattr("-i");
+    }
+    result.unwrap();
+    assert!(String::from_utf8(log)
+        .unwrap()
+        .contains("Cleared the immutable flag"));
+    assert!(!test_data.path.exists());
+    assert!(grave.exists());
+    assert_eq!(util::protection_flag(&grave), None);
+}
+
+/// Test that a protected directory, anything inside it, and any directory
+/// containing it are refused, whether protected by flag or from the env
+#[rstest]
+fn test_protect(#[values(false, true)] from_env: bool) {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let keep = test_env.src.join("keep");
+    fs::create_dir(&keep).unwrap();
+    let inside = keep.join("inside.txt");
+    fs::write(&inside, "precious").unwrap();
+    let test_data = TestData::new(&test_env, None);
+    if from_env {
+        env::set_var("RIP_PROTECT", &keep);
+    }
+
+    let bury = |target: &PathBuf| {
+        rip2::run(
+            Args {
+                targets: vec![target.clone()],
+                graveyard: Some(test_env.graveyard.clone()),
+                protect: if from_env {
+                    Vec::new()
+                } else {
+                    vec![keep.clone()]
+                },
+                ..Args::default()
+            },
+            TestMode,
+            &mut Vec::new(),
+        )
+    };
+    let results = [
+        bury(&keep),
+        bury(&inside),
+        bury(&test_env.src),
+        bury(&test_data.path),
+    ];
+    env::remove_var("RIP_PROTECT");
+
+    let [keep_result, inside_result, parent_result, other_result] = results;
+    for result in [keep_result, inside_result, parent_result] {
+        let error = result.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+        assert!(error.to_string().contains("is protected"));
+    }
+    assert_eq!(fs::read_to_string(&inside).unwrap(), "precious");
+    other_result.unwrap();
+    assert!(!test_data.path.exists());
+}
+
+/// Test that --warn-disk-usage warns once the graveyard's filesystem is at
+/// least as full as the limit. A limit of 0% stands in for a nearly full
+/// filesystem.
+#[rstest]
+fn test_warn_disk_usage(#[values(0, 100)] limit: u8) {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, None);
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            targets: vec![test_data.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            warn_disk_usage: Some(limit),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+
+    let log = String::from_utf8(log).unwrap();
+    let warned = regex::Regex::new(r"Warning: the graveyard's filesystem is \d+% full \(.+ free\)")
+        .unwrap()
+        .is_match(&log);
+    // Nothing here fills the disk completely
+    assert_eq!(warned, limit == 0);
+}
+
+/// Test that an interrupt partway through copying a directory stops the
+/// bury without losing data or leaving a partial grave behind
+#[rstest]
+fn test_interrupted_bury() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let dir = test_env.src.join("dir");
+    fs::create_dir(&dir).unwrap();
+    let files: Vec<TestData> = ["a.txt", "b.txt", "c.txt"]
+        .iter()
+        .map(|name| TestData::new(&test_env, Some(&PathBuf::from("dir").join(name))))
+        .collect();
+
+    env::set_var("__RIP_ALLOW_RENAME", "false");
+    env::set_var("__RIP_INTERRUPT_AFTER", "1");
+    let result = rip2::run(
+        Args {
+            targets: vec![dir.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    );
+    env::remove_var("__RIP_ALLOW_RENAME");
+    env::remove_var("__RIP_INTERRUPT_AFTER");
+
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::Interrupted);
+    assert!(!util::interrupted());
+    for file in &files {
+        assert_eq!(fs::read_to_string(&file.path).unwrap(), file.data);
+    }
+    let grave = util::join_absolute(&test_env.graveyard, dunce::canonicalize(&dir).unwrap());
+    assert!(!grave.exists());
+    let record = fs::read_to_string(test_env.graveyard.join(record::RECORD)).unwrap_or_default();
+    assert!(!record.contains("dir"));
+}
+
+/// Test that --import-trash moves the files of a FreeDesktop trash into the
+/// graveyard under their original paths and deletion times
+#[cfg(target_os = "linux")]
+#[rstest]
+fn test_import_trash() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let src = dunce::canonicalize(&test_env.src).unwrap();
+    let trash = src.join("Trash");
+    fs::create_dir_all(trash.join("info")).unwrap();
+    fs::create_dir_all(trash.join("files").join("build")).unwrap();
+    fs::write(trash.join("files").join("notes.txt"), "notes").unwrap();
+    fs::write(trash.join("files").join("build").join("out.o"), "out").unwrap();
+    for (name, path) in [
+        ("notes.txt", format!("{}/my%20notes.txt", src.display())),
+        ("build", format!("{}/build", src.display())),
+    ] {
+        fs::write(
+            trash.join("info").join(format!("{}.trashinfo", name)),
+            format!(
+                "[Trash Info]\nPath={}\nDeletionDate=2024-03-01T12:30:00\n",
+                path
+            ),
+        )
+        .unwrap();
+    }
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            import_trash: Some(Some(trash.clone())),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        format!("Imported 2 files from {}\n", trash.display())
+    );
+    assert_eq!(fs::read_dir(trash.join("files")).unwrap().count(), 0);
+    assert_eq!(fs::read_dir(trash.join("info")).unwrap().count(), 0);
+
+    let record = fs::read_to_string(test_env.graveyard.join(record::RECORD)).unwrap();
+    for orig in [src.join("my notes.txt"), src.join("build")] {
+        let line = record
+            .lines()
+            .find(|line| line.split('\t').nth(1) == Some(&*orig.to_string_lossy()))
+            .unwrap();
+        assert!(line.starts_with("2024-03-01T12:30:00"));
+    }
+
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(&src).unwrap();
+    let mut log = Vec::new();
+    let seance = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            seance: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    );
+    env::set_current_dir(cur_dir).unwrap();
+    seance.unwrap();
+    let log = String::from_utf8(log).unwrap();
+    let grave = util::join_absolute(&test_env.graveyard, src.join("my notes.txt"));
+    assert_eq!(fs::read_to_string(&grave).unwrap(), "notes");
+    assert!(log.contains(&*grave.to_string_lossy()));
+    assert!(log
+        .contains(&*util::join_absolute(&test_env.graveyard, src.join("build")).to_string_lossy()));
+}
+
+/// Test that --empty-permanently deletes empty files and directories
+/// without a grave, and that they are buried as usual without it
+#[rstest]
+fn test_empty_permanently(#[values(false, true)] empty_permanently: bool) {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let empty_file = test_env.src.join("empty.txt");
+    fs::File::create(&empty_file).unwrap();
+    let empty_dir = test_env.src.join("empty");
+    fs::create_dir(&empty_dir).unwrap();
+    let test_data = TestData::new(&test_env, None);
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            targets: vec![
+                empty_file.clone(),
+                empty_dir.clone(),
+                test_data.path.clone(),
+            ],
+            graveyard: Some(test_env.graveyard.clone()),
+            empty_permanently,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+
+    let src = dunce::canonicalize(&test_env.src).unwrap();
+    let grave = |name: &str| util::join_absolute(&test_env.graveyard, src.join(name));
+    assert!(!empty_file.exists());
+    assert!(!empty_dir.exists());
+    assert!(grave("test_file.txt").exists());
+    assert_eq!(grave("empty.txt").exists(), !empty_permanently);
+    assert_eq!(grave("empty").exists(), !empty_permanently);
+    let log = String::from_utf8(log).unwrap();
+    assert_eq!(
+        log.contains(&format!(
+            "Permanently deleted empty file {}",
+            src.join("empty.txt").display()
+        )),
+        empty_permanently
+    );
+    assert_eq!(
+        log.contains(&format!(
+            "Permanently deleted empty directory {}",
+            src.join("empty").display()
+        )),
+        empty_permanently
+    );
+    let record = fs::read_to_string(test_env.graveyard.join(record::RECORD)).unwrap();
+    assert_eq!(
+        record.lines().count(),
+        if empty_permanently { 2 } else { 4 }
+    );
+}
+
+/// Test that a file whose directory can't be written to is refused rather
+/// than copied into a duplicate grave, unless --force copies it anyway
+#[cfg(target_os = "linux")]
+#[rstest]
+fn test_unwritable_source_parent(#[values(false, true)] force: bool) {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    fs::create_dir(test_env.src.join("locked")).unwrap();
+    let test_data = TestData::new(&test_env, Some(&PathBuf::from("locked").join("file.txt")));
+    let locked = test_env.src.join("locked");
+    // Root ignores permissions, but not the immutable flag
+    let chattr = |flag: &str| {
+        std::process::Command::new("chattr")
+            .arg(flag)
+            .arg(&locked)
+            .status()
+            .is_ok_and(|status| status.success())
+    };
+    if !chattr("+i") {
+        println!("Skipping unwritable parent test: couldn't set the flag");
+        return;
+    }
+    let grave = util::join_absolute(
+        &test_env.graveyard,
+        dunce::canonicalize(&test_data.path).unwrap(),
+    );
+
+    let mut log = Vec::new();
+    let result = rip2::run(
+        Args {
+            targets: vec![test_data.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            force,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    );
+    chattr("-i");
+
+    assert_eq!(fs::read_to_string(&test_data.path).unwrap(), test_data.data);
+    if force {
+        result.unwrap();
+        assert_eq!(fs::read_to_string(&grave).unwrap(), test_data.data);
+        assert!(String::from_utf8(log)
+            .unwrap()
+            .contains("is not writable; left"));
+    } else {
+        let error = result.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+        assert!(error.to_string().contains("is not writable"));
+        assert!(!grave.exists());
+    }
+}
+
+/// Test that unbury skips a file grave whose original path is now a
+/// directory, warning instead of restoring into or next to it
+#[rstest]
+fn test_unbury_type_conflict() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, None);
+    let other = TestData::new(&test_env, Some(&PathBuf::from("other.txt")));
+
+    rip2::run(
+        Args {
+            targets: vec![test_data.path.clone(), other.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+    fs::create_dir(&test_data.path).unwrap();
+    fs::write(test_data.path.join("new.txt"), "new").unwrap();
+    let src = dunce::canonicalize(&test_env.src).unwrap();
+    let grave = util::join_absolute(&test_env.graveyard, src.join("test_file.txt"));
+    let other_grave = util::join_absolute(&test_env.graveyard, src.join("other.txt"));
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            unbury: Some(vec![grave.clone(), other_grave]),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+
+    assert!(String::from_utf8(log).unwrap().contains(&format!(
+        "Warning: skipping {}: it is a file, but {} is now a directory",
+        grave.display(),
+        src.join("test_file.txt").display()
+    )));
+    assert_eq!(fs::read_to_string(&grave).unwrap(), test_data.data);
+    assert_eq!(
+        fs::read_dir(&test_data.path).unwrap().count(),
+        1,
+        "the directory was left as it was"
+    );
+    assert!(!test_env.src.join("test_file.txt~1").exists());
+    assert_eq!(fs::read_to_string(&other.path).unwrap(), other.data);
+    let record = fs::read_to_string(test_env.graveyard.join(record::RECORD)).unwrap();
+    assert!(record.contains(&*grave.to_string_lossy()));
+}
+
+/// Test that --local buries into a .graveyard beside the target, and that
+/// seance and unbury find it from a subdirectory
+#[rstest]
+fn test_local_graveyard() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let test_data = TestData::new(&test_env, None);
+    fs::create_dir(test_env.src.join("sub")).unwrap();
+    let src = dunce::canonicalize(&test_env.src).unwrap();
+    let local = src.join(".graveyard");
+
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(src.join("sub")).unwrap();
+    let bury = rip2::run(
+        Args {
+            targets: vec![test_data.path.clone()],
+            local: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    );
+    let grave = util::join_absolute(&local, src.join("test_file.txt"));
+    let buried = grave.exists();
+    env::set_current_dir(&src).unwrap();
+    let mut log = Vec::new();
+    let seance = rip2::run(
+        Args {
+            seance: true,
+            local: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    );
+    let unbury = rip2::run(
+        Args {
+            unbury: Some(Vec::new()),
+            local: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    );
+    env::set_current_dir(cur_dir).unwrap();
+    bury.unwrap();
+    seance.unwrap();
+    unbury.unwrap();
+
+    assert!(buried);
+    assert!(!src.join("sub").join(".graveyard").exists());
+    assert!(String::from_utf8(log)
+        .unwrap()
+        .contains(&*grave.to_string_lossy()));
+    assert!(!grave.exists());
+    assert_eq!(fs::read_to_string(&test_data.path).unwrap(), test_data.data);
+    assert!(!test_env.graveyard.exists());
+}
+
+/// Test that seance with --min-age hides graves buried more recently
+#[rstest]
+fn test_seance_min_age() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let old = TestData::new(&test_env, Some(&PathBuf::from("old.txt")));
+    let new = TestData::new(&test_env, Some(&PathBuf::from("new.txt")));
+    rip2::run(
+        Args {
+            targets: vec![old.path.clone(), new.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    // Backdate the first burial by a year
+    let record_path = test_env.graveyard.join(record::RECORD);
+    let contents: Vec<String> = fs::read_to_string(&record_path)
+        .unwrap()
+        .lines()
+        .map(|line| match line.split_once('\t') {
+            Some((_, rest)) if rest.contains("old.txt") => {
+                format!("2000-01-01T00:00:00+00:00\t{}", rest)
+            }
+            _ => line.to_string(),
+        })
+        .collect();
+    fs::write(&record_path, contents.join("\n") + "\n").unwrap();
+
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(&test_env.src).unwrap();
+    let mut log = Vec::new();
+    let result = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            seance: true,
+            min_age: Some(std::time::Duration::from_secs(7 * 24 * 60 * 60)),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    );
+    env::set_current_dir(cur_dir).unwrap();
+    result.unwrap();
+
+    let output = String::from_utf8(log).unwrap();
+    assert!(output.contains("old.txt"));
+    assert!(!output.contains("new.txt"));
+}
+
+/// Test that ripping a grave again, through a graveyard path that is a
+/// symlink, unlinks it rather than burying it inside the graveyard
+#[rstest]
+fn test_rerip_grave_through_symlinked_graveyard() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let real = test_env.src.parent().unwrap().join("real_graveyard");
+    fs::create_dir(&real).unwrap();
+    std::os::unix::fs::symlink(&real, &test_env.graveyard).unwrap();
+    let test_data = TestData::new(&test_env, None);
+    rip2::run(
+        Args {
+            targets: vec![test_data.path.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+    let grave = util::join_absolute(
+        &test_env.graveyard,
+        dunce::canonicalize(test_data.path.parent().unwrap())
+            .unwrap()
+            .join("test_file.txt"),
+    );
+    assert!(grave.exists());
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            targets: vec![grave.clone()],
+            graveyard: Some(test_env.graveyard.clone()),
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+
+    let output = String::from_utf8(log).unwrap();
+    assert!(output.contains("is already in the graveyard."));
+    assert!(!grave.exists());
+    assert!(!util::join_absolute(&real, &real).exists());
+}
+
+/// Test that --unbury with --recursive restores every grave buried from
+/// under an original directory, recreating the directories in between
+#[rstest]
+fn test_unbury_recursive() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let src = dunce::canonicalize(&test_env.src).unwrap();
+    let proj = src.join("proj");
+    let files = [
+        proj.join("a.txt"),
+        proj.join("sub").join("b.txt"),
+        proj.join("sub").join("deep").join("c.txt"),
+    ];
+    let outside = src.join("outside.txt");
+    let graveyard = test_env.graveyard.to_str().unwrap();
+    for file in files.iter().chain([&outside]) {
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(file, file.display().to_string()).unwrap();
+        cli_runner(["--graveyard", graveyard, file.to_str().unwrap()], None)
+            .assert()
+            .success();
+    }
+    fs::remove_dir_all(&proj).unwrap();
+
+    cli_runner(
+        ["--graveyard", graveyard, "-u", "--recursive", "proj"],
+        Some(&src),
+    )
+    .assert()
+    .success();
+
+    for file in &files {
+        assert_eq!(
+            fs::read_to_string(file).unwrap(),
+            file.display().to_string()
+        );
+    }
+    assert!(!outside.exists());
+}
+
+/// Test that --recent lists the graves buried within the grace period,
+/// and --undo-recent restores them
+#[rstest]
+fn test_recent() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let first = TestData::new(&test_env, Some(&PathBuf::from("first.txt")));
+    let second = TestData::new(&test_env, Some(&PathBuf::from("second.txt")));
+    for test_data in [&first, &second] {
+        rip2::run(
+            Args {
+                targets: vec![test_data.path.clone()],
+                graveyard: Some(test_env.graveyard.clone()),
+                ..Args::default()
+            },
+            TestMode,
+            &mut Vec::new(),
+        )
+        .unwrap();
+    }
+
+    let mut log = Vec::new();
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            recent: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    )
+    .unwrap();
+    let output = String::from_utf8(log).unwrap();
+    assert!(output.contains("first.txt"));
+    assert!(output.contains("second.txt"));
+
+    rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            undo_recent: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+    for test_data in [&first, &second] {
+        assert_eq!(fs::read_to_string(&test_data.path).unwrap(), test_data.data);
+    }
+}
+
+/// Test that --unbury with --seance explains when the graveyard has no
+/// record yet
+#[rstest]
+fn test_unbury_seance_without_record() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let cur_dir = env::current_dir().unwrap();
+    env::set_current_dir(&test_env.src).unwrap();
+    let mut log = Vec::new();
+    let result = rip2::run(
+        Args {
+            graveyard: Some(test_env.graveyard.clone()),
+            unbury: Some(Vec::new()),
+            seance: true,
+            ..Args::default()
+        },
+        TestMode,
+        &mut log,
+    );
+    env::set_current_dir(cur_dir).unwrap();
+    result.unwrap();
+
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        format!(
+            "No graveyard record found at {}\n",
+            test_env.graveyard.join(record::RECORD).display()
+        )
+    );
+}
+
+/// Test that unburying a bare name shared by graves from different
+/// directories is refused with the list, unless --all-matches is given
+#[rstest]
+fn test_unbury_ambiguous_name() {
+    let _env_lock = aquire_lock();
+    let test_env = TestEnv::new();
+    let src = dunce::canonicalize(&test_env.src).unwrap();
+    let configs = [src.join("a").join("config"), src.join("b").join("config")];
+    for config in &configs {
+        fs::create_dir_all(config.parent().unwrap()).unwrap();
+        fs::write(config, config.display().to_string()).unwrap();
+    }
+    let other = TestData::new(&test_env, Some(&PathBuf::from("other")));
+    let graveyard = test_env.graveyard.to_str().unwrap();
+    cli_runner(
+        ["--graveyard", graveyard, "a/config", "b/config", "other"],
+        Some(&src),
+    )
+    .assert()
+    .success();
+
+    let refused = cli_runner(["--graveyard", graveyard, "-u", "config"], Some(&src))
+        .assert()
+        .failure();
+    let message = String::from_utf8(refused.get_output().stdout.clone()).unwrap();
+    assert!(message.contains("config matches several graves"));
+    for config in &configs {
+        assert!(message.contains(&config.display().to_string()));
+        assert!(!config.exists());
+    }
+    assert!(!other.path.exists());
+
+    let restored = cli_runner(
+        ["--graveyard", graveyard, "-u", "config", "--all-matches"],
+        Some(&src),
+    )
+    .assert()
+    .success();
+    assert!(String::from_utf8(restored.get_output().stdout.clone())
+        .unwrap()
+        .contains("Warning: config matches 2 graves"));
+    for config in &configs {
+        assert_eq!(
+            fs::read_to_string(config).unwrap(),
+            config.display().to_string()
+        );
+    }
+    assert!(!other.path.exists());
+}
@@ -1,7 +1,8 @@
 use lazy_static::lazy_static;
-use rip2::args::{validate_args, Args, Commands};
+use rip2::args::{validate_args, Args, CollisionSuffix, Commands};
 use rip2::completions;
-use rip2::util::{humanize_bytes, TestMode};
+use rip2::util::{check_writable, humanize_bytes, BigFileChoice, PromptPolicy, TestMode};
+use rip2::CopyOptions;
 use rstest::rstest;
 use std::fs;
 use std::io::{Cursor, ErrorKind};
@@ -35,6 +36,7 @@ fn test_validation() {
     let bad_completions = Args {
         command: Some(Commands::Completions {
             shell: "bash".to_string(),
+            output: None,
         }),
         decompose: true,
         ..Args::default()
@@ -49,6 +51,34 @@ fn test_validation() {
     validate_args(&bad_decompose).expect_err("-d,--decompose can only be used with --graveyard");
 }
 
+#[rstest]
+fn test_validation_graveyard(
+    #[values("seance", "unbury", "inspect", "decompose", "targets")] flag: &str,
+) {
+    let mut args = Args {
+        graveyard: Some(PathBuf::from("my_graveyard")),
+        ..Args::default()
+    };
+    match flag {
+        "seance" => args.seance = true,
+        "unbury" => args.unbury = Some(Vec::new()),
+        "inspect" => args.inspect = true,
+        "decompose" => args.decompose = true,
+        "targets" => args.targets = vec![PathBuf::from("file")],
+        _ => unreachable!(),
+    }
+    validate_args(&args).unwrap();
+
+    // --completions still can't be combined with anything
+    args.command = Some(Commands::Completions {
+        shell: "bash".to_string(),
+        output: None,
+    });
+    validate_args(&args).expect_err("--completions can only be used by itself");
+    args.graveyard = None;
+    validate_args(&args).expect_err("--completions can only be used by itself");
+}
+
 #[rstest]
 fn test_filetypes(
     #[values("regular", "big", "fifo", "symlink", "socket")] file_type: &str,
@@ -100,11 +130,12 @@ fn test_filetypes(
 
     let mut log = Vec::new();
     let mode = TestMode;
+    let opts = CopyOptions::default();
 
     if copy {
-        rip2::copy_file(&source_path, &dest_path, &mode, &mut log).unwrap();
+        rip2::copy_file(&source_path, &dest_path, &opts, &mode, &mut log).unwrap();
     } else {
-        rip2::move_target(&source_path, &dest_path, &mode, &mut log).unwrap();
+        rip2::move_target(&source_path, &dest_path, &opts, &mode, &mut log).unwrap();
     }
 
     let log_s = String::from_utf8(log).unwrap();
@@ -156,12 +187,171 @@ fn test_filetypes(
 }
 
 #[rstest]
-fn test_prompt_read(#[values("y", "Y", "n", "N", "", "\n", "q", "Q", "k")] key: &str) {
+fn test_throttle() {
+    let tmpdir = tempdir().unwrap();
+    let source_path = tmpdir.path().join("source");
+    let dest_path = tmpdir.path().join("dest");
+    let data = vec![7_u8; 10_000];
+    fs::write(&source_path, &data).unwrap();
+
+    let opts = CopyOptions {
+        throttle: Some(20_000),
+        ..CopyOptions::default()
+    };
+    let start = std::time::Instant::now();
+    rip2::copy_file(&source_path, &dest_path, &opts, &TestMode, &mut Vec::new()).unwrap();
+
+    // 10 kB at 20 kB/s takes at least half a second
+    assert!(start.elapsed() >= std::time::Duration::from_millis(500));
+    assert_eq!(fs::read(&dest_path).unwrap(), data);
+}
+
+#[rstest]
+fn test_throttle_zero_rejected() {
+    use clap::Parser;
+
+    let err = Args::try_parse_from(["rip", "--throttle", "0", "file"]).unwrap_err();
+    assert_eq!(err.kind(), clap::error::ErrorKind::ValueValidation);
+    let args = Args::try_parse_from(["rip", "--throttle", "1", "file"]).unwrap();
+    assert_eq!(args.throttle, Some(1));
+}
+
+#[rstest]
+fn test_graveyard_per_host() {
+    let hostname = rip2::util::hostname();
+    assert!(!hostname.is_empty());
+    let args = Args {
+        graveyard: Some(PathBuf::from("flag_graveyard")),
+        graveyard_per_host: true,
+        ..Args::default()
+    };
+    assert_eq!(
+        rip2::resolve_graveyard(&args),
+        PathBuf::from("flag_graveyard").join(hostname)
+    );
+}
+
+#[rstest]
+fn test_reserve_grave_concurrently() {
+    let tmpdir = tempdir().unwrap();
+    let grave = tmpdir.path().join("dir").join("file.txt");
+    let barrier = std::sync::Barrier::new(8);
+    let mut reserved: Vec<PathBuf> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                scope.spawn(|| {
+                    barrier.wait();
+                    rip2::util::reserve_grave(&grave, false, CollisionSuffix::Numeric).unwrap()
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    reserved.sort();
+    reserved.dedup();
+    assert_eq!(reserved.len(), 8);
+    assert!(reserved.contains(&grave));
+    assert!(reserved.iter().all(|path| path.is_file()));
+}
+
+#[rstest]
+fn test_collision_suffix(
+    #[values(
+        CollisionSuffix::Numeric,
+        CollisionSuffix::Timestamp,
+        CollisionSuffix::Uuid
+    )]
+    style: CollisionSuffix,
+) {
+    let tmpdir = tempdir().unwrap();
+    let grave = tmpdir.path().join("file.txt");
+    fs::write(&grave, "").unwrap();
+    let pattern = match style {
+        CollisionSuffix::Numeric => r"^file\.txt~\d+$",
+        CollisionSuffix::Timestamp => r"^file\.txt~\d{8}T\d{6}(~\d+)?$",
+        CollisionSuffix::Uuid => {
+            r"^file\.txt~[0-9a-f]{8}-[0-9a-f]{4}-4[0-9a-f]{3}-[89ab][0-9a-f]{3}-[0-9a-f]{12}$"
+        }
+    };
+    let pattern = regex::Regex::new(pattern).unwrap();
+
+    let mut names = Vec::new();
+    for _ in 0..3 {
+        let renamed = rip2::util::rename_grave(&grave, style);
+        assert!(!renamed.exists());
+        let reserved = rip2::util::reserve_grave(&grave, false, style).unwrap();
+        for path in [&renamed, &reserved] {
+            let name = path.file_name().unwrap().to_str().unwrap();
+            assert!(pattern.is_match(name), "unexpected name {}", name);
+        }
+        names.push(reserved);
+    }
+    names.dedup();
+    assert_eq!(names.len(), 3);
+}
+
+#[rstest]
+fn test_symlink_existing_dest() {
+    let tmpdir = tempdir().unwrap();
+    let target_path = tmpdir.path().join("target");
+    let source_path = tmpdir.path().join("source");
+    let dest_path = tmpdir.path().join("dest");
+    fs::File::create(&target_path).unwrap();
+    symlink(&target_path, &source_path).unwrap();
+    fs::write(&dest_path, "leftover").unwrap();
+
+    rip2::copy_file(
+        &source_path,
+        &dest_path,
+        &CopyOptions::default(),
+        &TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+    assert!(fs::symlink_metadata(&dest_path)
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    assert_eq!(fs::read_link(&dest_path).unwrap(), target_path);
+}
+
+#[rstest]
+fn test_dir_summary_single_walk() {
+    let tmpdir = tempdir().unwrap();
+    let dir = tmpdir.path();
+    for name in ["a", "b", "c"] {
+        fs::write(dir.join(name), vec![0_u8; 10]).unwrap();
+    }
+    fs::create_dir(dir.join("d")).unwrap();
+    fs::write(dir.join("d").join("e"), vec![0_u8; 5]).unwrap();
+
+    let visits = std::cell::Cell::new(0);
+    let walker = walkdir::WalkDir::new(dir)
+        .sort_by(|a, b| a.cmp(b))
+        .into_iter()
+        .inspect(|_| visits.set(visits.get() + 1));
+    let (size, entries) = rip2::util::dir_summary(walker, 2).unwrap();
+
+    assert_eq!(size, 35);
+    assert_eq!(entries, vec![dir.join("a"), dir.join("b")]);
+    // The root, four top-level entries and one nested file, each seen once
+    assert_eq!(visits.get(), 6);
+}
+
+#[rstest]
+fn test_prompt_read(
+    #[values("y", "Y", "n", "N", "", "\n", "q", "Q", "k")] key: &str,
+    #[values(false, true)] default: bool,
+) {
     let input = Cursor::new(key);
-    let result = rip2::util::yes_no_quit(input);
+    let result = rip2::util::yes_no_quit(input, default);
     match key {
         "y" | "Y" => assert!(result.unwrap()),
-        "n" | "N" | "" | "\n" => assert!(!result.unwrap()),
+        "n" | "N" => assert!(!result.unwrap()),
+        // An empty line selects the default
+        "\n" => assert_eq!(result.unwrap(), default),
+        // End of input answers no
+        "" => assert!(!result.unwrap()),
         "q" | "Q" => {
             let err = result.unwrap_err();
             assert_eq!(err.kind(), ErrorKind::Interrupted);
@@ -176,6 +366,60 @@ fn test_prompt_read(#[values("y", "Y", "n", "N", "", "\n", "q", "Q", "k")] key:
     }
 }
 
+#[rstest]
+fn test_big_file_choice(#[values("m", "M", "", "\n", "d", "D", "c", "C", "q", "y")] key: &str) {
+    let result = rip2::util::big_file_choice(Cursor::new(key));
+    match key {
+        "m" | "M" | "" | "\n" => assert_eq!(result.unwrap(), BigFileChoice::Move),
+        "d" | "D" => assert_eq!(result.unwrap(), BigFileChoice::Delete),
+        "c" | "C" => assert_eq!(result.unwrap(), BigFileChoice::Cancel),
+        "q" => assert_eq!(result.unwrap_err().kind(), ErrorKind::Interrupted),
+        _ => assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput),
+    }
+}
+
+#[rstest]
+fn test_prompt_defaults() {
+    let mut log = Vec::new();
+    rip2::util::prompt_yes("Send it?", true, &TestMode, &mut log).unwrap();
+    rip2::util::prompt_yes("Delete it?", false, &TestMode, &mut log).unwrap();
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        "Send it? (Y/n) Delete it? (y/N) "
+    );
+
+    // --yes answers safe prompts with their default, but still asks
+    // before destructive ones
+    let yes = PromptPolicy {
+        yes: true,
+        force: false,
+    };
+    let mut log = Vec::new();
+    assert!(yes
+        .ask("Send it?", true, false, &TestMode, &mut log)
+        .unwrap());
+    assert!(!yes
+        .ask("Keep going?", false, false, &TestMode, &mut log)
+        .unwrap());
+    yes.ask("Delete it?", false, true, &TestMode, &mut log)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        "Send it? (Y/n) y\nKeep going? (y/N) n\nDelete it? (y/N) "
+    );
+
+    // --force answers yes to everything
+    let force = PromptPolicy {
+        yes: false,
+        force: true,
+    };
+    let mut log = Vec::new();
+    assert!(force
+        .ask("Delete it?", false, true, &TestMode, &mut log)
+        .unwrap());
+    assert_eq!(String::from_utf8(log).unwrap(), "Delete it? (y/N) y\n");
+}
+
 #[rstest]
 fn test_completions(
     #[values("bash", "elvish", "fish", "powershell", "zsh", "nushell", "fake")] shell: &str,
@@ -214,6 +458,15 @@ fn test_completions(
     }
 }
 
+/// Test that completions can be written to a file in a new directory
+#[rstest]
+fn test_completions_output() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("completions").join("rip.fish");
+    completions::write_shell_completions("fish", Some(&path), &mut Vec::new()).unwrap();
+    assert!(fs::read_to_string(&path).unwrap().contains("complete -c"));
+}
+
 #[rstest]
 fn test_graveyard_path() {
     let _env_lock = aquire_lock();
@@ -230,6 +483,108 @@ fn test_graveyard_path() {
     );
 }
 
+#[rstest]
+fn test_resolve_graveyard(
+    #[values("flag", "rip_graveyard", "xdg", "xdg_trailing", "default")] source: &str,
+) {
+    let _env_lock = aquire_lock();
+
+    let saved: Vec<_> = ["RIP_GRAVEYARD", "XDG_DATA_HOME"]
+        .iter()
+        .map(|key| (key, std::env::var(key).ok()))
+        .collect();
+    std::env::remove_var("RIP_GRAVEYARD");
+    std::env::remove_var("XDG_DATA_HOME");
+
+    let sep = std::path::MAIN_SEPARATOR;
+    let mut args = Args::default();
+    let expected = match source {
+        "flag" => {
+            // The flag wins over the environment
+            std::env::set_var("RIP_GRAVEYARD", "env_graveyard");
+            args.graveyard = Some(PathBuf::from("flag_graveyard"));
+            PathBuf::from("flag_graveyard")
+        }
+        "rip_graveyard" => {
+            std::env::set_var("XDG_DATA_HOME", "xdg");
+            std::env::set_var("RIP_GRAVEYARD", "env_graveyard");
+            PathBuf::from("env_graveyard")
+        }
+        "xdg" => {
+            std::env::set_var("XDG_DATA_HOME", format!("{sep}data"));
+            PathBuf::from(format!("{sep}data{sep}graveyard"))
+        }
+        "xdg_trailing" => {
+            std::env::set_var("XDG_DATA_HOME", format!("{sep}data{sep}"));
+            PathBuf::from(format!("{sep}data{sep}graveyard"))
+        }
+        "default" => std::env::temp_dir().join(format!("graveyard-{}", rip2::util::get_user())),
+        _ => unreachable!(),
+    };
+    let graveyard = rip2::resolve_graveyard(&args);
+
+    for (key, value) in saved {
+        std::env::remove_var(key);
+        if let Some(value) = value {
+            std::env::set_var(key, value);
+        }
+    }
+    assert_eq!(graveyard, expected);
+}
+
+#[rstest]
+fn test_parse_duration() {
+    use rip2::util::parse_duration;
+    use std::time::Duration;
+
+    assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+    assert_eq!(parse_duration("15m").unwrap(), Duration::from_secs(15 * 60));
+    assert_eq!(
+        parse_duration("12h").unwrap(),
+        Duration::from_secs(12 * 3600)
+    );
+    assert_eq!(
+        parse_duration("30d").unwrap(),
+        Duration::from_secs(30 * 86400)
+    );
+    assert_eq!(
+        parse_duration("2w").unwrap(),
+        Duration::from_secs(14 * 86400)
+    );
+    for bad in ["", "d", "30", "30y", "-1d", "1.5d", "3é"] {
+        assert!(parse_duration(bad).is_err(), "{}", bad);
+    }
+}
+
+#[rstest]
+fn test_parse_size() {
+    use rip2::util::parse_size;
+
+    assert_eq!(parse_size("512").unwrap(), 512);
+    assert_eq!(parse_size("4K").unwrap(), 4096);
+    assert_eq!(parse_size("10m").unwrap(), 10 << 20);
+    assert_eq!(parse_size("1G").unwrap(), 1 << 30);
+    for bad in ["", "K", "1.5M", "10X", "-1", "3é"] {
+        assert!(parse_size(bad).is_err(), "{}", bad);
+    }
+}
+
+#[rstest]
+fn test_device_node_format() {
+    use rip2::util::DeviceNode;
+
+    let device = DeviceNode {
+        block: true,
+        rdev: 2049,
+        mode: 0o660,
+    };
+    assert_eq!(device.to_string(), "b:2049:660");
+    assert_eq!(DeviceNode::parse(&device.to_string()), Some(device));
+    for bad in ["", "x:1:644", "c:one:644", "c:1:999", "c:1"] {
+        assert_eq!(DeviceNode::parse(bad), None, "{}", bad);
+    }
+}
+
 #[rstest]
 fn test_humanize_bytes() {
     assert_eq!(humanize_bytes(0), "0 B");
@@ -239,6 +594,10 @@ fn test_humanize_bytes() {
     assert_eq!(humanize_bytes(1024 * 1024 * 1024), "1.0 GiB");
     assert_eq!(humanize_bytes(1024 * 1024 * 1024 * 1024), "1.0 TiB");
 
+    assert_eq!(humanize_bytes(1 << 50), "1.0 PiB");
+    assert_eq!(humanize_bytes(1 << 60), "1.0 EiB");
+    assert_eq!(humanize_bytes(u64::MAX), "16.0 EiB");
+
     assert_eq!(humanize_bytes(1024 * 1024 + 1024 * 512), "1.5 MiB");
 }
 
@@ -251,9 +610,204 @@ fn fail_move_dir() {
     let dest = path_dest.join("foo");
     let target = path_target.join("bar");
     let mut log = Vec::new();
-    let results = rip2::move_dir(&target, &dest, &TestMode, &mut log);
+    let results = rip2::move_dir(&target, &dest, &CopyOptions::default(), &TestMode, &mut log);
     assert!(results.is_err());
     if let Err(e) = results {
         assert!(e.to_string().contains("Failed to remove dir"));
     }
 }
+
+#[rstest]
+fn test_move_dir_nested_dest() {
+    let tmpdir = tempdir().unwrap();
+    let target = tmpdir.path().join("target");
+    fs::create_dir_all(target.join("a").join("b")).unwrap();
+    fs::write(target.join("top.txt"), "top").unwrap();
+    fs::write(target.join("a").join("b").join("deep.txt"), "deep").unwrap();
+    // None of the destination's parents exist yet
+    let dest = tmpdir.path().join("x").join("y").join("dest");
+
+    let outcome = rip2::move_dir(
+        &target,
+        &dest,
+        &CopyOptions::default(),
+        &TestMode,
+        &mut Vec::new(),
+    )
+    .unwrap();
+    assert_eq!(outcome, rip2::MoveOutcome::Moved);
+    assert!(!target.exists());
+    assert_eq!(fs::read_to_string(dest.join("top.txt")).unwrap(), "top");
+    assert_eq!(
+        fs::read_to_string(dest.join("a").join("b").join("deep.txt")).unwrap(),
+        "deep"
+    );
+}
+
+#[rstest]
+fn test_tail_lines() {
+    // Long enough to need several chunks, with CRLF endings
+    let text: String = (0..2000).map(|i| format!("line {}\r\n", i)).collect();
+    let lines = rip2::util::tail_lines(&mut Cursor::new(text.as_bytes()), 3).unwrap();
+    assert_eq!(lines, ["line 1997", "line 1998", "line 1999"]);
+
+    let lines = rip2::util::tail_lines(&mut Cursor::new("a\nb"), 6).unwrap();
+    assert_eq!(lines, ["a", "b"]);
+    assert!(rip2::util::tail_lines(&mut Cursor::new(""), 6)
+        .unwrap()
+        .is_empty());
+}
+
+/// Counts the bytes read through it
+struct CountingReader<R> {
+    inner: R,
+    read: std::rc::Rc<std::cell::Cell<usize>>,
+}
+
+impl<R: std::io::Read> std::io::Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read.set(self.read.get() + n);
+        Ok(n)
+    }
+}
+
+impl<R: std::io::Seek> std::io::Seek for CountingReader<R> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[rstest]
+fn test_reverse_lines_reads_only_the_end() {
+    let text: String = (0..100_000).map(|i| format!("line {}\n", i)).collect();
+    let read = std::rc::Rc::new(std::cell::Cell::new(0));
+    let reader = CountingReader {
+        inner: Cursor::new(text.as_bytes()),
+        read: read.clone(),
+    };
+    let last: Vec<String> = rip2::util::ReverseLines::new(reader)
+        .unwrap()
+        .take(2)
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(last, ["line 99999", "line 99998"]);
+    // A single chunk is enough, out of over a megabyte
+    assert!(read.get() <= 4096);
+}
+
+#[rstest]
+fn test_get_last_bury_large_record() {
+    let tmpdir = tempdir().unwrap();
+    let graveyard = tmpdir.path();
+    let grave = graveyard.join("last.txt");
+    fs::write(&grave, "").unwrap();
+    let mut contents = String::from("Time\tOriginal\tDestination\n");
+    for i in 0..50_000 {
+        contents.push_str(&format!(
+            "2024-01-01T00:00:00+00:00\t/orig/{}\t{}\n",
+            i,
+            graveyard.join(i.to_string()).display()
+        ));
+    }
+    contents.push_str(&format!(
+        "2024-01-01T00:00:00+00:00\t/orig/last.txt\t{}\n",
+        grave.display()
+    ));
+    fs::write(graveyard.join(".record"), &contents).unwrap();
+
+    let record = rip2::record::Record::new(graveyard, ".record");
+    assert_eq!(record.get_last_bury().unwrap(), grave);
+    // Nothing needed cleaning up on the way
+    assert_eq!(
+        fs::read_to_string(graveyard.join(".record")).unwrap(),
+        contents
+    );
+}
+
+#[rstest]
+fn test_glob_regex() {
+    let glob = rip2::util::glob_regex("*.log").unwrap();
+    assert!(glob.is_match("app.log"));
+    assert!(!glob.is_match("app.log.gz"));
+    assert!(!glob.is_match("applog"));
+    let glob = rip2::util::glob_regex("img_??.[jp]g").unwrap();
+    assert!(glob.is_match("img_01.[jp]g"));
+    assert!(!glob.is_match("img_1.[jp]g"));
+}
+
+#[rstest]
+fn test_check_writable() {
+    let tmpdir = tempdir().unwrap();
+    assert!(check_writable(tmpdir.path()).is_ok());
+    // The probe file is cleaned up
+    assert_eq!(fs::read_dir(tmpdir.path()).unwrap().count(), 0);
+
+    let err = check_writable(&tmpdir.path().join("missing")).unwrap_err();
+    assert!(err.to_string().contains("is not writable"));
+}
+
+#[cfg(unix)]
+#[rstest]
+fn test_create_private_temp() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let (first, _) = rip2::util::create_private_temp("rip-test", ".txt").unwrap();
+    let (second, _) = rip2::util::create_private_temp("rip-test", ".txt").unwrap();
+    let first_mode = fs::symlink_metadata(&first).unwrap().permissions().mode();
+    let second_is_file = fs::symlink_metadata(&second).unwrap().is_file();
+    fs::remove_file(&first).unwrap();
+    fs::remove_file(&second).unwrap();
+    assert_ne!(first, second);
+    assert_eq!(first_mode & 0o777, 0o600);
+    assert!(second_is_file);
+}
+
+/// Test that xattr support is probed on the filesystem, which procfs lacks
+#[cfg(target_os = "linux")]
+#[rstest]
+fn test_xattrs_supported() {
+    let tmpdir = tempdir().unwrap();
+    assert!(rip2::util::xattrs_supported(tmpdir.path()).is_ok());
+    if PathBuf::from("/proc/self").exists() {
+        assert!(!rip2::util::xattrs_supported(&PathBuf::from("/proc/self")).unwrap());
+    }
+}
+
+#[rstest]
+fn test_error_json() {
+    let err = std::io::Error::new(ErrorKind::PermissionDenied, "no\taccess");
+    assert_eq!(
+        rip2::util::error_json(&err),
+        r#"{"error":"no\taccess","kind":"PermissionDenied","path":null}"#
+    );
+}
+
+#[rstest]
+fn test_retry_transient(#[values(0, 2, 4, 5)] failures: u32) {
+    let mut calls = 0;
+    let result = rip2::util::retry_transient(|| {
+        calls += 1;
+        if calls <= failures {
+            Err(std::io::Error::from(ErrorKind::Interrupted))
+        } else {
+            Ok(calls)
+        }
+    });
+    if failures < 5 {
+        assert_eq!(result.unwrap(), failures + 1);
+    } else {
+        // Attempts are bounded
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::Interrupted);
+        assert_eq!(calls, 5);
+    }
+
+    // Other errors are not retried
+    let mut calls = 0;
+    let result: std::io::Result<()> = rip2::util::retry_transient(|| {
+        calls += 1;
+        Err(std::io::Error::from(ErrorKind::NotFound))
+    });
+    assert!(result.is_err());
+    assert_eq!(calls, 1);
+}